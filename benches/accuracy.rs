@@ -0,0 +1,48 @@
+//! Observed relative error of `count` across P and cardinalities, run with
+//! `cargo bench --bench accuracy`. Every trial adds a distinct set of items, the table
+//! reports percentiles of the absolute relative error over the trials, next to the
+//! standard error `1.04 / sqrt(m)`.
+
+use simple_hll::HyperLogLog;
+
+const TRIALS: u64 = 100;
+const CARDINALITIES: [u64; 6] = [10, 100, 1_000, 10_000, 100_000, 1_000_000];
+
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
+
+fn report<const P: usize>() {
+    for n in CARDINALITIES {
+        let mut errors: Vec<f64> = (0..TRIALS)
+            .map(|trial| {
+                let mut hll = HyperLogLog::<P>::new();
+                for i in 0..n {
+                    hll.add_object(&(trial, i));
+                }
+                (hll.count() as f64 - n as f64).abs() / n as f64
+            })
+            .collect();
+        errors.sort_by(f64::total_cmp);
+        println!(
+            "{:>2} {:>9} {:>7.3}% {:>7.3}% {:>7.3}% {:>7.3}% {:>7.3}%",
+            P,
+            n,
+            HyperLogLog::<P>::error_rate() * 100.0,
+            percentile(&errors, 0.5) * 100.0,
+            percentile(&errors, 0.9) * 100.0,
+            percentile(&errors, 0.99) * 100.0,
+            errors[errors.len() - 1] * 100.0,
+        );
+    }
+}
+
+fn main() {
+    println!(
+        "{:>2} {:>9} {:>8} {:>8} {:>8} {:>8} {:>8}",
+        "P", "n", "std err", "p50", "p90", "p99", "max"
+    );
+    report::<10>();
+    report::<14>();
+    report::<18>();
+}
@@ -0,0 +1,156 @@
+//! Throughput of adding, merging, counting and (de)serializing sketches across P, hashers
+//! and representations, run with `cargo bench --bench hll`.
+
+use core::hash::BuildHasher;
+use core::hash::Hash;
+use std::hint::black_box;
+
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::BenchmarkId;
+use criterion::Criterion;
+use criterion::Throughput;
+use simple_hll::HyperLogLog;
+
+const ITEMS: u64 = 100_000;
+
+struct Xxh3;
+
+impl simple_hll::Hasher for Xxh3 {
+    fn hll_hash<T: Hash>(x: T) -> u64 {
+        xxhash_rust::xxh3::Xxh3Builder::new().hash_one(x)
+    }
+}
+
+fn sketch<const P: usize>(n: u64) -> HyperLogLog<P> {
+    let mut hll = HyperLogLog::new();
+    for i in 0..n {
+        hll.add_object(&i);
+    }
+    hll
+}
+
+fn bench_add(c: &mut Criterion) {
+    let mut group = c.benchmark_group("add");
+    group.throughput(Throughput::Elements(ITEMS));
+
+    macro_rules! add {
+        ($p:literal) => {
+            group.bench_function(BenchmarkId::new("ahash", $p), |b| {
+                b.iter(|| black_box(sketch::<$p>(ITEMS)))
+            });
+            group.bench_function(BenchmarkId::new("xxh3", $p), |b| {
+                b.iter(|| {
+                    let mut hll = HyperLogLog::<$p>::new();
+                    for i in 0..ITEMS {
+                        hll.add_object_by_hasher::<_, Xxh3>(&i);
+                    }
+                    black_box(hll)
+                })
+            });
+            group.bench_function(BenchmarkId::new("add_hash", $p), |b| {
+                b.iter(|| {
+                    let mut hll = HyperLogLog::<$p>::new();
+                    for i in 0..ITEMS {
+                        hll.add_hash(i.wrapping_mul(0x9e3779b97f4a7c15));
+                    }
+                    black_box(hll)
+                })
+            });
+            group.bench_function(BenchmarkId::new("add_hashes_sorted", $p), |b| {
+                let hashes: Vec<u64> = (0..ITEMS)
+                    .map(|i| i.wrapping_mul(0x9e3779b97f4a7c15))
+                    .collect();
+                b.iter(|| {
+                    let mut hashes = hashes.clone();
+                    let mut hll = HyperLogLog::<$p>::new();
+                    hll.add_hashes_sorted(&mut hashes);
+                    black_box(hll)
+                })
+            });
+        };
+    }
+    add!(10);
+    add!(14);
+    add!(18);
+    group.finish();
+}
+
+fn bench_merge(c: &mut Criterion) {
+    let mut group = c.benchmark_group("merge");
+
+    macro_rules! merge {
+        ($p:literal) => {
+            let sketches: Vec<HyperLogLog<$p>> = (0..64_u64)
+                .map(|i| {
+                    let mut hll = HyperLogLog::new();
+                    for j in i * 1000..(i + 1) * 1000 {
+                        hll.add_object(&j);
+                    }
+                    hll
+                })
+                .collect();
+            group.throughput(Throughput::Elements(sketches.len() as u64));
+            group.bench_function(BenchmarkId::new("pairwise", $p), |b| {
+                b.iter(|| {
+                    let mut hll = HyperLogLog::<$p>::new();
+                    sketches.iter().for_each(|other| hll.merge(other));
+                    black_box(hll)
+                })
+            });
+            group.bench_function(BenchmarkId::new("union", $p), |b| {
+                b.iter(|| black_box(HyperLogLog::<$p>::union(&sketches)))
+            });
+        };
+    }
+    merge!(10);
+    merge!(14);
+    merge!(18);
+    group.finish();
+}
+
+fn bench_count(c: &mut Criterion) {
+    let mut group = c.benchmark_group("count");
+
+    macro_rules! count {
+        ($p:literal) => {
+            let hll = sketch::<$p>(ITEMS);
+            group.bench_function(BenchmarkId::new("count", $p), |b| {
+                b.iter(|| black_box(&hll).count())
+            });
+        };
+    }
+    count!(10);
+    count!(14);
+    count!(18);
+    group.finish();
+}
+
+/// The binary format picks the smallest representation, few items are sparse
+fn bench_serialize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("serialize");
+
+    for (name, n) in [("sparse", 100), ("dense", ITEMS)] {
+        let hll = sketch::<14>(n);
+        let bytes = hll.to_bytes();
+        group.bench_function(BenchmarkId::new("to_bytes", name), |b| {
+            b.iter(|| black_box(&hll).to_bytes())
+        });
+        group.bench_function(BenchmarkId::new("from_bytes", name), |b| {
+            b.iter(|| HyperLogLog::<14>::from_bytes(black_box(&bytes)).unwrap())
+        });
+        group.bench_function(BenchmarkId::new("count_from_serialized", name), |b| {
+            b.iter(|| HyperLogLog::<14>::count_from_serialized(black_box(&bytes)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_add,
+    bench_merge,
+    bench_count,
+    bench_serialize
+);
+criterion_main!(benches);
@@ -0,0 +1,17 @@
+//! The borsh impls
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use simple_hll::HyperLogLog;
+
+fn check<const P: usize>(data: &[u8]) {
+    if let Ok(hll) = borsh::from_slice::<HyperLogLog<P>>(data) {
+        assert!(hll.validate().is_ok());
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    check::<4>(data);
+    check::<14>(data);
+});
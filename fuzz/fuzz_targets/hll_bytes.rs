@@ -0,0 +1,47 @@
+//! The binary format of `HyperLogLog::to_bytes` and the decoders built on it
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use simple_hll::HyperLogLog;
+
+fn check<const P: usize>(data: &[u8]) {
+    if let Ok(hll) = HyperLogLog::<P>::from_bytes(data) {
+        assert!(hll.validate().is_ok());
+        assert!(HyperLogLog::<P>::count_from_serialized(data).is_ok());
+
+        let mut merged = HyperLogLog::<P>::new();
+        merged.merge_from_slice(data).unwrap();
+        assert!(merged.validate().is_ok());
+        assert_eq!(merged, hll);
+    } else {
+        let mut hll = HyperLogLog::<P>::new();
+        hll.add_hash(1);
+        let before = hll.clone();
+        assert!(hll.merge_from_slice(data).is_err());
+        assert_eq!(hll, before);
+    }
+
+    let mut hll = HyperLogLog::<P>::new();
+    if hll.apply_delta(data).is_ok() {
+        assert!(hll.validate().is_ok());
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    check::<4>(data);
+    check::<14>(data);
+
+    if let Ok(s) = std::str::from_utf8(data) {
+        for hll in [
+            HyperLogLog::<14>::from_base64(s),
+            HyperLogLog::<14>::from_hex(s),
+            HyperLogLog::<14>::from_druid(s),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            assert!(hll.validate().is_ok());
+        }
+    }
+});
@@ -0,0 +1,16 @@
+//! Redis HyperLogLog strings
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use simple_hll::HyperLogLog;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(hll) = HyperLogLog::<14>::from_redis(data) {
+        assert!(hll.validate().is_ok());
+        assert_eq!(
+            HyperLogLog::<14>::from_redis(&hll.to_redis_dense()).unwrap(),
+            hll
+        );
+    }
+});
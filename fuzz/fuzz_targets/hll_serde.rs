@@ -0,0 +1,27 @@
+//! The serde impls, through bincode and JSON
+
+#![no_main]
+
+use bincode::Options;
+use libfuzzer_sys::fuzz_target;
+use simple_hll::HyperLogLog;
+
+fn check<const P: usize>(data: &[u8]) {
+    // same encoding as `bincode::deserialize`, with a limit so that a huge length prefix
+    // is rejected by bincode instead of allocated
+    let options = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(1 << 20);
+    if let Ok(hll) = options.deserialize::<HyperLogLog<P>>(data) {
+        assert!(hll.validate().is_ok());
+    }
+    if let Ok(hll) = serde_json::from_slice::<HyperLogLog<P>>(data) {
+        assert!(hll.validate().is_ok());
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    check::<4>(data);
+    check::<14>(data);
+});
@@ -0,0 +1,34 @@
+//! The binary formats of the other sketches, accepted inputs must round-trip
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use simple_hll::HyperBitBit;
+use simple_hll::LinearCounting;
+use simple_hll::Pcsa;
+use simple_hll::ThetaSketch;
+use simple_hll::UltraLogLog;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(sketch) = ThetaSketch::from_bytes(data) {
+        assert_eq!(ThetaSketch::from_bytes(&sketch.to_bytes()).unwrap(), sketch);
+    }
+    if let Ok(sketch) = UltraLogLog::<14>::from_bytes(data) {
+        assert_eq!(
+            UltraLogLog::<14>::from_bytes(&sketch.to_bytes()).unwrap(),
+            sketch
+        );
+    }
+    if let Ok(sketch) = Pcsa::<10>::from_bytes(data) {
+        assert_eq!(Pcsa::<10>::from_bytes(&sketch.to_bytes()).unwrap(), sketch);
+    }
+    if let Ok(sketch) = LinearCounting::from_bytes(data) {
+        assert_eq!(
+            LinearCounting::from_bytes(&sketch.to_bytes()).unwrap(),
+            sketch
+        );
+    }
+    if let Ok(sketch) = HyperBitBit::from_bytes(data) {
+        assert_eq!(HyperBitBit::from_bytes(&sketch.to_bytes()).unwrap(), sketch);
+    }
+});
@@ -0,0 +1,70 @@
+#ifndef SIMPLE_HLL_H
+#define SIMPLE_HLL_H
+
+/* Generated by cbindgen from src/ffi.rs, don't edit */
+
+#include <stddef.h>
+#include <stdint.h>
+
+// A sketch of precision `14`, only handled through pointers
+typedef struct HllSketch HllSketch;
+
+// A new empty sketch, release it with `hll_free`
+HllSketch *hll_new(void);
+
+// Release a sketch, `NULL` is ignored
+//
+// # Safety
+//
+// `hll` must be `NULL` or a sketch returned by this library, not released yet
+void hll_free(HllSketch *hll);
+
+// Add a 64-bit hash computed by the caller, the low 14 bits pick the register
+//
+// # Safety
+//
+// `hll` must be a live sketch returned by this library
+void hll_add_hash(HllSketch *hll, uint64_t hash);
+
+// Add `len` bytes hashed with the default hasher, the same as `add_object` of a
+// `&[u8]` or `Vec<u8>` with these bytes in Rust
+//
+// # Safety
+//
+// `hll` must be a live sketch returned by this library, `bytes` must point to `len`
+// readable bytes, it may be `NULL` if `len` is `0`
+void hll_add_bytes(HllSketch *hll, const uint8_t *bytes, size_t len);
+
+// Merge `src` into `dst`, nothing changes if they are the same sketch
+//
+// # Safety
+//
+// `dst` and `src` must be live sketches returned by this library, they may be the same
+void hll_merge(HllSketch *dst, const HllSketch *src);
+
+// The estimated number of distinct items, `0` for `NULL`
+//
+// # Safety
+//
+// `hll` must be a live sketch returned by this library
+uint64_t hll_count(const HllSketch *hll);
+
+// Write the binary format of the sketch into `buf` and return its size. Nothing is
+// written if `cap` is smaller than the size, call it with `NULL` and `0` to get the
+// size first. Returns `0` for a `NULL` sketch.
+//
+// # Safety
+//
+// `hll` must be a live sketch returned by this library, `buf` must point to `cap`
+// writable bytes, it may be `NULL` if `cap` is `0`
+size_t hll_serialize(const HllSketch *hll, uint8_t *buf, size_t cap);
+
+// Read a sketch written by `hll_serialize` or `HyperLogLog::to_bytes`, `NULL` if the bytes
+// are invalid. Release it with `hll_free`.
+//
+// # Safety
+//
+// `bytes` must point to `len` readable bytes, it may be `NULL` if `len` is `0`
+HllSketch *hll_deserialize(const uint8_t *bytes, size_t len);
+
+#endif  /* SIMPLE_HLL_H */
@@ -0,0 +1,46 @@
+"""Polars expressions over simple_hll sketches.
+
+Sketches are the binary format of ``HyperLogLog::to_bytes`` in Rust, with precision 14 and
+the default hasher, so they can be exchanged with the Rust services using simple_hll.
+"""
+
+from __future__ import annotations
+
+from pathlib import Path
+
+import polars as pl
+from polars.plugins import register_plugin_function
+
+from simple_hll_polars._internal import __version__ as __version__
+
+PLUGIN_PATH = Path(__file__).parent
+
+
+def hll_agg(expr: pl.Expr | str) -> pl.Expr:
+    """Aggregate the non-null values into a sketch, hashed like ``add_object`` in Rust."""
+    return register_plugin_function(
+        plugin_path=PLUGIN_PATH,
+        function_name="hll_agg",
+        args=expr,
+        returns_scalar=True,
+    )
+
+
+def hll_merge(expr: pl.Expr | str) -> pl.Expr:
+    """Union of a column of sketches, null sketches are ignored."""
+    return register_plugin_function(
+        plugin_path=PLUGIN_PATH,
+        function_name="hll_merge",
+        args=expr,
+        returns_scalar=True,
+    )
+
+
+def hll_count(expr: pl.Expr | str) -> pl.Expr:
+    """The estimated number of distinct items of every sketch of a column."""
+    return register_plugin_function(
+        plugin_path=PLUGIN_PATH,
+        function_name="hll_count",
+        args=expr,
+        is_elementwise=True,
+    )
@@ -0,0 +1,132 @@
+//! Polars plugin expressions over [`HyperLogLog`] sketches, so DataFrame pipelines produce
+//! and consume the same sketch bytes as the Rust services using `simple_hll`.
+//!
+//! - `hll_agg(expr)`: the sketch of the non-null values of a column, hashed like
+//!   [`HyperLogLog::add_object`] does (strings like `&str`, binaries like `&[u8]`, integers
+//!   like their native type).
+//! - `hll_merge(expr)`: the union of a column of sketches.
+//! - `hll_count(expr)`: the estimate of every sketch of a column.
+//!
+//! Sketches are the binary format of [`HyperLogLog::to_bytes`] with precision `14`. Build
+//! the Python package `simple_hll_polars` with `maturin develop --release` in this
+//! directory.
+
+use polars::prelude::*;
+use pyo3::prelude::*;
+use pyo3_polars::derive::polars_expr;
+use pyo3_polars::PolarsAllocator;
+use simple_hll::HyperLogLog;
+
+#[global_allocator]
+static ALLOC: PolarsAllocator = PolarsAllocator::new();
+
+#[pymodule]
+fn _internal(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("__version__", env!("CARGO_PKG_VERSION"))
+}
+
+/// Add the non-null values of `s`
+fn add_series(hll: &mut HyperLogLog, s: &Series) -> PolarsResult<()> {
+    macro_rules! add {
+        ($ca:expr) => {
+            $ca.into_iter().flatten().for_each(|v| hll.add_object(&v))
+        };
+    }
+    match s.dtype() {
+        DataType::Int8 => add!(s.i8()?),
+        DataType::Int16 => add!(s.i16()?),
+        DataType::Int32 => add!(s.i32()?),
+        DataType::Int64 => add!(s.i64()?),
+        DataType::UInt8 => add!(s.u8()?),
+        DataType::UInt16 => add!(s.u16()?),
+        DataType::UInt32 => add!(s.u32()?),
+        DataType::UInt64 => add!(s.u64()?),
+        DataType::String => add!(s.str()?),
+        DataType::Binary => add!(s.binary()?),
+        DataType::Null => {}
+        dtype => polars_bail!(InvalidOperation: "hll_agg of {}", dtype),
+    }
+    Ok(())
+}
+
+fn from_bytes(bytes: &[u8]) -> PolarsResult<HyperLogLog> {
+    HyperLogLog::from_bytes(bytes).map_err(|e| polars_err!(ComputeError: "{}", e))
+}
+
+fn sketch(name: PlSmallStr, hll: &HyperLogLog) -> Series {
+    BinaryChunked::from_slice(name, &[hll.to_bytes()]).into_series()
+}
+
+#[polars_expr(output_type=Binary)]
+fn hll_agg(inputs: &[Series]) -> PolarsResult<Series> {
+    let s = &inputs[0];
+    let mut hll = HyperLogLog::new();
+    add_series(&mut hll, s)?;
+    Ok(sketch(s.name().clone(), &hll))
+}
+
+#[polars_expr(output_type=Binary)]
+fn hll_merge(inputs: &[Series]) -> PolarsResult<Series> {
+    let s = &inputs[0];
+    let mut hll = HyperLogLog::new();
+    for bytes in s.binary()?.into_iter().flatten() {
+        hll.merge(&from_bytes(bytes)?);
+    }
+    Ok(sketch(s.name().clone(), &hll))
+}
+
+#[polars_expr(output_type=UInt64)]
+fn hll_count(inputs: &[Series]) -> PolarsResult<Series> {
+    let ca = inputs[0].binary()?;
+    let out: UInt64Chunked = ca.try_apply_nonnull_values_generic(|bytes| {
+        from_bytes(bytes).map(|hll| hll.count() as u64)
+    })?;
+    Ok(out.into_series())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_polars_expressions() {
+        let users = Series::new(
+            "users".into(),
+            (0..1000)
+                .map(|i| (i % 10 != 0).then(|| format!("user-{}", i)))
+                .collect::<Vec<_>>(),
+        );
+        let ids = Series::new("ids".into(), (0..1000_i64).collect::<Vec<_>>());
+        let a = hll_agg(&[users]).unwrap();
+        let b = hll_agg(&[ids]).unwrap();
+        assert_eq!(a.len(), 1);
+
+        let mut expected = HyperLogLog::<14>::new();
+        for i in 0..1000_i64 {
+            if i % 10 != 0 {
+                expected.add_object(&format!("user-{}", i).as_str());
+            }
+            expected.add_object(&i);
+        }
+
+        let mut sketches = a.clone();
+        sketches.append(&b).unwrap();
+        sketches.append(&Series::full_null("".into(), 1, &DataType::Binary)).unwrap();
+        let merged = hll_merge(&[sketches.clone()]).unwrap();
+        let bytes = merged.binary().unwrap().get(0).unwrap();
+        assert_eq!(HyperLogLog::<14>::from_bytes(bytes).unwrap(), expected);
+
+        let counts = hll_count(&[sketches]).unwrap();
+        let counts = counts.u64().unwrap();
+        assert_eq!(counts.get(2), None);
+        assert_eq!(
+            hll_count(&[merged]).unwrap().u64().unwrap().get(0),
+            Some(expected.count() as u64)
+        );
+
+        let invalid = Series::new("invalid".into(), [b"SHLL".as_slice()]);
+        assert!(hll_count(&[invalid.clone()]).is_err());
+        assert!(hll_merge(&[invalid]).is_err());
+        assert!(hll_agg(&[Series::new("f".into(), [1.0_f64])]).is_err());
+    }
+}
@@ -0,0 +1 @@
+::pgrx::pgrx_embed!();
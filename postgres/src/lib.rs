@@ -0,0 +1,186 @@
+//! Postgres extension over [`HyperLogLog`] sketches, built with
+//! [pgrx](https://github.com/pgcentralfoundation/pgrx), so the database aggregates the same
+//! sketches as the Rust services using `simple_hll`.
+//!
+//! - `hll`: a domain over `bytea` holding the binary format of [`HyperLogLog::to_bytes`]
+//!   with precision `14`, services read and write it like any `bytea` column.
+//! - `hll_add(hll, value)`: the sketch with `value` added, a `NULL` sketch is empty.
+//! - `hll_add_agg(value)`: the sketch of the non-null values of a group.
+//! - `hll_union(hll)`: the union of the non-null sketches of a group.
+//! - `hll_cardinality(hll)`: the estimate of a sketch.
+//!
+//! Values are hashed like [`HyperLogLog::add_object`] does: `text` like `&str`, `bytea` like
+//! `&[u8]` and integers like `i64`. Install the extension with `cargo pgrx install --release`
+//! in this directory, then `CREATE EXTENSION simple_hll_postgres`.
+
+use pgrx::prelude::*;
+use pgrx::Internal;
+use simple_hll::HyperLogLog;
+
+::pgrx::pg_module_magic!();
+
+extension_sql!(
+    "CREATE DOMAIN hll AS bytea CHECK (hll_is_valid(VALUE));",
+    name = "hll_domain",
+    requires = [hll_is_valid],
+);
+
+fn from_bytes(bytes: &[u8]) -> HyperLogLog {
+    HyperLogLog::from_bytes(bytes).unwrap_or_else(|e| error!("invalid hll: {}", e))
+}
+
+fn sketch(hll: Option<&[u8]>) -> HyperLogLog {
+    hll.map(from_bytes).unwrap_or_default()
+}
+
+/// Whether `bytes` is a sketch of precision `14`, the check of domain `hll`
+#[pg_extern(immutable, parallel_safe)]
+fn hll_is_valid(bytes: &[u8]) -> bool {
+    HyperLogLog::<14>::from_bytes(bytes).is_ok()
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn hll_empty() -> Vec<u8> {
+    HyperLogLog::<14>::new().to_bytes()
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn hll_cardinality(hll: &[u8]) -> i64 {
+    from_bytes(hll).count() as i64
+}
+
+macro_rules! hll_add {
+    ($add:ident, $agg:ident, $value:ty) => {
+        #[pg_extern(name = "hll_add", immutable, parallel_safe)]
+        fn $add(hll: Option<&[u8]>, value: Option<$value>) -> Vec<u8> {
+            let mut hll = sketch(hll);
+            if let Some(value) = value {
+                hll.add_object(&value);
+            }
+            hll.to_bytes()
+        }
+
+        /// `hll_add_agg`, the sketch lives in the aggregate memory context between rows
+        pub struct $agg;
+
+        #[pg_aggregate]
+        impl Aggregate<$agg> for $agg {
+            const NAME: &'static str = "hll_add_agg";
+            type Args = pgrx::name!(value, Option<$value>);
+            type State = Internal;
+            type Finalize = Vec<u8>;
+
+            fn state(
+                mut current: Self::State,
+                value: Self::Args,
+                _fcinfo: pg_sys::FunctionCallInfo,
+            ) -> Self::State {
+                let hll = unsafe { current.get_or_insert_default::<HyperLogLog>() };
+                if let Some(value) = value {
+                    hll.add_object(&value);
+                }
+                current
+            }
+
+            fn finalize(
+                mut current: Self::State,
+                _direct_args: Self::OrderedSetArgs,
+                _fcinfo: pg_sys::FunctionCallInfo,
+            ) -> Self::Finalize {
+                unsafe { current.get_or_insert_default::<HyperLogLog>() }.to_bytes()
+            }
+        }
+    };
+}
+
+// owned values, the aggregate arguments can't borrow from the row
+hll_add!(hll_add_text, HllAddText, String);
+hll_add!(hll_add_bytea, HllAddBytea, Vec<u8>);
+hll_add!(hll_add_bigint, HllAddBigint, i64);
+
+/// `hll_union`, the union lives in the aggregate memory context between rows
+pub struct HllUnion;
+
+#[pg_aggregate]
+impl Aggregate<HllUnion> for HllUnion {
+    const NAME: &'static str = "hll_union";
+    type Args = pgrx::name!(hll, Option<Vec<u8>>);
+    type State = Internal;
+    type Finalize = Vec<u8>;
+
+    fn state(
+        mut current: Self::State,
+        hll: Self::Args,
+        _fcinfo: pg_sys::FunctionCallInfo,
+    ) -> Self::State {
+        let union = unsafe { current.get_or_insert_default::<HyperLogLog>() };
+        if let Some(hll) = hll {
+            union.merge(&from_bytes(&hll));
+        }
+        current
+    }
+
+    fn finalize(
+        mut current: Self::State,
+        _direct_args: Self::OrderedSetArgs,
+        _fcinfo: pg_sys::FunctionCallInfo,
+    ) -> Self::Finalize {
+        unsafe { current.get_or_insert_default::<HyperLogLog>() }.to_bytes()
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use pgrx::prelude::*;
+    use simple_hll::HyperLogLog;
+
+    #[pg_test]
+    fn test_postgres_functions() {
+        Spi::run(
+            "CREATE TABLE daily (day int, users hll);
+             INSERT INTO daily
+             SELECT i % 2, hll_add_agg('user-' || i) FROM generate_series(0, 999) i GROUP BY i % 2;
+             INSERT INTO daily VALUES (2, hll_add(hll_add(NULL, 1), NULL::bigint)), (3, NULL);",
+        )
+        .unwrap();
+
+        let mut expected = HyperLogLog::<14>::new();
+        for i in 0..1000 {
+            expected.add_object(&format!("user-{}", i));
+        }
+        expected.add_object(&1_i64);
+
+        let union = Spi::get_one::<Vec<u8>>("SELECT hll_union(users) FROM daily")
+            .unwrap()
+            .unwrap();
+        assert_eq!(HyperLogLog::<14>::from_bytes(&union).unwrap(), expected);
+        assert_eq!(
+            Spi::get_one::<i64>("SELECT hll_cardinality(hll_union(users)) FROM daily").unwrap(),
+            Some(expected.count() as i64)
+        );
+        assert_eq!(
+            Spi::get_one::<i64>("SELECT hll_cardinality(hll_add_agg(NULL::text))").unwrap(),
+            Some(0)
+        );
+        assert_eq!(
+            Spi::get_one::<bool>("SELECT hll_is_valid('SHLL'::bytea)").unwrap(),
+            Some(false)
+        );
+    }
+
+    #[pg_test(error = "value for domain hll violates check constraint \"hll_check\"")]
+    fn test_postgres_invalid() {
+        Spi::run("SELECT 'SHLL'::bytea::hll").unwrap();
+    }
+}
+
+/// Required by `cargo pgrx test`
+#[cfg(test)]
+pub mod pg_test {
+    pub fn setup(_options: Vec<&str>) {}
+
+    pub fn postgresql_conf_options() -> Vec<&'static str> {
+        vec![]
+    }
+}
@@ -0,0 +1,68 @@
+//! `Arbitrary` sketches for fuzzing, always structurally valid: `P` is checked at compile
+//! time and every register is at most `64 - P + 1`.
+
+use ::arbitrary::Arbitrary;
+use ::arbitrary::Result;
+use ::arbitrary::Unstructured;
+
+use crate::HyperLogLog;
+
+impl<'a, const P: usize> Arbitrary<'a> for HyperLogLog<P> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut hll = HyperLogLog::<P>::new();
+        let m = Self::NUM_REGISTERS;
+        let max = Self::max_register_value();
+        if u.arbitrary()? {
+            // a few non-zero registers, like sketches of small sets, which are serialized
+            // with the sparse representations
+            for _ in 0..u.arbitrary_len::<(u32, u8)>()?.min(m) {
+                let index = u.choose_index(m)?;
+                hll.registers[index] = u.int_in_range(1..=max)?;
+            }
+        } else {
+            for r in hll.registers.iter_mut() {
+                *r = u.int_in_range(0..=max)?;
+            }
+        }
+        Ok(hll)
+    }
+
+    fn size_hint(_depth: usize) -> (usize, Option<usize>) {
+        (1, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arbitrary() {
+        let mut state = 0x1234_u64;
+        let data: Vec<u8> = (0..1 << 16)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                (state >> 56) as u8
+            })
+            .collect();
+
+        let mut u = Unstructured::new(&data);
+        let mut sparse = 0;
+        for _ in 0..100 {
+            let hll = HyperLogLog::<8>::arbitrary(&mut u).unwrap();
+            assert!(hll
+                .registers
+                .iter()
+                .all(|r| *r <= HyperLogLog::<8>::max_register_value()));
+            assert_eq!(HyperLogLog::<8>::from_bytes(&hll.to_bytes()).unwrap(), hll);
+            if hll.num_empty_registers() > 128 {
+                sparse += 1;
+            }
+        }
+        assert!(sparse > 0);
+
+        // running out of data still gives a valid sketch
+        let hll = HyperLogLog::<14>::arbitrary(&mut Unstructured::new(&[0])).unwrap();
+        assert_eq!(hll, HyperLogLog::<14>::new());
+    }
+}
@@ -0,0 +1,112 @@
+//! Vectorized aggregation of Arrow columns of serialized sketches.
+//!
+//! Sketches are serialized in the binary format (see [`crate::format`]), null values are
+//! skipped, like SQL aggregate functions do.
+
+use arrow_array::builder::BinaryBuilder;
+use arrow_array::Array;
+use arrow_array::BinaryArray;
+use arrow_array::GenericBinaryArray;
+use arrow_array::OffsetSizeTrait;
+use arrow_array::UInt64Array;
+
+use crate::HllError;
+use crate::HyperLogLog;
+
+/// Merge every sketch of the column into one
+pub fn merge<const P: usize, O: OffsetSizeTrait>(
+    sketches: &GenericBinaryArray<O>,
+) -> Result<HyperLogLog<P>, HllError> {
+    let mut hll = HyperLogLog::<P>::new();
+    for bytes in sketches.iter().flatten() {
+        hll.merge_from_slice(bytes)?;
+    }
+    Ok(hll)
+}
+
+/// Merge the sketches of the column per group, row `i` belongs to group `group_indices[i]`,
+/// every group index must be smaller than `num_groups`.
+pub fn merge_grouped<const P: usize, O: OffsetSizeTrait>(
+    sketches: &GenericBinaryArray<O>,
+    group_indices: &[usize],
+    num_groups: usize,
+) -> Result<Vec<HyperLogLog<P>>, HllError> {
+    assert_eq!(sketches.len(), group_indices.len());
+
+    let mut groups = vec![HyperLogLog::<P>::new(); num_groups];
+    for (bytes, group) in sketches.iter().zip(group_indices) {
+        if let Some(bytes) = bytes {
+            groups[*group].merge_from_slice(bytes)?;
+        }
+    }
+    Ok(groups)
+}
+
+/// Serialize the sketches into a column, e.g. the partial state of an aggregation
+pub fn to_binary_array<const P: usize>(sketches: &[HyperLogLog<P>]) -> BinaryArray {
+    let mut builder = BinaryBuilder::with_capacity(sketches.len(), 0);
+    let mut buf = Vec::new();
+    for hll in sketches {
+        buf.clear();
+        hll.serialize_into(&mut buf);
+        builder.append_value(&buf);
+    }
+    builder.finish()
+}
+
+/// The estimated count of every sketch
+pub fn count<const P: usize>(sketches: &[HyperLogLog<P>]) -> UInt64Array {
+    sketches.iter().map(|hll| hll.count() as u64).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::LargeBinaryArray;
+
+    use super::*;
+
+    fn sketch(range: core::ops::Range<u64>) -> HyperLogLog<12> {
+        let mut hll = HyperLogLog::new();
+        for i in range {
+            hll.add_object(&i);
+        }
+        hll
+    }
+
+    #[test]
+    fn test_merge() {
+        let sketches = [sketch(0..1000), sketch(500..2000), sketch(0..0)];
+        let array = to_binary_array(&sketches);
+        let mut expected = sketches[0].clone();
+        expected.merge(&sketches[1]);
+        assert_eq!(merge::<12, _>(&array).unwrap(), expected);
+
+        // nulls are skipped, large offsets work the same
+        let large: LargeBinaryArray = vec![
+            Some(sketches[0].to_bytes().as_slice()),
+            None,
+            Some(sketches[1].to_bytes().as_slice()),
+        ]
+        .into();
+        assert_eq!(merge::<12, _>(&large).unwrap(), expected);
+
+        let invalid = BinaryArray::from(vec![&b"SHLL"[..]]);
+        assert_eq!(merge::<12, _>(&invalid), Err(HllError::UnexpectedEof));
+    }
+
+    #[test]
+    fn test_merge_grouped() {
+        let sketches = [sketch(0..1000), sketch(0..10), sketch(1000..3000)];
+        let array = to_binary_array(&sketches);
+        let groups = merge_grouped::<12, _>(&array, &[0, 1, 0], 3).unwrap();
+
+        let mut expected = sketches[0].clone();
+        expected.merge(&sketches[2]);
+        assert_eq!(groups, [expected, sketches[1].clone(), HyperLogLog::new()]);
+
+        let counts = count(&groups);
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts.value(0), groups[0].count() as u64);
+        assert_eq!(counts.value(2), 0);
+    }
+}
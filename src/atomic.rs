@@ -0,0 +1,196 @@
+//! A sketch shared by many threads, updated without locks.
+
+use core::hash::Hash;
+#[cfg(not(loom))]
+use core::sync::atomic::AtomicU8;
+#[cfg(not(loom))]
+use core::sync::atomic::Ordering;
+
+#[cfg(loom)]
+use loom::sync::atomic::AtomicU8;
+#[cfg(loom)]
+use loom::sync::atomic::Ordering;
+
+use crate::Hasher;
+use crate::HyperLogLog;
+
+/// [`HyperLogLog`] with atomic registers, `add_hash` takes `&self` so many threads can add
+/// to the same sketch concurrently, e.g. through an `Arc`.
+///
+/// Registers only ever grow and each update is a single atomic max, so updates are never
+/// lost. [`Self::snapshot`] reads every register once, registers updated during the
+/// snapshot may or may not be included, like with a lock released in between.
+#[derive(Debug)]
+pub struct AtomicHyperLogLog<const P: usize> {
+    registers: Box<[AtomicU8]>,
+}
+
+impl<const P: usize> Default for AtomicHyperLogLog<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const P: usize> From<&HyperLogLog<P>> for AtomicHyperLogLog<P> {
+    fn from(hll: &HyperLogLog<P>) -> Self {
+        Self {
+            registers: hll.registers.iter().map(|r| AtomicU8::new(*r)).collect(),
+        }
+    }
+}
+
+impl<const P: usize> AtomicHyperLogLog<P> {
+    pub fn new() -> Self {
+        Self::from(&HyperLogLog::<P>::new())
+    }
+
+    /// Adds an hash, see [`HyperLogLog::add_hash`]
+    #[inline]
+    pub fn add_hash(&self, hash: u64) {
+        let index = (hash & HyperLogLog::<P>::REGISTER_MASK) as usize;
+        let rank = HyperLogLog::<P>::rank(hash);
+        // most updates don't raise the register, a load is cheaper than a read-modify-write
+        let register = &self.registers[index];
+        if register.load(Ordering::Relaxed) < rank {
+            register.fetch_max(rank, Ordering::Relaxed);
+        }
+    }
+
+    #[inline]
+    pub fn add_object<T: Hash>(&self, obj: &T) {
+        self.add_object_by_hasher::<T, ahash::AHasher>(obj);
+    }
+
+    #[inline]
+    pub fn add_object_by_hasher<T: Hash, H: Hasher>(&self, obj: &T) {
+        self.add_hash(H::hll_hash(obj));
+    }
+
+    /// Merge a sketch into this one
+    pub fn merge(&self, other: &HyperLogLog<P>) {
+        for (register, value) in self.registers.iter().zip(&other.registers) {
+            if register.load(Ordering::Relaxed) < *value {
+                register.fetch_max(*value, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// A plain copy of the registers, to count, serialize or merge
+    pub fn snapshot(&self) -> HyperLogLog<P> {
+        HyperLogLog {
+            registers: self
+                .registers
+                .iter()
+                .map(|r| r.load(Ordering::Relaxed))
+                .collect(),
+        }
+    }
+
+    /// Count the unique elements of a snapshot
+    pub fn count(&self) -> usize {
+        self.snapshot().count()
+    }
+
+    pub fn into_inner(self) -> HyperLogLog<P> {
+        HyperLogLog {
+            registers: self
+                .registers
+                .into_vec()
+                .into_iter()
+                .map(AtomicU8::into_inner)
+                .collect(),
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concurrent_add() {
+        let mut expected = HyperLogLog::<14>::new();
+        for i in 0..100_000_u64 {
+            expected.add_object(&i);
+        }
+
+        let hll = AtomicHyperLogLog::<14>::new();
+        std::thread::scope(|s| {
+            for t in 0..4_u64 {
+                let hll = &hll;
+                // overlapping ranges, threads race on the same registers
+                s.spawn(move || {
+                    for i in (t * 20_000)..(t * 20_000 + 40_000).min(100_000) {
+                        hll.add_object(&i);
+                    }
+                });
+            }
+        });
+        assert_eq!(hll.snapshot(), expected);
+        assert_eq!(hll.count(), expected.count());
+
+        let other = AtomicHyperLogLog::<14>::new();
+        other.merge(&expected);
+        assert_eq!(other.into_inner(), expected);
+        assert_eq!(AtomicHyperLogLog::from(&expected).snapshot(), expected);
+    }
+}
+
+/// Every interleaving of concurrent adds and snapshots, run with
+/// `RUSTFLAGS="--cfg loom" cargo test --release --lib loom`
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::sync::Arc;
+    use loom::thread;
+
+    use super::*;
+
+    /// A hash of the given rank in register 0 of a sketch with P = 4
+    fn hash(rank: u8) -> u64 {
+        1 << (4 + rank - 1)
+    }
+
+    #[test]
+    fn test_concurrent_add_snapshot() {
+        loom::model(|| {
+            let hll = Arc::new(AtomicHyperLogLog::<4>::new());
+            let threads: Vec<_> = [2, 5]
+                .into_iter()
+                .map(|rank| {
+                    let hll = hll.clone();
+                    thread::spawn(move || hll.add_hash(hash(rank)))
+                })
+                .collect();
+
+            // snapshots see either value, never a lower one than seen before
+            let first = hll.snapshot().registers[0];
+            let second = hll.snapshot().registers[0];
+            assert!([0, 2, 5].contains(&first));
+            assert!([0, 2, 5].contains(&second));
+            assert!(second >= first);
+
+            for t in threads {
+                t.join().unwrap();
+            }
+            // no update is lost whatever the order of the loads and maxes
+            assert_eq!(hll.snapshot().registers[0], 5);
+        });
+    }
+
+    #[test]
+    fn test_concurrent_merge() {
+        loom::model(|| {
+            let hll = Arc::new(AtomicHyperLogLog::<4>::new());
+            let mut other = HyperLogLog::<4>::new();
+            other.add_hash(hash(3));
+
+            let adder = {
+                let hll = hll.clone();
+                thread::spawn(move || hll.add_hash(hash(4)))
+            };
+            hll.merge(&other);
+            adder.join().unwrap();
+            assert_eq!(hll.snapshot().registers[0], 4);
+        });
+    }
+}
@@ -0,0 +1,217 @@
+//! Avro record of the sketch, the schema is in `avro/simple_hll.avsc`.
+//!
+//! [`HyperLogLog::to_avro_datum`] and [`HyperLogLog::from_avro_datum`] encode a single
+//! datum without the container header, the usual shape of Kafka messages, the schema
+//! being registered in a schema registry. Values can also be written into Avro container
+//! files with [`apache_avro::Writer`].
+
+use apache_avro::types::Value;
+use apache_avro::Schema;
+
+use crate::format;
+use crate::Hasher;
+use crate::HllError;
+use crate::HyperLogLog;
+
+/// The Avro schema of the sketch record
+pub const SCHEMA: &str = include_str!("../avro/simple_hll.avsc");
+
+const REPRESENTATIONS: [&str; 4] = ["EMPTY", "SPARSE", "DENSE", "COMPRESSED"];
+
+/// Parsed [`SCHEMA`]
+pub fn schema() -> Schema {
+    Schema::parse_str(SCHEMA).expect("the schema is valid")
+}
+
+impl<const P: usize> From<&HyperLogLog<P>> for Value {
+    fn from(hll: &HyperLogLog<P>) -> Self {
+        let mut bytes = hll.to_bytes();
+        let header: Vec<u8> = bytes.drain(..format::HEADER_SIZE).collect();
+        let (hasher, representation) = (header[6], header[7]);
+        Value::Record(vec![
+            ("precision".to_string(), Value::Int(P as i32)),
+            (
+                "representation".to_string(),
+                Value::Enum(
+                    representation as u32,
+                    REPRESENTATIONS[representation as usize].to_string(),
+                ),
+            ),
+            ("payload".to_string(), Value::Bytes(bytes)),
+            ("hasher".to_string(), Value::Int(hasher as i32)),
+        ])
+    }
+}
+
+impl<const P: usize> TryFrom<&Value> for HyperLogLog<P> {
+    type Error = HllError;
+
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        let fields = match value {
+            Value::Record(fields) => fields,
+            _ => return Err(malformed("not a record")),
+        };
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v)
+                .ok_or_else(|| malformed(&format!("missing field {}", name)))
+        };
+
+        // a higher precision is folded into `P` by `from_bytes`
+        let precision = match field("precision")? {
+            Value::Int(p) => {
+                u8::try_from(*p).map_err(|_| HllError::InvalidPrecision((*p).max(0) as usize))?
+            }
+            _ => return Err(malformed("precision isn't an int")),
+        };
+        let representation = match field("representation")? {
+            Value::Enum(index, _) => u8::try_from(*index).unwrap_or(u8::MAX),
+            _ => return Err(malformed("representation isn't an enum")),
+        };
+        let payload = match field("payload")? {
+            Value::Bytes(payload) => payload,
+            _ => return Err(malformed("payload isn't bytes")),
+        };
+        let hasher = match field("hasher")? {
+            Value::Int(hasher) => u8::try_from(*hasher).map_err(|_| HllError::HasherMismatch {
+                expected: ahash::AHasher::ID,
+                actual: u8::MAX,
+            })?,
+            _ => return Err(malformed("hasher isn't an int")),
+        };
+
+        let mut bytes = Vec::with_capacity(format::HEADER_SIZE + payload.len());
+        bytes.extend_from_slice(format::MAGIC);
+        bytes.extend_from_slice(&[format::VERSION, precision, hasher, representation]);
+        bytes.extend_from_slice(payload);
+        HyperLogLog::<P>::from_bytes(&bytes)
+    }
+}
+
+impl<const P: usize> TryFrom<Value> for HyperLogLog<P> {
+    type Error = HllError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        HyperLogLog::<P>::try_from(&value)
+    }
+}
+
+impl<const P: usize> HyperLogLog<P> {
+    /// Encode a single Avro datum of [`SCHEMA`], without the container header
+    pub fn to_avro_datum(&self) -> Vec<u8> {
+        apache_avro::to_avro_datum(&schema(), Value::from(self))
+            .expect("the value matches the schema")
+    }
+
+    /// Decode a single Avro datum written with [`SCHEMA`]
+    pub fn from_avro_datum(mut bytes: &[u8]) -> Result<Self, HllError> {
+        let value = apache_avro::from_avro_datum(&schema(), &mut bytes, None)
+            .map_err(|e| malformed(&e.to_string()))?;
+        if !bytes.is_empty() {
+            return Err(HllError::TrailingBytes(bytes.len()));
+        }
+        Self::try_from(value)
+    }
+}
+
+fn malformed(reason: &str) -> HllError {
+    HllError::Malformed(format!("avro: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use apache_avro::Reader;
+    use apache_avro::Writer;
+
+    use super::*;
+
+    #[test]
+    fn test_avro_datum() {
+        let mut hll = HyperLogLog::<14>::new();
+        for n in [0, 100, 10_000, 1_000_000] {
+            for i in 0..n {
+                hll.add_object(&i);
+            }
+            let bytes = hll.to_avro_datum();
+            assert_eq!(HyperLogLog::<14>::from_avro_datum(&bytes).unwrap(), hll);
+        }
+        // the hasher of the binary format
+        match Value::from(&hll) {
+            Value::Record(fields) => {
+                assert_eq!(fields[3].1, Value::Int(hll.to_bytes()[6] as i32))
+            }
+            _ => unreachable!(),
+        }
+
+        // a higher precision is folded, a lower one is an error
+        assert_eq!(
+            HyperLogLog::<12>::from_avro_datum(&hll.to_avro_datum()).unwrap(),
+            hll.fold::<12>().unwrap()
+        );
+        assert_eq!(
+            HyperLogLog::<16>::from_avro_datum(&hll.to_avro_datum()),
+            Err(HllError::PrecisionMismatch {
+                expected: 16,
+                actual: 14
+            })
+        );
+        let mut bytes = hll.to_avro_datum();
+        bytes.truncate(bytes.len() - 1);
+        assert!(matches!(
+            HyperLogLog::<14>::from_avro_datum(&bytes),
+            Err(HllError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn test_avro_container() {
+        let sketches: Vec<HyperLogLog<12>> = [0, 10, 100_000]
+            .iter()
+            .map(|n| {
+                let mut hll = HyperLogLog::new();
+                (0..*n).for_each(|i| hll.add_object(&i));
+                hll
+            })
+            .collect();
+
+        let schema = schema();
+        let mut writer = Writer::new(&schema, Vec::new());
+        for hll in &sketches {
+            writer.append(Value::from(hll)).unwrap();
+        }
+        let bytes = writer.into_inner().unwrap();
+
+        let read: Vec<HyperLogLog<12>> = Reader::new(&bytes[..])
+            .unwrap()
+            .map(|value| HyperLogLog::try_from(value.unwrap()).unwrap())
+            .collect();
+        assert_eq!(read, sketches);
+    }
+
+    #[test]
+    fn test_avro_invalid() {
+        let value = Value::Record(vec![("precision".to_string(), Value::Int(12))]);
+        assert_eq!(
+            HyperLogLog::<12>::try_from(&value),
+            Err(HllError::Malformed(
+                "avro: missing field representation".to_string()
+            ))
+        );
+
+        let mut hll = HyperLogLog::<12>::new();
+        hll.add_object(&1);
+        let mut value = Value::from(&hll);
+        if let Value::Record(fields) = &mut value {
+            fields[3].1 = Value::Int(0);
+        }
+        assert_eq!(
+            HyperLogLog::<12>::try_from(&value),
+            Err(HllError::HasherMismatch {
+                expected: 1,
+                actual: 0
+            })
+        );
+    }
+}
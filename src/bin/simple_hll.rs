@@ -0,0 +1,10 @@
+use std::io;
+use std::process;
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if let Err(e) = simple_hll::cli::run(&args, io::stdin().lock(), &mut io::stdout().lock()) {
+        eprintln!("simple_hll: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,369 @@
+//! The `simple_hll` command line tool, built with `cargo install simple_hll --features cli`.
+//!
+//! ```sh
+//! # the estimated number of distinct lines, optionally saving the sketch
+//! cat users.txt | simple_hll count -o users.hll
+//! # the union of sketch files written by `to_bytes`
+//! simple_hll merge a.hll b.hll -o out.hll
+//! # precision, hasher, fill and estimate of a sketch file
+//! simple_hll inspect out.hll
+//! ```
+//!
+//! `count` hashes every line like [`HyperLogLog::add_from_reader`]. `merge` and `inspect`
+//! read sketches of any precision written with any hasher of the enabled features, `merge`
+//! folds the inputs into the lowest precision and keeps their hasher id.
+
+use std::error::Error;
+use std::fs;
+use std::io::BufRead;
+use std::io::Write;
+
+use crate::format::HASHER_OFFSET;
+use crate::format::HEADER_SIZE;
+use crate::format::MAGIC;
+use crate::format::PRECISION_OFFSET;
+use crate::format::REPR_OFFSET;
+use crate::Hasher;
+use crate::HllError;
+use crate::HyperLogLog;
+
+pub const USAGE: &str = "\
+usage: simple_hll count [-p <precision>] [-o <out.hll>] < lines
+       simple_hll merge <in.hll>... -o <out.hll>
+       simple_hll inspect <in.hll>";
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// Run the command in `args` (without the program name), reading lines from `stdin` and
+/// printing to `stdout`
+pub fn run(args: &[String], stdin: impl BufRead, stdout: &mut impl Write) -> Result<()> {
+    let Some((command, args)) = args.split_first() else {
+        return Err(USAGE.into());
+    };
+    match command.as_str() {
+        "count" => {
+            let args = Args::parse(args, &["-p", "-o"])?;
+            if !args.inputs.is_empty() {
+                return Err(USAGE.into());
+            }
+            let precision = match args.value("-p") {
+                Some(p) => p
+                    .parse()
+                    .map_err(|_| format!("invalid precision `{}`", p))?,
+                None => crate::hyperloglog::DEFAULT_P,
+            };
+            dispatch(
+                precision,
+                <ahash::AHasher as Hasher>::ID,
+                Count {
+                    stdin,
+                    stdout,
+                    out: args.value("-o"),
+                },
+            )
+        }
+        "merge" => {
+            let args = Args::parse(args, &["-o"])?;
+            let (Some(out), false) = (args.value("-o"), args.inputs.is_empty()) else {
+                return Err(USAGE.into());
+            };
+            let inputs = args
+                .inputs
+                .iter()
+                .map(|path| read(path).map(|bytes| (path.as_str(), bytes)))
+                .collect::<Result<Vec<_>>>()?;
+            let mut precision = usize::MAX;
+            for (path, bytes) in &inputs {
+                precision = precision.min(header(path, bytes)?.0);
+            }
+            let hasher = header(inputs[0].0, &inputs[0].1)?.1;
+            dispatch(precision, hasher, Merge { inputs, out })
+        }
+        "inspect" => {
+            let args = Args::parse(args, &[])?;
+            let [path] = args.inputs.as_slice() else {
+                return Err(USAGE.into());
+            };
+            let bytes = read(path)?;
+            let (precision, hasher) = header(path, &bytes)?;
+            dispatch(
+                precision,
+                hasher,
+                Inspect {
+                    path,
+                    bytes: &bytes,
+                    stdout,
+                },
+            )
+        }
+        "-h" | "--help" | "help" => Ok(writeln!(stdout, "{}", USAGE)?),
+        command => Err(format!("unknown command `{}`\n{}", command, USAGE).into()),
+    }
+}
+
+/// Positional arguments and the values of the options
+struct Args<'a> {
+    inputs: Vec<&'a String>,
+    options: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Args<'a> {
+    fn parse(args: &'a [String], options: &[&str]) -> Result<Self> {
+        let mut parsed = Args {
+            inputs: Vec::new(),
+            options: Vec::new(),
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if options.contains(&arg.as_str()) {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("missing value of `{}`", arg))?;
+                parsed.options.push((arg, value));
+            } else if arg.starts_with('-') && arg.len() > 1 {
+                return Err(format!("unknown option `{}`\n{}", arg, USAGE).into());
+            } else {
+                parsed.inputs.push(arg);
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// The last value of `option`
+    fn value(&self, option: &str) -> Option<&'a str> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(name, _)| *name == option)
+            .map(|(_, value)| *value)
+    }
+}
+
+fn read(path: &str) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|e| format!("{}: {}", path, e).into())
+}
+
+/// The precision and the hasher id in the header of a sketch file, the rest is validated
+/// when it's read
+fn header(path: &str, bytes: &[u8]) -> Result<(usize, u8)> {
+    match bytes.get(..HEADER_SIZE) {
+        Some(header) if header.starts_with(MAGIC) => {
+            Ok((header[PRECISION_OFFSET] as usize, header[HASHER_OFFSET]))
+        }
+        Some(_) => Err(format!("{}: {}", path, HllError::InvalidMagic).into()),
+        None => Err(format!("{}: {}", path, HllError::UnexpectedEof).into()),
+    }
+}
+
+fn hasher_name(id: u8) -> &'static str {
+    match id {
+        1 => "ahash",
+        2 => "datafusion",
+        3 => "xxh3",
+        4 => "portable",
+        _ => "unknown",
+    }
+}
+
+/// A command run on sketches of precision `P` hashed by `H`
+trait Command {
+    fn run<const P: usize, H: Hasher>(self) -> Result<()>;
+}
+
+fn dispatch(precision: usize, hasher: u8, command: impl Command) -> Result<()> {
+    match hasher {
+        1 => dispatch_precision::<ahash::AHasher>(precision, command),
+        #[cfg(feature = "datafusion_compat")]
+        2 => dispatch_precision::<crate::DataFusionHasher>(precision, command),
+        #[cfg(feature = "xxh3")]
+        3 => dispatch_precision::<crate::Xxh3>(precision, command),
+        #[cfg(feature = "portable")]
+        4 => dispatch_precision::<crate::Portable>(precision, command),
+        id => Err(format!(
+            "sketches of hasher {} ({}) aren't supported by this build",
+            id,
+            hasher_name(id)
+        )
+        .into()),
+    }
+}
+
+fn dispatch_precision<H: Hasher>(precision: usize, command: impl Command) -> Result<()> {
+    macro_rules! precisions {
+        ($($p:literal)*) => {
+            match precision {
+                $($p => command.run::<$p, H>(),)*
+                p => Err(HllError::InvalidPrecision(p).into()),
+            }
+        };
+    }
+    precisions!(4 5 6 7 8 9 10 11 12 13 14 15 16 17 18)
+}
+
+struct Count<'a, R, W> {
+    stdin: R,
+    stdout: &'a mut W,
+    out: Option<&'a str>,
+}
+
+impl<R: BufRead, W: Write> Command for Count<'_, R, W> {
+    fn run<const P: usize, H: Hasher>(self) -> Result<()> {
+        let mut hll = HyperLogLog::<P>::new();
+        hll.add_from_reader_by_hasher::<_, H>(self.stdin, b'\n')?;
+        if let Some(out) = self.out {
+            fs::write(out, hll.to_bytes_with_hasher::<H>())
+                .map_err(|e| format!("{}: {}", out, e))?;
+        }
+        writeln!(self.stdout, "{}", hll.count())?;
+        Ok(())
+    }
+}
+
+struct Merge<'a> {
+    inputs: Vec<(&'a str, Vec<u8>)>,
+    out: &'a str,
+}
+
+impl Command for Merge<'_> {
+    fn run<const P: usize, H: Hasher>(self) -> Result<()> {
+        let mut hll = HyperLogLog::<P>::new();
+        for (path, bytes) in &self.inputs {
+            let other = HyperLogLog::<P>::from_bytes_with_hasher::<H>(bytes)
+                .map_err(|e| format!("{}: {}", path, e))?;
+            hll.merge(&other);
+        }
+        fs::write(self.out, hll.to_bytes_with_hasher::<H>())
+            .map_err(|e| format!("{}: {}", self.out, e))?;
+        Ok(())
+    }
+}
+
+struct Inspect<'a, W> {
+    path: &'a str,
+    bytes: &'a [u8],
+    stdout: &'a mut W,
+}
+
+impl<W: Write> Command for Inspect<'_, W> {
+    fn run<const P: usize, H: Hasher>(self) -> Result<()> {
+        let hll = HyperLogLog::<P>::from_bytes_with_hasher::<H>(self.bytes)
+            .map_err(|e| format!("{}: {}", self.path, e))?;
+        let repr = match self.bytes[REPR_OFFSET] & 0x7f {
+            0 => "empty",
+            1 => "sparse",
+            2 => "dense",
+            _ => "compressed",
+        };
+        let non_empty = HyperLogLog::<P>::NUM_REGISTERS - hll.num_empty_registers();
+        let out = self.stdout;
+        writeln!(out, "precision:      {}", P)?;
+        writeln!(out, "hasher:         {} ({})", H::ID, hasher_name(H::ID))?;
+        writeln!(out, "representation: {}, {} bytes", repr, self.bytes.len())?;
+        writeln!(
+            out,
+            "fill:           {}/{} registers ({:.2}%)",
+            non_empty,
+            HyperLogLog::<P>::NUM_REGISTERS,
+            non_empty as f64 * 100.0 / HyperLogLog::<P>::NUM_REGISTERS as f64
+        )?;
+        writeln!(out, "estimate:       {}", hll.count())?;
+        writeln!(
+            out,
+            "error:          {:.2}%",
+            HyperLogLog::<P>::error_rate() * 100.0
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("simple_hll_cli_{}_{}", std::process::id(), name))
+    }
+
+    fn run_cli(args: &[&str], stdin: &str) -> Result<String> {
+        let args = args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let mut stdout = Vec::new();
+        run(&args, stdin.as_bytes(), &mut stdout)?;
+        Ok(String::from_utf8(stdout).unwrap())
+    }
+
+    #[test]
+    fn test_cli() {
+        let a = path("a.hll");
+        let b = path("b.hll");
+        let out = path("out.hll");
+        let (a_str, b_str, out_str) = (
+            a.to_str().unwrap(),
+            b.to_str().unwrap(),
+            out.to_str().unwrap(),
+        );
+
+        let lines = (0..1000).map(|i| format!("{}\n", i)).collect::<String>();
+        let mut expected = HyperLogLog::<14>::new();
+        expected.add_from_reader(lines.as_bytes()).unwrap();
+        assert_eq!(
+            run_cli(&["count", "-o", a_str], &lines).unwrap(),
+            format!("{}\n", expected.count())
+        );
+        assert_eq!(fs::read(&a).unwrap(), expected.to_bytes());
+
+        let lines = (500..2000).map(|i| format!("{}\n", i)).collect::<String>();
+        let mut other = HyperLogLog::<12>::new();
+        other.add_from_reader(lines.as_bytes()).unwrap();
+        run_cli(&["count", "-p", "12", "-o", b_str], &lines).unwrap();
+        assert_eq!(fs::read(&b).unwrap(), other.to_bytes());
+
+        // folded into the lowest precision
+        run_cli(&["merge", a_str, b_str, "-o", out_str], "").unwrap();
+        let mut union = expected.fold::<12>().unwrap();
+        union.merge(&other);
+        assert_eq!(fs::read(&out).unwrap(), union.to_bytes());
+
+        let inspect = run_cli(&["inspect", out_str], "").unwrap();
+        assert!(inspect.contains("precision:      12\n"), "{}", inspect);
+        assert!(
+            inspect.contains("hasher:         1 (ahash)\n"),
+            "{}",
+            inspect
+        );
+        assert!(
+            inspect.contains(&format!("estimate:       {}\n", union.count())),
+            "{}",
+            inspect
+        );
+        let non_empty = 4096 - union.num_empty_registers();
+        assert!(
+            inspect.contains(&format!("fill:           {}/4096 registers", non_empty)),
+            "{}",
+            inspect
+        );
+
+        fs::write(&b, b"SHLL").unwrap();
+        let err = run_cli(&["merge", a_str, b_str, "-o", out_str], "").unwrap_err();
+        assert!(err.to_string().starts_with(b_str), "{}", err);
+        assert!(run_cli(&["inspect", b_str], "").is_err());
+
+        for f in [a, b, out] {
+            fs::remove_file(f).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_cli_usage() {
+        assert!(run_cli(&[], "").is_err());
+        assert!(run_cli(&["unknown"], "").is_err());
+        assert!(run_cli(&["count", "-x"], "").is_err());
+        assert!(run_cli(&["count", "-p"], "").is_err());
+        assert!(run_cli(&["count", "-p", "3"], "").is_err());
+        assert!(run_cli(&["merge", "a.hll"], "").is_err());
+        assert!(run_cli(&["inspect"], "").is_err());
+        assert_eq!(run_cli(&["count"], "").unwrap(), "0\n");
+        assert!(run_cli(&["--help"], "").unwrap().starts_with("usage"));
+    }
+}
@@ -0,0 +1,150 @@
+//! Compressed registers: every non-zero register is encoded as one LEB128 varint
+//! `(zeros << 6) | value`, where `zeros` is the number of zero registers before it.
+//! Zero registers after the last token are implicit.
+//!
+//! A non-zero register costs 1 byte if it directly follows the previous one, 2 bytes if
+//! fewer than 256 zero registers are skipped, so it beats the 6-bit packed form once
+//! a fair share of registers is still empty, and the sparse form almost always.
+
+use crate::HllError;
+
+/// Encode the registers, every register must be smaller than 64
+#[cfg(test)]
+pub(crate) fn encode(registers: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(encoded_len(registers));
+    bytes.extend(iter(registers));
+    bytes
+}
+
+/// Iterate over the encoded bytes without allocating
+pub(crate) fn iter(registers: &[u8]) -> impl Iterator<Item = u8> + Clone + '_ {
+    tokens(registers).flat_map(|token| Varint {
+        v: token,
+        done: false,
+    })
+}
+
+/// Number of bytes of the encoded registers
+pub(crate) fn encoded_len(registers: &[u8]) -> usize {
+    tokens(registers)
+        .map(|token| (64 - (token | 1).leading_zeros() as usize).div_ceil(7))
+        .sum()
+}
+
+#[inline]
+fn tokens(registers: &[u8]) -> impl Iterator<Item = u64> + Clone + '_ {
+    let mut zeros = 0_u64;
+    registers.iter().filter_map(move |r| {
+        if *r == 0 {
+            zeros += 1;
+            return None;
+        }
+        let token = (zeros << 6) | (*r & 0x3f) as u64;
+        zeros = 0;
+        Some(token)
+    })
+}
+
+/// Decode `n` registers from `bytes`
+#[cfg(any(test, feature = "serde", feature = "borsh"))]
+pub(crate) fn decode(bytes: &[u8], n: usize) -> Result<Vec<u8>, HllError> {
+    let mut registers = vec![0; n];
+    visit(bytes, n, |index, value| {
+        registers[index] = value;
+        Ok(())
+    })?;
+    Ok(registers)
+}
+
+/// Decode `bytes` and call `f` with the index and value of every non-zero register,
+/// indexes are guaranteed to be smaller than `n`
+pub(crate) fn visit(
+    bytes: &[u8],
+    n: usize,
+    mut f: impl FnMut(usize, u8) -> Result<(), HllError>,
+) -> Result<(), HllError> {
+    let mut index = 0_usize;
+    let mut bytes = bytes;
+    while !bytes.is_empty() {
+        let token = read_varint(&mut bytes)?;
+        index = index.saturating_add((token >> 6) as usize);
+        if index >= n {
+            return Err(HllError::InvalidRegisterIndex { index, len: n });
+        }
+        f(index, (token & 0x3f) as u8)?;
+        index += 1;
+    }
+    Ok(())
+}
+
+/// LEB128 bytes of a varint
+#[derive(Clone)]
+struct Varint {
+    v: u64,
+    done: bool,
+}
+
+impl Iterator for Varint {
+    type Item = u8;
+
+    #[inline]
+    fn next(&mut self) -> Option<u8> {
+        if self.done {
+            None
+        } else if self.v >= 0x80 {
+            let b = self.v as u8 | 0x80;
+            self.v >>= 7;
+            Some(b)
+        } else {
+            self.done = true;
+            Some(self.v as u8)
+        }
+    }
+}
+
+#[inline]
+fn read_varint(bytes: &mut &[u8]) -> Result<u64, HllError> {
+    let mut v = 0_u64;
+    for shift in (0..64).step_by(7) {
+        let (b, rest) = bytes.split_first().ok_or(HllError::UnexpectedEof)?;
+        *bytes = rest;
+        v |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Ok(v);
+        }
+    }
+    // more than 10 bytes can't be a valid u64
+    Err(HllError::UnexpectedEof)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let n = 1 << 14;
+        for step in [1, 2, 3, 100, 300, 20000] {
+            let registers: Vec<u8> = (0..n)
+                .map(|i| if i % step == 0 { (i % 51 + 1) as u8 } else { 0 })
+                .collect();
+            let bytes = encode(&registers);
+            assert_eq!(bytes.len(), encoded_len(&registers));
+            assert_eq!(decode(&bytes, n).unwrap(), registers);
+        }
+
+        assert!(encode(&[0; 16]).is_empty());
+        assert_eq!(encode(&[1, 0, 0, 2, 0]), [1, (2 << 6) | 2, 1]);
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        // index overflow
+        assert_eq!(
+            decode(&[0x81, 0x08], 16),
+            Err(HllError::InvalidRegisterIndex { index: 16, len: 16 })
+        );
+        // truncated varint
+        assert_eq!(decode(&[0x81], 16), Err(HllError::UnexpectedEof));
+    }
+}
@@ -0,0 +1,141 @@
+//! A sketch shared by many threads, split into one shard per core.
+
+use core::hash::Hash;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+use crate::Hasher;
+use crate::HyperLogLog;
+
+/// Shards are padded to their own cache lines, so threads writing to neighbouring shards
+/// don't invalidate each other's lines
+#[derive(Debug, Default)]
+#[repr(align(128))]
+struct Shard<const P: usize>(Mutex<HyperLogLog<P>>);
+
+impl<const P: usize> Shard<P> {
+    fn lock(&self) -> MutexGuard<'_, HyperLogLog<P>> {
+        // merging can't panic halfway, a poisoned sketch is still consistent
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// [`HyperLogLog`] for write-heavy workloads shared by many threads: each thread writes
+/// to one of a few shards, one per core by default, with plain registers behind an
+/// uncontended lock. Shards are merged on [`Self::count`] and [`Self::snapshot`].
+///
+/// Compared to [`crate::AtomicHyperLogLog`], inserts don't need atomic operations, but
+/// snapshots cost a merge per shard.
+#[derive(Debug)]
+pub struct ConcurrentHll<const P: usize> {
+    shards: Box<[Shard<P>]>,
+}
+
+impl<const P: usize> Default for ConcurrentHll<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Shard of the current thread, threads are spread round robin over the shards
+fn thread_index() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    std::thread_local! {
+        static INDEX: usize = NEXT.fetch_add(1, Ordering::Relaxed);
+    }
+    INDEX.with(|index| *index)
+}
+
+impl<const P: usize> ConcurrentHll<P> {
+    /// One shard per core, as reported by [`std::thread::available_parallelism`]
+    pub fn new() -> Self {
+        Self::with_shards(std::thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+
+    /// `shards` shards, at least one
+    pub fn with_shards(shards: usize) -> Self {
+        Self {
+            shards: (0..shards.max(1)).map(|_| Shard::default()).collect(),
+        }
+    }
+
+    fn shard(&self) -> &Shard<P> {
+        &self.shards[thread_index() % self.shards.len()]
+    }
+
+    #[inline]
+    pub fn add_hash(&self, hash: u64) {
+        self.shard().lock().add_hash(hash);
+    }
+
+    /// Adds many hashes under a single lock of the shard
+    pub fn add_hashes(&self, hashes: &[u64]) {
+        self.shard().lock().add_hashes(hashes);
+    }
+
+    #[inline]
+    pub fn add_object<T: Hash>(&self, obj: &T) {
+        self.add_object_by_hasher::<T, ahash::AHasher>(obj);
+    }
+
+    #[inline]
+    pub fn add_object_by_hasher<T: Hash, H: Hasher>(&self, obj: &T) {
+        self.add_hash(H::hll_hash(obj));
+    }
+
+    /// Merge a sketch into this one
+    pub fn merge(&self, other: &HyperLogLog<P>) {
+        self.shard().lock().merge(other);
+    }
+
+    /// The union of the shards
+    pub fn snapshot(&self) -> HyperLogLog<P> {
+        let mut hll = HyperLogLog::new();
+        for shard in self.shards.iter() {
+            hll.merge(&shard.lock());
+        }
+        hll
+    }
+
+    /// Count the unique elements of a snapshot
+    pub fn count(&self) -> usize {
+        self.snapshot().count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concurrent_hll() {
+        let mut expected = HyperLogLog::<14>::new();
+        for i in 0..100_000_u64 {
+            expected.add_object(&i);
+        }
+
+        for shards in [1, 3, 8] {
+            let hll = ConcurrentHll::<14>::with_shards(shards);
+            std::thread::scope(|s| {
+                for t in 0..4_u64 {
+                    let hll = &hll;
+                    s.spawn(move || {
+                        for i in (t * 25_000)..((t + 1) * 25_000) {
+                            hll.add_object(&i);
+                        }
+                    });
+                }
+            });
+            assert_eq!(hll.snapshot(), expected);
+            assert_eq!(hll.count(), expected.count());
+        }
+
+        let hll = ConcurrentHll::<14>::new();
+        hll.merge(&expected);
+        assert_eq!(hll.snapshot(), expected);
+    }
+}
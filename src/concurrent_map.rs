@@ -0,0 +1,516 @@
+//! Sketches per key shared by many threads, e.g. distinct users per tenant in a server.
+
+use core::borrow::Borrow;
+use core::hash::BuildHasher;
+use core::hash::Hash;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::Hasher;
+use crate::HyperLogLog;
+
+#[derive(Debug)]
+struct Entry<const P: usize> {
+    hll: HyperLogLog<P>,
+    /// Last add or merge, only updated with a TTL, and reset when one is set
+    touched: Instant,
+}
+
+impl<const P: usize> Entry<P> {
+    fn new(hll: HyperLogLog<P>) -> Self {
+        Self {
+            hll,
+            touched: Instant::now(),
+        }
+    }
+}
+
+/// Keys are spread over shards, padded to their own cache lines like the shards of
+/// [`crate::ConcurrentHll`]
+#[derive(Debug)]
+#[repr(align(128))]
+struct Shard<K, const P: usize>(Mutex<HashMap<K, Entry<P>>>);
+
+impl<K, const P: usize> Shard<K, P> {
+    fn lock(&self) -> MutexGuard<'_, HashMap<K, Entry<P>>> {
+        // adding can't panic halfway, a poisoned map is still consistent
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// A [`HyperLogLog`] per key, any number of threads can add to any key concurrently.
+/// Keys are spread over a few shards, one per core by default, each a map behind its own
+/// lock, so threads adding to keys of different shards don't contend.
+///
+/// Keys are kept until removed, unless a TTL is set with [`Self::set_ttl`], then
+/// [`Self::gc`] or a thread started by [`Self::spawn_gc`] removes the keys idle for longer.
+#[derive(Debug)]
+pub struct ConcurrentHllMap<K, const P: usize> {
+    shards: Box<[Shard<K, P>]>,
+    hasher: RandomState,
+    ttl: Option<Duration>,
+}
+
+impl<K: Hash + Eq, const P: usize> Default for ConcurrentHllMap<K, P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Hash + Eq, const P: usize> ConcurrentHllMap<K, P> {
+    /// One shard per core, as reported by [`std::thread::available_parallelism`]
+    pub fn new() -> Self {
+        Self::with_shards(std::thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+
+    /// `shards` shards, at least one
+    pub fn with_shards(shards: usize) -> Self {
+        Self {
+            shards: (0..shards.max(1))
+                .map(|_| Shard(Mutex::new(HashMap::new())))
+                .collect(),
+            hasher: RandomState::new(),
+            ttl: None,
+        }
+    }
+
+    /// Remove the keys without add or merge for `ttl` on [`Self::gc`], never if `None`.
+    /// The keys are idle from the first TTL set, not from their last add or merge before.
+    pub fn set_ttl(&mut self, ttl: Option<Duration>) {
+        if self.ttl.is_none() && ttl.is_some() {
+            // adds don't keep track of the time without a TTL
+            let now = Instant::now();
+            for shard in self.shards.iter_mut() {
+                let shard = shard
+                    .0
+                    .get_mut()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                for entry in shard.values_mut() {
+                    entry.touched = now;
+                }
+            }
+        }
+        self.ttl = ttl;
+    }
+
+    fn shard<Q: Hash + ?Sized>(&self, key: &Q) -> &Shard<K, P> {
+        let hash = self.hasher.hash_one(key);
+        &self.shards[(hash % self.shards.len() as u64) as usize]
+    }
+
+    /// Runs `f` on the sketch of the key, created empty on first use
+    fn with_sketch<Q>(&self, key: &Q, f: impl FnOnce(&mut HyperLogLog<P>))
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let mut shard = self.shard(key).lock();
+        // only allocate the owned key the first time it's seen
+        match shard.get_mut(key) {
+            Some(entry) => {
+                if self.ttl.is_some() {
+                    entry.touched = Instant::now();
+                }
+                f(&mut entry.hll)
+            }
+            None => {
+                let entry = shard
+                    .entry(key.to_owned())
+                    .or_insert_with(|| Entry::new(HyperLogLog::new()));
+                f(&mut entry.hll)
+            }
+        }
+    }
+
+    #[inline]
+    pub fn add_hash<Q>(&self, key: &Q, hash: u64)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.with_sketch(key, |hll| hll.add_hash(hash));
+    }
+
+    #[inline]
+    pub fn add<Q, T: Hash>(&self, key: &Q, obj: &T)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.add_by_hasher::<Q, T, ahash::AHasher>(key, obj);
+    }
+
+    #[inline]
+    pub fn add_by_hasher<Q, T: Hash, H: Hasher>(&self, key: &Q, obj: &T)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.add_hash(key, H::hll_hash(obj));
+    }
+
+    /// Merge a sketch into the one of the key
+    pub fn merge<Q>(&self, key: &Q, other: &HyperLogLog<P>)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.with_sketch(key, |hll| hll.merge(other));
+    }
+
+    /// A copy of the sketch of the key
+    pub fn get<Q>(&self, key: &Q) -> Option<HyperLogLog<P>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key)
+            .lock()
+            .get(key)
+            .map(|entry| entry.hll.clone())
+    }
+
+    /// Count the unique elements added to the key
+    pub fn count<Q>(&self, key: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key)
+            .lock()
+            .get(key)
+            .map(|entry| entry.hll.count())
+    }
+
+    /// Removes the key and returns its sketch
+    pub fn remove<Q>(&self, key: &Q) -> Option<HyperLogLog<P>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.shard(key).lock().remove(key).map(|entry| entry.hll)
+    }
+
+    /// Number of keys
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.lock().is_empty())
+    }
+
+    /// A copy of every sketch. Every shard is locked while copying, so the snapshot is
+    /// consistent: it holds either all or none of the effects of each add or merge.
+    pub fn snapshot(&self) -> HashMap<K, HyperLogLog<P>>
+    where
+        K: Clone,
+    {
+        let shards: Vec<_> = self.shards.iter().map(Shard::lock).collect();
+        shards
+            .iter()
+            .flat_map(|shard| shard.iter())
+            .map(|(key, entry)| (key.clone(), entry.hll.clone()))
+            .collect()
+    }
+
+    /// Merge every sketch of `other` into the one of its key
+    pub fn merge_map<'a, I>(&self, other: I)
+    where
+        K: Clone + 'a,
+        I: IntoIterator<Item = (&'a K, &'a HyperLogLog<P>)>,
+    {
+        for (key, hll) in other {
+            let mut shard = self.shard(key).lock();
+            match shard.get_mut(key) {
+                Some(entry) => {
+                    if self.ttl.is_some() {
+                        entry.touched = Instant::now();
+                    }
+                    entry.hll.merge(hll)
+                }
+                None => {
+                    shard.insert(key.clone(), Entry::new(hll.clone()));
+                }
+            }
+        }
+    }
+
+    /// The sketches, without copying them
+    pub fn into_inner(self) -> HashMap<K, HyperLogLog<P>> {
+        self.shards
+            .into_vec()
+            .into_iter()
+            .flat_map(|shard| {
+                shard
+                    .0
+                    .into_inner()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+            })
+            .map(|(key, entry)| (key, entry.hll))
+            .collect()
+    }
+
+    /// Remove the keys without add or merge for the TTL, and return their sketches to be
+    /// archived, e.g. in a database. Shards are locked one at a time.
+    pub fn gc(&self) -> Vec<(K, HyperLogLog<P>)> {
+        self.gc_at(Instant::now())
+    }
+
+    fn gc_at(&self, now: Instant) -> Vec<(K, HyperLogLog<P>)> {
+        let Some(ttl) = self.ttl else {
+            return vec![];
+        };
+        let expired = |entry: &Entry<P>| now.saturating_duration_since(entry.touched) >= ttl;
+        let mut removed = vec![];
+        for shard in self.shards.iter() {
+            let mut shard = shard.lock();
+            // most collections expire nothing, don't rebuild the map then
+            if shard.values().any(expired) {
+                let (gone, kept): (HashMap<_, _>, _) =
+                    shard.drain().partition(|(_, entry)| expired(entry));
+                *shard = kept;
+                removed.extend(gone.into_iter().map(|(key, entry)| (key, entry.hll)));
+            }
+        }
+        removed
+    }
+
+    /// Run [`Self::gc`] every `interval` on a thread, passing the expired keys to
+    /// `archive`. The thread exits once the map is dropped.
+    pub fn spawn_gc<F>(map: &Arc<Self>, interval: Duration, mut archive: F) -> JoinHandle<()>
+    where
+        K: Send + Sync + 'static,
+        F: FnMut(K, HyperLogLog<P>) + Send + 'static,
+    {
+        let map = Arc::downgrade(map);
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            let Some(map) = map.upgrade() else {
+                return;
+            };
+            for (key, hll) in map.gc() {
+                archive(key, hll);
+            }
+        })
+    }
+
+    /// A map of the sketches on `new`, the sketches of a duplicate key are merged
+    #[cfg(any(feature = "serde", feature = "borsh"))]
+    fn from_sketches(sketches: impl IntoIterator<Item = (K, HyperLogLog<P>)>) -> Self {
+        let map = Self::new();
+        for (key, hll) in sketches {
+            let mut shard = map.shard(&key).lock();
+            match shard.get_mut(&key) {
+                Some(entry) => entry.hll.merge(&hll),
+                None => {
+                    shard.insert(key, Entry::new(hll));
+                }
+            }
+        }
+        map
+    }
+}
+
+impl<K, const P: usize> ConcurrentHllMap<K, P> {
+    /// Every shard, locked in order for a consistent view
+    #[cfg(any(feature = "serde", feature = "borsh"))]
+    fn lock_all(&self) -> Vec<MutexGuard<'_, HashMap<K, Entry<P>>>> {
+        self.shards.iter().map(|shard| shard.lock()).collect()
+    }
+}
+
+/// Serialized as a map of the sketches by key, each one in the sparse-aware form of
+/// [`HyperLogLog`]. Every shard is locked while serializing, the TTL and the idle times
+/// of the keys aren't serialized.
+#[cfg(feature = "serde")]
+impl<K: serde::Serialize, const P: usize> serde::Serialize for ConcurrentHllMap<K, P> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let shards = self.lock_all();
+        let mut map = serializer.serialize_map(Some(shards.iter().map(|s| s.len()).sum()))?;
+        for (key, entry) in shards.iter().flat_map(|shard| shard.iter()) {
+            map.serialize_entry(key, &entry.hll)?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, K, const P: usize> serde::Deserialize<'de> for ConcurrentHllMap<K, P>
+where
+    K: serde::Deserialize<'de> + Hash + Eq,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let sketches = HashMap::<K, HyperLogLog<P>>::deserialize(deserializer)?;
+        Ok(Self::from_sketches(sketches))
+    }
+}
+
+/// Serialized as a `Vec<(K, HyperLogLog<P>)>` in no particular order, like serde
+#[cfg(feature = "borsh")]
+impl<K: borsh::BorshSerialize, const P: usize> borsh::BorshSerialize for ConcurrentHllMap<K, P> {
+    fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let shards = self.lock_all();
+        let len: usize = shards.iter().map(|shard| shard.len()).sum();
+        (len as u32).serialize(writer)?;
+        for (key, entry) in shards.iter().flat_map(|shard| shard.iter()) {
+            key.serialize(writer)?;
+            entry.hll.serialize(writer)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl<K, const P: usize> borsh::BorshDeserialize for ConcurrentHllMap<K, P>
+where
+    K: borsh::BorshDeserialize + Hash + Eq,
+{
+    fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        let sketches = Vec::<(K, HyperLogLog<P>)>::deserialize_reader(reader)?;
+        Ok(Self::from_sketches(sketches))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concurrent_map() {
+        let tenants = ["a", "b", "c", "d", "e"];
+        let mut expected = HashMap::new();
+        for (t, tenant) in tenants.iter().enumerate() {
+            let hll: &mut HyperLogLog<14> = expected.entry(tenant.to_string()).or_default();
+            for i in 0..(t as u64 + 1) * 1000 {
+                hll.add_object(&i);
+            }
+        }
+
+        for shards in [1, 3] {
+            let map = ConcurrentHllMap::<String, 14>::with_shards(shards);
+            std::thread::scope(|s| {
+                for thread in 0..4_u64 {
+                    let map = &map;
+                    s.spawn(move || {
+                        for (t, tenant) in tenants.iter().enumerate() {
+                            for i in (0..(t as u64 + 1) * 1000).filter(|i| i % 4 == thread) {
+                                map.add(*tenant, &i);
+                            }
+                        }
+                    });
+                }
+            });
+            assert_eq!(map.len(), tenants.len());
+            assert_eq!(map.snapshot(), expected);
+            assert_eq!(map.count("c"), Some(expected["c"].count()));
+            assert_eq!(map.count("z"), None);
+
+            let other = ConcurrentHllMap::<String, 14>::new();
+            other.merge_map(&expected);
+            other.merge("a", &expected["a"]);
+            assert_eq!(other.get("a").as_ref(), Some(&expected["a"]));
+            assert_eq!(other.remove("e").as_ref(), Some(&expected["e"]));
+            assert_eq!(other.len(), tenants.len() - 1);
+            assert_eq!(map.into_inner(), expected);
+        }
+    }
+
+    #[test]
+    fn test_concurrent_map_ttl() {
+        let mut map = ConcurrentHllMap::<String, 14>::with_shards(2);
+        map.add("a", &1);
+        map.add("b", &2);
+        assert!(map
+            .gc_at(Instant::now() + Duration::from_secs(3600))
+            .is_empty());
+
+        let added = Instant::now();
+        std::thread::sleep(Duration::from_millis(1));
+        map.set_ttl(Some(Duration::from_secs(60)));
+        // idle from the TTL on, not from the last add
+        assert!(map.gc_at(added + Duration::from_secs(60)).is_empty());
+
+        let idle = Instant::now();
+        std::thread::sleep(Duration::from_millis(1));
+        map.add("a", &3);
+        let mut expired = map.gc_at(idle + Duration::from_secs(60));
+        assert_eq!(expired.len(), 1);
+        let (key, hll) = expired.pop().unwrap();
+        assert_eq!(key, "b");
+        assert_eq!(hll.count(), 1);
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.gc_at(Instant::now() + Duration::from_secs(60)).len(), 1);
+        assert!(map.is_empty());
+
+        // the thread stops once the map is dropped
+        map.set_ttl(Some(Duration::ZERO));
+        let map = Arc::new(map);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let gc = ConcurrentHllMap::spawn_gc(&map, Duration::from_millis(1), move |key, _| {
+            sender.send(key).unwrap();
+        });
+        map.add("c", &4);
+        assert_eq!(receiver.recv().unwrap(), "c");
+        drop(map);
+        gc.join().unwrap();
+    }
+
+    #[cfg(any(feature = "serde", feature = "borsh"))]
+    fn sketches() -> ConcurrentHllMap<String, 14> {
+        let map = ConcurrentHllMap::with_shards(3);
+        for i in 0..10_000 {
+            map.add(&format!("tenant{}", i % 10), &i);
+        }
+        map.add("single", &1);
+        map
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_concurrent_map_serde() {
+        let map = sketches();
+        let json = serde_json::to_string(&map).unwrap();
+        // the single item sketch keeps its sparse form
+        assert!(json.contains(r#""single":{"Precision":[14,{"Sparse":"#));
+        let read: ConcurrentHllMap<String, 14> = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.into_inner(), map.snapshot());
+
+        let bytes = bincode::serialize(&map).unwrap();
+        let read: ConcurrentHllMap<String, 14> = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(read.into_inner(), map.snapshot());
+    }
+
+    #[cfg(feature = "borsh")]
+    #[test]
+    fn test_concurrent_map_borsh() {
+        let map = sketches();
+        let bytes = borsh::to_vec(&map).unwrap();
+        let read: ConcurrentHllMap<String, 14> = borsh::from_slice(&bytes).unwrap();
+        assert_eq!(read.into_inner(), map.snapshot());
+
+        // the sketches of a duplicate key are merged
+        let mut hll = HyperLogLog::<14>::new();
+        hll.add_object(&1);
+        let mut other = HyperLogLog::<14>::new();
+        other.add_object(&2);
+        let pairs = vec![
+            ("a".to_string(), hll.clone()),
+            ("a".to_string(), other.clone()),
+        ];
+        let read: ConcurrentHllMap<String, 14> =
+            borsh::from_slice(&borsh::to_vec(&pairs).unwrap()).unwrap();
+        hll.merge(&other);
+        assert_eq!(read.get("a"), Some(hll));
+    }
+}
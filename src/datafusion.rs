@@ -0,0 +1,249 @@
+//! `approx_distinct_hll`, a DataFusion aggregate function backed by [`HyperLogLog`].
+//!
+//! Unlike DataFusion's `approx_distinct`, values are hashed like [`HyperLogLog::add_object`]
+//! does in Rust (strings like `&str`, binaries like `&[u8]`, integers like their native
+//! type) and the partial state is the binary format of [`HyperLogLog::to_bytes`], so
+//! states can be exchanged with the other Rust services using this crate.
+//!
+//! ```ignore
+//! ctx.register_udaf(simple_hll::datafusion::approx_distinct_hll_udaf());
+//! ctx.sql("SELECT approx_distinct_hll(user_id) FROM events").await?;
+//! ```
+
+use std::sync::Arc;
+
+use datafusion_common::arrow::array::Array;
+use datafusion_common::arrow::array::ArrayRef;
+use datafusion_common::arrow::array::AsArray;
+use datafusion_common::arrow::datatypes::*;
+use datafusion_common::exec_err;
+use datafusion_common::not_impl_err;
+use datafusion_common::Result;
+use datafusion_common::ScalarValue;
+use datafusion_expr::function::AccumulatorArgs;
+use datafusion_expr::function::StateFieldsArgs;
+use datafusion_expr::utils::format_state_name;
+use datafusion_expr::Accumulator;
+use datafusion_expr::AggregateUDF;
+use datafusion_expr::AggregateUDFImpl;
+use datafusion_expr::Signature;
+use datafusion_expr::Volatility;
+
+use crate::HyperLogLog;
+
+/// The `approx_distinct_hll` aggregate function, register it with
+/// `SessionContext::register_udaf`
+pub fn approx_distinct_hll_udaf() -> AggregateUDF {
+    AggregateUDF::new_from_impl(ApproxDistinctHll::new())
+}
+
+/// `approx_distinct_hll(expr)`, the estimated number of distinct non-null values of `expr`
+/// as a `UInt64`, computed with a [`HyperLogLog`] of precision `14`
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct ApproxDistinctHll {
+    signature: Signature,
+}
+
+impl ApproxDistinctHll {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::any(1, Volatility::Immutable),
+        }
+    }
+}
+
+impl Default for ApproxDistinctHll {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AggregateUDFImpl for ApproxDistinctHll {
+    fn name(&self) -> &str {
+        "approx_distinct_hll"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        match arg_types {
+            [data_type] if is_supported(data_type) => Ok(DataType::UInt64),
+            [data_type] => not_impl_err!("approx_distinct_hll of {}", data_type),
+            _ => exec_err!("approx_distinct_hll takes one argument"),
+        }
+    }
+
+    fn state_fields(&self, args: StateFieldsArgs) -> Result<Vec<FieldRef>> {
+        Ok(vec![Arc::new(Field::new(
+            format_state_name(args.name, "hll"),
+            DataType::Binary,
+            false,
+        ))])
+    }
+
+    fn accumulator(&self, _args: AccumulatorArgs) -> Result<Box<dyn Accumulator>> {
+        Ok(Box::new(HllAccumulator::default()))
+    }
+
+    fn default_value(&self, _data_type: &DataType) -> Result<ScalarValue> {
+        Ok(ScalarValue::UInt64(Some(0)))
+    }
+}
+
+fn is_supported(data_type: &DataType) -> bool {
+    use DataType::*;
+    matches!(
+        data_type,
+        Int8 | Int16
+            | Int32
+            | Int64
+            | UInt8
+            | UInt16
+            | UInt32
+            | UInt64
+            | Utf8
+            | LargeUtf8
+            | Utf8View
+            | Binary
+            | LargeBinary
+            | BinaryView
+            | Null
+    )
+}
+
+/// The accumulator of `approx_distinct_hll`, its state is the binary format of the sketch
+#[derive(Clone, Debug, Default)]
+pub struct HllAccumulator {
+    hll: HyperLogLog,
+}
+
+impl HllAccumulator {
+    pub fn hll(&self) -> &HyperLogLog {
+        &self.hll
+    }
+}
+
+impl From<HyperLogLog> for HllAccumulator {
+    fn from(hll: HyperLogLog) -> Self {
+        Self { hll }
+    }
+}
+
+impl Accumulator for HllAccumulator {
+    fn update_batch(&mut self, values: &[ArrayRef]) -> Result<()> {
+        let Some(array) = values.first() else {
+            return Ok(());
+        };
+        macro_rules! add {
+            ($iter:expr) => {
+                $iter.flatten().for_each(|v| self.hll.add_object(&v))
+            };
+        }
+        match array.data_type() {
+            DataType::Int8 => add!(array.as_primitive::<Int8Type>().iter()),
+            DataType::Int16 => add!(array.as_primitive::<Int16Type>().iter()),
+            DataType::Int32 => add!(array.as_primitive::<Int32Type>().iter()),
+            DataType::Int64 => add!(array.as_primitive::<Int64Type>().iter()),
+            DataType::UInt8 => add!(array.as_primitive::<UInt8Type>().iter()),
+            DataType::UInt16 => add!(array.as_primitive::<UInt16Type>().iter()),
+            DataType::UInt32 => add!(array.as_primitive::<UInt32Type>().iter()),
+            DataType::UInt64 => add!(array.as_primitive::<UInt64Type>().iter()),
+            DataType::Utf8 => add!(array.as_string::<i32>().iter()),
+            DataType::LargeUtf8 => add!(array.as_string::<i64>().iter()),
+            DataType::Utf8View => add!(array.as_string_view().iter()),
+            DataType::Binary => add!(array.as_binary::<i32>().iter()),
+            DataType::LargeBinary => add!(array.as_binary::<i64>().iter()),
+            DataType::BinaryView => add!(array.as_binary_view().iter()),
+            DataType::Null => {}
+            data_type => return not_impl_err!("approx_distinct_hll of {}", data_type),
+        }
+        Ok(())
+    }
+
+    fn merge_batch(&mut self, states: &[ArrayRef]) -> Result<()> {
+        let Some(array) = states.first() else {
+            return Ok(());
+        };
+        for bytes in array.as_binary::<i32>().iter().flatten() {
+            let other = HyperLogLog::from_bytes(bytes)
+                .map_err(|e| datafusion_common::DataFusionError::External(Box::new(e)))?;
+            self.hll.merge(&other);
+        }
+        Ok(())
+    }
+
+    fn state(&mut self) -> Result<Vec<ScalarValue>> {
+        Ok(vec![ScalarValue::Binary(Some(self.hll.to_bytes()))])
+    }
+
+    fn evaluate(&mut self) -> Result<ScalarValue> {
+        Ok(ScalarValue::UInt64(Some(self.hll.count() as u64)))
+    }
+
+    fn size(&self) -> usize {
+        std::mem::size_of_val(self) + self.hll.registers.capacity()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion_common::arrow::array::BinaryArray;
+    use datafusion_common::arrow::array::Float64Array;
+    use datafusion_common::arrow::array::Int64Array;
+    use datafusion_common::arrow::array::StringArray;
+
+    use super::*;
+
+    #[test]
+    fn test_datafusion_accumulator() {
+        let strings: ArrayRef = Arc::new(StringArray::from_iter(
+            (0..1000).map(|i| (i % 10 != 0).then(|| format!("user-{}", i))),
+        ));
+        let ints: ArrayRef = Arc::new(Int64Array::from_iter_values(0..1000));
+
+        let mut a = HllAccumulator::default();
+        a.update_batch(&[strings]).unwrap();
+        let mut b = HllAccumulator::default();
+        b.update_batch(&[ints]).unwrap();
+
+        let mut expected = HyperLogLog::<14>::new();
+        for i in 0..1000_i64 {
+            if i % 10 != 0 {
+                expected.add_object(&format!("user-{}", i).as_str());
+            }
+            expected.add_object(&i);
+        }
+
+        // partial states are the binary format of the sketch
+        let states = [a.state().unwrap(), b.state().unwrap()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        let states: ArrayRef = ScalarValue::iter_to_array(states).unwrap();
+        let mut merged = HllAccumulator::default();
+        merged.merge_batch(&[states]).unwrap();
+        assert_eq!(merged.hll(), &expected);
+        assert_eq!(
+            merged.evaluate().unwrap(),
+            ScalarValue::UInt64(Some(expected.count() as u64))
+        );
+
+        let invalid: ArrayRef = Arc::new(BinaryArray::from_iter_values([b"SHLL"]));
+        assert!(merged.merge_batch(&[invalid]).is_err());
+        let floats: ArrayRef = Arc::new(Float64Array::from_iter_values([1.0]));
+        assert!(merged.update_batch(&[floats]).is_err());
+    }
+
+    #[test]
+    fn test_datafusion_udaf() {
+        let udaf = approx_distinct_hll_udaf();
+        assert_eq!(udaf.name(), "approx_distinct_hll");
+        assert_eq!(
+            udaf.return_type(&[DataType::Utf8]).unwrap(),
+            DataType::UInt64
+        );
+        assert!(udaf.return_type(&[DataType::Float64]).is_err());
+    }
+}
@@ -0,0 +1,117 @@
+//! Compatibility with the state of DataFusion's `approx_distinct` accumulator.
+//!
+//! DataFusion uses `P = 14` and the same register layout and hash mapping as
+//! [`HyperLogLog::add_hash`], its state is one binary value:
+//! - dense: the `1 << 14` raw registers.
+//! - sparse: up to 256 distinct `u64` hashes, little-endian, written by the groups
+//!   accumulator for groups which saw few values.
+//! - empty: no bytes.
+//!
+//! Values are hashed with foldhash's quality hasher, seed `0`, see [`DataFusionHasher`].
+
+use core::hash::BuildHasher;
+use core::hash::Hash;
+
+use crate::Hasher;
+use crate::HllError;
+use crate::HyperLogLog;
+
+/// DataFusion's `approx_distinct` registers
+const DATAFUSION_P: usize = 14;
+
+/// Largest number of hashes of a sparse state
+const SPARSE_LIMIT: usize = 256;
+
+/// Same hash function as DataFusion's `HLL_RANDOM_STATE`.
+/// DataFusion hashes floats as the bits of their integer representation, with `-0.0`
+/// normalized to `0.0`, hash `f64::to_bits` to get the same registers.
+pub struct DataFusionHasher;
+
+impl Hasher for DataFusionHasher {
+    const ID: u8 = 2;
+
+    fn hll_hash<T: Hash>(x: T) -> u64 {
+        foldhash::quality::FixedState::with_seed(0).hash_one(x)
+    }
+}
+
+impl HyperLogLog<DATAFUSION_P> {
+    /// Load a partial state of DataFusion's `approx_distinct`, dense, sparse or empty
+    pub fn from_datafusion_state(bytes: &[u8]) -> Result<Self, HllError> {
+        if bytes.len() == Self::NUM_REGISTERS {
+            return Self::try_from(bytes);
+        }
+        if !bytes.len().is_multiple_of(8) || bytes.len() > SPARSE_LIMIT * 8 {
+            return Err(HllError::InvalidRegisterLength {
+                expected: Self::NUM_REGISTERS,
+                actual: bytes.len(),
+            });
+        }
+        let mut hll = Self::new();
+        for chunk in bytes.chunks_exact(8) {
+            hll.add_hash(u64::from_le_bytes(chunk.try_into().unwrap()));
+        }
+        Ok(hll)
+    }
+
+    /// Serialize into a dense partial state of DataFusion's `approx_distinct`
+    pub fn to_datafusion_state(&self) -> Vec<u8> {
+        self.registers.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_datafusion_state() {
+        let mut hll = HyperLogLog::<14>::new();
+        for i in 0..1000_i64 {
+            hll.add_object_by_hasher::<_, DataFusionHasher>(&i);
+        }
+        let state = hll.to_datafusion_state();
+        assert_eq!(state.len(), 16384);
+        assert_eq!(
+            HyperLogLog::<14>::from_datafusion_state(&state).unwrap(),
+            hll
+        );
+
+        // sparse state of a group which saw 3 values
+        let hashes: Vec<u64> = (0..3_i64).map(DataFusionHasher::hll_hash).collect();
+        let mut expected = HyperLogLog::<14>::new();
+        let mut state = vec![];
+        for hash in hashes {
+            expected.add_hash(hash);
+            state.extend_from_slice(&hash.to_le_bytes());
+        }
+        assert_eq!(
+            HyperLogLog::<14>::from_datafusion_state(&state).unwrap(),
+            expected
+        );
+        assert_eq!(
+            HyperLogLog::<14>::from_datafusion_state(&[]).unwrap(),
+            HyperLogLog::<14>::new()
+        );
+
+        assert_eq!(
+            HyperLogLog::<14>::from_datafusion_state(&[0; 9]),
+            Err(HllError::InvalidRegisterLength {
+                expected: 16384,
+                actual: 9
+            })
+        );
+    }
+
+    #[test]
+    fn test_datafusion_hasher() {
+        // HLL_RANDOM_STATE.hash_one(value)
+        let state = foldhash::quality::FixedState::with_seed(0);
+        assert_eq!(DataFusionHasher::hll_hash(42_i64), state.hash_one(42_i64));
+        assert_eq!(DataFusionHasher::hll_hash("foo"), state.hash_one("foo"));
+        assert_ne!(
+            DataFusionHasher::hll_hash(42_i64),
+            ahash::AHasher::hll_hash(42_i64)
+        );
+    }
+}
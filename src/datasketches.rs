@@ -0,0 +1,413 @@
+//! Apache DataSketches HLL sketch images, as written by the Java, C++ and Rust libraries.
+//!
+//! | offset | size | field                                                         |
+//! |--------|------|---------------------------------------------------------------|
+//! | 0      | 1    | preamble ints: 2 list, 3 set, 10 HLL                          |
+//! | 1      | 1    | serialization version, `1`                                    |
+//! | 2      | 1    | family id, `7`                                                |
+//! | 3      | 1    | `lg_k`, same as `P`                                           |
+//! | 4      | 1    | `lg_arr`, size of the coupon array in list and set modes      |
+//! | 5      | 1    | flags: 4 empty, 8 compact, 16 out of order                    |
+//! | 6      | 1    | coupon count in list mode, `cur_min` in HLL mode              |
+//! | 7      | 1    | mode: low 2 bits 0 list, 1 set, 2 HLL, bits 2-3 0 HLL_4, 1 HLL_6, 2 HLL_8 |
+//!
+//! List and set modes store `u32` coupons `(value << 26) | slot`, register `slot & (k - 1)`
+//! holds `value`. The set mode has a `u32` coupon count at offset 8, coupons start at 12.
+//! Non-compact images store the whole coupon array, with `0` for the empty slots.
+//!
+//! The HLL mode has the `f64` HIP accumulator, `kxq0` and `kxq1` at offset 8, 16 and 24,
+//! the `u32` number of registers at `cur_min` and the `u32` number of auxiliary exceptions
+//! at offset 32 and 36, then the registers at offset 40: one byte per register for HLL_8,
+//! 6-bit packed registers (see [`packed`]) and one padding byte for HLL_6, and for HLL_4
+//! two registers per byte (the even register in the low nibble) relative to `cur_min`.
+//! An HLL_4 nibble of `15` is an exception, the register is in the auxiliary table after
+//! the nibbles, `u32` pairs `(value << 26) | slot` like the coupons: the number of
+//! exceptions in compact images, `2^lg_arr` slots with `0` for the empty ones otherwise.
+//!
+//! DataSketches hashes elements with MurmurHash3_x64_128 (seed `9001`), sketches built with
+//! another hasher can be counted but must not be merged with DataSketches sketches.
+
+use crate::format::SliceReader;
+use crate::packed;
+use crate::HllError;
+use crate::HyperLogLog;
+
+const SERIAL_VERSION: u8 = 1;
+const FAMILY_HLL: u8 = 7;
+
+const EMPTY_FLAG: u8 = 4;
+const COMPACT_FLAG: u8 = 8;
+const OUT_OF_ORDER_FLAG: u8 = 16;
+
+const LIST_PREINTS: u8 = 2;
+const SET_PREINTS: u8 = 3;
+const HLL_PREINTS: u8 = 10;
+
+const MODE_LIST: u8 = 0;
+const MODE_SET: u8 = 1;
+const MODE_HLL: u8 = 2;
+
+const TGT_HLL4: u8 = 0;
+const TGT_HLL6: u8 = 1;
+const TGT_HLL8: u8 = 2;
+
+const KEY_BITS: u32 = 26;
+const AUX_TOKEN: u8 = 15;
+
+impl<const P: usize> HyperLogLog<P> {
+    /// Load a DataSketches HLL sketch image, `lg_k` must match `P`.
+    /// List, set and HLL modes of HLL_4, HLL_6 and HLL_8 images are supported.
+    pub fn from_datasketches(bytes: &[u8]) -> Result<Self, HllError> {
+        let mut reader = SliceReader::new(bytes);
+        let preamble_ints = reader.read_u8()?;
+        if reader.read_u8()? != SERIAL_VERSION || reader.read_u8()? != FAMILY_HLL {
+            return Err(HllError::InvalidMagic);
+        }
+        let lg_k = reader.read_u8()? as usize;
+        if lg_k != P {
+            return Err(HllError::PrecisionMismatch {
+                expected: P,
+                actual: lg_k,
+            });
+        }
+        let lg_arr = reader.read_u8()? as u32;
+        if lg_arr > KEY_BITS {
+            return Err(HllError::Malformed(format!(
+                "datasketches: 2^{} coupons",
+                lg_arr
+            )));
+        }
+        let flags = reader.read_u8()?;
+        let list_count = reader.read_u8()?;
+        let mode = reader.read_u8()?;
+
+        let m = Self::NUM_REGISTERS;
+        let mut registers = vec![0; m];
+        let compact = flags & COMPACT_FLAG != 0;
+        let mut add_coupons = |reader: &mut SliceReader<'_>, n: usize| {
+            for _ in 0..n {
+                let coupon = reader.read_u32()?;
+                if coupon != 0 {
+                    let index = coupon as usize & (m - 1);
+                    registers[index] = registers[index].max((coupon >> KEY_BITS) as u8);
+                }
+            }
+            Ok(())
+        };
+
+        match (mode & 0x3, preamble_ints) {
+            _ if flags & EMPTY_FLAG != 0 => {}
+            (MODE_LIST, LIST_PREINTS) => {
+                let n = if compact {
+                    list_count as usize
+                } else {
+                    1 << lg_arr
+                };
+                add_coupons(&mut reader, n)?;
+            }
+            (MODE_SET, SET_PREINTS) => {
+                let count = reader.read_u32()? as usize;
+                let n = if compact { count } else { 1 << lg_arr };
+                add_coupons(&mut reader, n)?;
+            }
+            (MODE_HLL, HLL_PREINTS) => {
+                // HIP accumulator, kxq0, kxq1, number of registers at cur_min
+                reader.read_slice(3 * 8 + 4)?;
+                let aux_count = reader.read_u32()?;
+                match mode >> 2 & 0x3 {
+                    TGT_HLL8 if aux_count == 0 => registers.copy_from_slice(reader.read_slice(m)?),
+                    TGT_HLL6 if aux_count == 0 => {
+                        registers = packed::unpack(reader.read_slice(packed::packed_len(m))?, m);
+                        reader.read_u8()?;
+                    }
+                    TGT_HLL4 => {
+                        let n = if compact {
+                            aux_count as usize
+                        } else {
+                            1 << lg_arr
+                        };
+                        registers = read_hll4(&mut reader, m, list_count, n)?;
+                    }
+                    tgt => return Err(HllError::UnsupportedRepresentation(tgt)),
+                }
+            }
+            _ => return Err(HllError::UnsupportedRepresentation(mode)),
+        }
+        reader.finish()?;
+        Self::try_from(registers)
+    }
+
+    /// Serialize into a DataSketches HLL_8 image, one byte per register
+    pub fn to_datasketches_hll8(&self) -> Vec<u8> {
+        self.to_datasketches(TGT_HLL8)
+    }
+
+    /// Serialize into a DataSketches HLL_6 image, 6-bit packed registers
+    pub fn to_datasketches_hll6(&self) -> Vec<u8> {
+        self.to_datasketches(TGT_HLL6)
+    }
+
+    fn to_datasketches(&self, tgt: u8) -> Vec<u8> {
+        if self.num_empty_registers() == Self::NUM_REGISTERS {
+            // an empty compact list, like the DataSketches libraries write it
+            return vec![
+                LIST_PREINTS,
+                SERIAL_VERSION,
+                FAMILY_HLL,
+                P as u8,
+                3,
+                EMPTY_FLAG | COMPACT_FLAG,
+                0,
+                MODE_LIST | tgt << 2,
+            ];
+        }
+
+        let (mut kxq0, mut kxq1) = (0.0, 0.0);
+        for r in &self.registers {
+            let inv_pow2 = 1.0 / (1_u64 << *r) as f64;
+            if *r < 32 {
+                kxq0 += inv_pow2;
+            } else {
+                kxq1 += inv_pow2;
+            }
+        }
+
+        let mut bytes = Vec::with_capacity(40 + self.registers.len());
+        // the HIP accumulator is unknown, so it's marked out of order and DataSketches
+        // falls back to the composite estimator
+        bytes.extend_from_slice(&[
+            HLL_PREINTS,
+            SERIAL_VERSION,
+            FAMILY_HLL,
+            P as u8,
+            0,
+            OUT_OF_ORDER_FLAG,
+            0,
+            MODE_HLL | tgt << 2,
+        ]);
+        bytes.extend_from_slice(&0_f64.to_le_bytes());
+        bytes.extend_from_slice(&kxq0.to_le_bytes());
+        bytes.extend_from_slice(&kxq1.to_le_bytes());
+        bytes.extend_from_slice(&(self.num_empty_registers() as u32).to_le_bytes());
+        bytes.extend_from_slice(&0_u32.to_le_bytes());
+        if tgt == TGT_HLL8 {
+            bytes.extend_from_slice(&self.registers);
+        } else {
+            bytes.extend(packed::iter(&self.registers));
+            bytes.push(0);
+        }
+        bytes
+    }
+}
+
+/// Read `m` HLL_4 registers relative to `cur_min`, then their `n` slot exception table
+fn read_hll4(
+    reader: &mut SliceReader<'_>,
+    m: usize,
+    cur_min: u8,
+    n: usize,
+) -> Result<Vec<u8>, HllError> {
+    let nibbles = reader.read_slice(m / 2)?;
+    let nibble = |index: usize| nibbles[index / 2] >> (index % 2 * 4) & 0xf;
+
+    let mut registers = vec![0; m];
+    let mut exceptions = 0;
+    for (index, register) in registers.iter_mut().enumerate() {
+        match nibble(index) {
+            AUX_TOKEN => exceptions += 1,
+            v => *register = cur_min.saturating_add(v),
+        }
+    }
+    for _ in 0..n {
+        let pair = reader.read_u32()?;
+        if pair == 0 {
+            continue;
+        }
+        let index = pair as usize & (m - 1);
+        let value = (pair >> KEY_BITS) as u8;
+        // an exception is at least cur_min + 15, and only stored once
+        if nibble(index) != AUX_TOKEN
+            || registers[index] != 0
+            || value < cur_min.saturating_add(AUX_TOKEN)
+        {
+            return Err(HllError::Malformed(format!(
+                "datasketches: invalid HLL_4 exception {} for register {}",
+                value, index
+            )));
+        }
+        registers[index] = value;
+        exceptions -= 1;
+    }
+    if exceptions != 0 {
+        return Err(HllError::Malformed(format!(
+            "datasketches: {} HLL_4 exceptions are missing",
+            exceptions
+        )));
+    }
+    Ok(registers)
+}
+
+#[cfg(test)]
+mod tests {
+    use datasketches::hll::HllSketch;
+    use datasketches::hll::HllType;
+
+    use super::*;
+
+    #[test]
+    fn test_datasketches_hll() {
+        let mut hll = HyperLogLog::<12>::new();
+        for i in 0..100_000 {
+            hll.add_object(&i);
+        }
+
+        for (bytes, len) in [
+            (hll.to_datasketches_hll8(), 40 + 4096),
+            (hll.to_datasketches_hll6(), 40 + 3072 + 1),
+        ] {
+            assert_eq!(bytes.len(), len);
+            assert_eq!(HyperLogLog::<12>::from_datasketches(&bytes).unwrap(), hll);
+
+            let sketch = HllSketch::deserialize(&bytes).unwrap();
+            // written back byte for byte, including kxq0 and kxq1
+            assert_eq!(sketch.serialize(), bytes);
+            let error = (sketch.estimate() - hll.count() as f64).abs() / hll.count() as f64;
+            assert!(error < 0.01, "{} vs {}", sketch.estimate(), hll.count());
+        }
+
+        let empty = HyperLogLog::<12>::new();
+        let bytes = empty.to_datasketches_hll8();
+        assert_eq!(
+            bytes,
+            HllSketch::new(12, HllType::Hll8).unwrap().serialize()
+        );
+        assert_eq!(HyperLogLog::<12>::from_datasketches(&bytes).unwrap(), empty);
+    }
+
+    #[test]
+    fn test_from_datasketches() {
+        // list, set and HLL modes of every target type
+        for hll_type in [HllType::Hll4, HllType::Hll6, HllType::Hll8] {
+            for n in [0, 5, 100, 1000, 100_000] {
+                let mut sketch = HllSketch::new(12, hll_type).unwrap();
+                for i in 0..n {
+                    sketch.update(i);
+                }
+                let bytes = sketch.serialize();
+                let hll = HyperLogLog::<12>::from_datasketches(&bytes)
+                    .unwrap_or_else(|e| panic!("{:?} {}: {}", hll_type, n, e));
+
+                let expected = sketch.estimate();
+                let error = (hll.count() as f64 - expected).abs() / expected.max(1.0);
+                assert!(
+                    error < 0.05,
+                    "{:?} {}: {} vs {}",
+                    hll_type,
+                    n,
+                    hll.count(),
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_datasketches_hll4() {
+        // enough items for cur_min to move and for registers in the exception table
+        let mut hll4 = HllSketch::new(8, HllType::Hll4).unwrap();
+        let mut hll8 = HllSketch::new(8, HllType::Hll8).unwrap();
+        for i in 0..1_000_000 {
+            hll4.update(i);
+            hll8.update(i);
+        }
+        let bytes = hll4.serialize();
+        assert_eq!(bytes[7], MODE_HLL | TGT_HLL4 << 2);
+        assert!(bytes[6] > 0);
+        assert!(u32::from_le_bytes(bytes[36..40].try_into().unwrap()) > 0);
+        assert_eq!(
+            HyperLogLog::<8>::from_datasketches(&bytes).unwrap(),
+            HyperLogLog::<8>::from_datasketches(&hll8.serialize()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_hll4_image() {
+        // cur_min 2, register 1 is an exception, register 2 is 2 + 14
+        let mut bytes = vec![HLL_PREINTS, SERIAL_VERSION, FAMILY_HLL, 4, 0];
+        bytes.extend_from_slice(&[COMPACT_FLAG, 2, MODE_HLL | TGT_HLL4 << 2]);
+        bytes.extend_from_slice(&[0; 28]);
+        bytes.extend_from_slice(&1_u32.to_le_bytes());
+        bytes.extend_from_slice(&[0xf0, 0x0e, 0, 0, 0, 0, 0, 0x10]);
+        bytes.extend_from_slice(&(20 << KEY_BITS | 1_u32).to_le_bytes());
+
+        let mut registers = vec![2; 16];
+        registers[1] = 20;
+        registers[2] = 16;
+        registers[15] = 3;
+        assert_eq!(
+            HyperLogLog::<4>::from_datasketches(&bytes).unwrap(),
+            HyperLogLog::<4>::try_from(registers).unwrap()
+        );
+
+        // the exception table of a non-compact image, 2^lg_arr slots
+        let mut updatable = bytes[..48].to_vec();
+        updatable[4] = 2;
+        updatable[5] = 0;
+        updatable.extend_from_slice(&[0; 4]);
+        updatable.extend_from_slice(&bytes[48..]);
+        updatable.extend_from_slice(&[0; 8]);
+        assert_eq!(
+            HyperLogLog::<4>::from_datasketches(&updatable),
+            HyperLogLog::<4>::from_datasketches(&bytes)
+        );
+
+        // a missing, a misplaced and a too small exception
+        let mut missing = bytes[..48].to_vec();
+        missing[36] = 0;
+        let mut misplaced = bytes.clone();
+        misplaced[48] = 2;
+        let mut small = bytes.clone();
+        small[51] = 16 << 2;
+        for invalid in [missing, misplaced, small] {
+            assert!(matches!(
+                HyperLogLog::<4>::from_datasketches(&invalid),
+                Err(HllError::Malformed(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_datasketches_invalid() {
+        let mut hll = HyperLogLog::<12>::new();
+        hll.add_object(&1);
+        let bytes = hll.to_datasketches_hll8();
+
+        assert_eq!(
+            HyperLogLog::<14>::from_datasketches(&bytes),
+            Err(HllError::PrecisionMismatch {
+                expected: 14,
+                actual: 12
+            })
+        );
+
+        let mut invalid = bytes.clone();
+        invalid[2] = 3;
+        assert_eq!(
+            HyperLogLog::<12>::from_datasketches(&invalid),
+            Err(HllError::InvalidMagic)
+        );
+
+        assert_eq!(
+            HyperLogLog::<12>::from_datasketches(&bytes[..bytes.len() - 1]),
+            Err(HllError::UnexpectedEof)
+        );
+
+        let mut invalid = bytes.clone();
+        invalid[4] = 200;
+        invalid[5] &= !COMPACT_FLAG;
+        assert!(matches!(
+            HyperLogLog::<12>::from_datasketches(&invalid),
+            Err(HllError::Malformed(_))
+        ));
+    }
+}
@@ -0,0 +1,143 @@
+//! Diesel codecs, a sketch is a `Binary` column (`BYTEA`, `BLOB`) holding the binary format
+//! of [`HyperLogLog::to_bytes`]. PostgreSQL and MySQL are supported with feature `diesel`,
+//! SQLite needs feature `diesel_sqlite`.
+//!
+//! ```ignore
+//! table! {
+//!     daily (day) {
+//!         day -> Date,
+//!         users -> Binary,
+//!     }
+//! }
+//!
+//! diesel::insert_into(daily::table)
+//!     .values((daily::day.eq(day), daily::users.eq(&hll)))
+//!     .execute(&mut conn)?;
+//! let users: HyperLogLog = daily::table.select(daily::users).first(&mut conn)?;
+//! ```
+
+use std::io::Write;
+
+use ::diesel::backend::Backend;
+use ::diesel::deserialize;
+use ::diesel::deserialize::FromSql;
+use ::diesel::mysql::Mysql;
+use ::diesel::pg::Pg;
+use ::diesel::serialize;
+use ::diesel::serialize::IsNull;
+use ::diesel::serialize::Output;
+use ::diesel::serialize::ToSql;
+use ::diesel::sql_types::Binary;
+
+use crate::HyperLogLog;
+
+impl<const P: usize> ToSql<Binary, Pg> for HyperLogLog<P> {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(&self.to_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl<const P: usize> ToSql<Binary, Mysql> for HyperLogLog<P> {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Mysql>) -> serialize::Result {
+        out.write_all(&self.to_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+#[cfg(feature = "diesel_sqlite")]
+impl<const P: usize> ToSql<Binary, ::diesel::sqlite::Sqlite> for HyperLogLog<P> {
+    fn to_sql<'b>(
+        &'b self,
+        out: &mut Output<'b, '_, ::diesel::sqlite::Sqlite>,
+    ) -> serialize::Result {
+        out.set_value(self.to_bytes());
+        Ok(IsNull::No)
+    }
+}
+
+/// Fails if the column isn't a sketch of precision `P` built by the default hasher
+impl<DB: Backend, const P: usize> FromSql<Binary, DB> for HyperLogLog<P>
+where
+    *const [u8]: FromSql<Binary, DB>,
+{
+    fn from_sql(bytes: DB::RawValue<'_>) -> deserialize::Result<Self> {
+        let bytes = <*const [u8] as FromSql<Binary, DB>>::from_sql(bytes)?;
+        // valid as long as the raw value, like the `Vec<u8>` implementation of diesel
+        let bytes = unsafe { &*bytes };
+        Ok(Self::from_bytes(bytes)?)
+    }
+}
+
+#[cfg(all(test, feature = "diesel_sqlite"))]
+mod tests {
+    use ::diesel::prelude::*;
+    use ::diesel::sql_query;
+    use ::diesel::sql_types::Binary;
+    use ::diesel::SqliteConnection;
+
+    use super::*;
+
+    ::diesel::table! {
+        daily (day) {
+            day -> Integer,
+            users -> Binary,
+        }
+    }
+
+    fn assert_codec<DB: Backend>()
+    where
+        HyperLogLog: ToSql<Binary, DB> + FromSql<Binary, DB>,
+    {
+    }
+
+    #[test]
+    fn test_diesel() {
+        assert_codec::<Pg>();
+        assert_codec::<Mysql>();
+
+        let empty = HyperLogLog::<12>::new();
+        let mut hll = HyperLogLog::<12>::new();
+        for i in 0..1000 {
+            hll.add_object(&i);
+        }
+
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        sql_query("CREATE TABLE daily (day INTEGER PRIMARY KEY, users BLOB NOT NULL)")
+            .execute(&mut conn)
+            .unwrap();
+        ::diesel::insert_into(daily::table)
+            .values(&vec![
+                (daily::day.eq(1), daily::users.eq(&hll)),
+                (daily::day.eq(2), daily::users.eq(&empty)),
+            ])
+            .execute(&mut conn)
+            .unwrap();
+        sql_query("INSERT INTO daily VALUES (3, x'00')")
+            .execute(&mut conn)
+            .unwrap();
+
+        let users: Vec<HyperLogLog<12>> = daily::table
+            .select(daily::users)
+            .filter(daily::day.le(2))
+            .order(daily::day)
+            .load(&mut conn)
+            .unwrap();
+        assert_eq!(users, [hll.clone(), empty]);
+        let bytes: Vec<u8> = daily::table
+            .select(daily::users)
+            .find(1)
+            .first(&mut conn)
+            .unwrap();
+        assert_eq!(bytes, hll.to_bytes());
+
+        // invalid bytes, another precision
+        for day in [3, 1] {
+            let users = daily::table
+                .select(daily::users)
+                .find(day)
+                .first::<HyperLogLog<14>>(&mut conn);
+            assert!(users.is_err());
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! Druid `HLLSketch` columns, from the `druid-datasketches` extension.
+//!
+//! Druid stores the DataSketches HLL sketch image (see [`crate::datasketches`]) as is in the
+//! segments, but sketches are exchanged as base64 strings: pre-built sketches are ingested
+//! from base64 strings, and queries return them the same way.
+
+use crate::encoding;
+use crate::HllError;
+use crate::HyperLogLog;
+
+impl<const P: usize> HyperLogLog<P> {
+    /// Serialize into a base64 HLL_8 DataSketches image, which can be ingested into
+    /// a Druid `HLLSketchMerge` metric, `P` is the `lgK` of the metric.
+    pub fn to_druid(&self) -> String {
+        encoding::to_base64(&self.to_datasketches_hll8())
+    }
+
+    /// Load a base64 sketch as returned by Druid for a `HLLSketch` column or aggregator
+    pub fn from_druid(s: &str) -> Result<Self, HllError> {
+        let bytes = encoding::from_base64(s.trim())
+            .ok_or_else(|| HllError::Malformed("invalid base64".to_string()))?;
+        Self::from_datasketches(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use datasketches::hll::HllSketch;
+    use datasketches::hll::HllType;
+
+    use super::*;
+
+    #[test]
+    fn test_druid() {
+        let mut hll = HyperLogLog::<12>::new();
+        for i in 0..10_000 {
+            hll.add_object(&i);
+        }
+        let s = hll.to_druid();
+        assert_eq!(HyperLogLog::<12>::from_druid(&s).unwrap(), hll);
+
+        // what Druid returns for an aggregator of the default type, HLL_4
+        let mut sketch = HllSketch::new(12, HllType::Hll4).unwrap();
+        for i in 0..100 {
+            sketch.update(i);
+        }
+        let s = encoding::to_base64(&sketch.serialize());
+        let hll = HyperLogLog::<12>::from_druid(&s).unwrap();
+        assert!((98..=102).contains(&hll.count()));
+
+        // and once it switched to the HLL mode, with 4-bit registers and exceptions
+        let mut hll8 = HllSketch::new(12, HllType::Hll8).unwrap();
+        for i in 0..1_000_000 {
+            sketch.update(i);
+            hll8.update(i);
+        }
+        let bytes = sketch.serialize();
+        assert_eq!(bytes[7], 2);
+        let s = encoding::to_base64(&bytes);
+        let hll = HyperLogLog::<12>::from_druid(&s).unwrap();
+        assert_eq!(
+            hll,
+            HyperLogLog::<12>::from_datasketches(&hll8.serialize()).unwrap()
+        );
+        let error = (hll.count() as f64 - sketch.estimate()).abs() / sketch.estimate();
+        assert!(error < 0.05, "{} vs {}", hll.count(), sketch.estimate());
+
+        assert_eq!(
+            HyperLogLog::<12>::from_druid("AgEH!"),
+            Err(HllError::Malformed("invalid base64".to_string()))
+        );
+    }
+}
@@ -0,0 +1,206 @@
+//! Text encodings of bytes, and of sketches in the binary format
+
+use core::fmt;
+
+use crate::HllError;
+use crate::HyperLogLog;
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard base64 with padding (RFC 4648)
+pub(crate) fn to_base64(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    write_base64(&mut s, bytes.iter().copied()).unwrap();
+    s
+}
+
+/// Write the bytes as standard base64 with padding, without buffering them
+pub(crate) fn write_base64<W: fmt::Write>(
+    w: &mut W,
+    mut bytes: impl Iterator<Item = u8>,
+) -> fmt::Result {
+    while let Some(b0) = bytes.next() {
+        let chunk = [Some(b0), bytes.next(), bytes.next()];
+        let len = chunk.iter().flatten().count();
+        let n = chunk
+            .iter()
+            .fold(0_u32, |n, b| n << 8 | b.unwrap_or(0) as u32);
+        for i in 0..4 {
+            if i <= len {
+                w.write_char(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char)?;
+            } else {
+                w.write_char('=')?;
+            }
+        }
+        if len < 3 {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Decode standard base64 with padding, returns `None` if the input is malformed
+pub(crate) fn from_base64(s: &str) -> Option<Vec<u8>> {
+    let s = s.as_bytes();
+    if !s.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(s.len() / 4 * 3);
+    for (i, chunk) in s.chunks(4).enumerate() {
+        let last = i == s.len() / 4 - 1;
+        let pad = chunk.iter().rev().take_while(|c| **c == b'=').count();
+        if pad > 2 || (pad > 0 && !last) {
+            return None;
+        }
+        let mut n = 0_u32;
+        for c in &chunk[..4 - pad] {
+            n = n << 6 | base64_value(*c)? as u32;
+        }
+        n <<= 6 * pad;
+        bytes.extend_from_slice(&[(n >> 16) as u8, (n >> 8) as u8, n as u8][..3 - pad]);
+    }
+    Some(bytes)
+}
+
+/// Lowercase hex, two digits per byte
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        s.push(HEX_DIGITS[(b >> 4) as usize] as char);
+        s.push(HEX_DIGITS[(b & 0xf) as usize] as char);
+    }
+    s
+}
+
+/// Decode hex of either case, returns `None` if the input is malformed
+pub(crate) fn from_hex(s: &str) -> Option<Vec<u8>> {
+    let s = s.as_bytes();
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    s.chunks(2)
+        .map(|pair| Some(hex_value(pair[0])? << 4 | hex_value(pair[1])?))
+        .collect()
+}
+
+#[inline]
+fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[inline]
+fn base64_value(c: u8) -> Option<u8> {
+    match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+impl<const P: usize> HyperLogLog<P> {
+    /// The binary format (see [`Self::to_bytes`]) as standard base64, a compact text form
+    /// for JSON columns, environment variables, logs...
+    pub fn to_base64(&self) -> String {
+        to_base64(&self.to_bytes())
+    }
+
+    /// Load a sketch written by [`Self::to_base64`]
+    pub fn from_base64(s: &str) -> Result<Self, HllError> {
+        let bytes =
+            from_base64(s).ok_or_else(|| HllError::Malformed("invalid base64".to_string()))?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// The binary format (see [`Self::to_bytes`]) as lowercase hex
+    pub fn to_hex(&self) -> String {
+        to_hex(&self.to_bytes())
+    }
+
+    /// Load a sketch written by [`Self::to_hex`], both cases are accepted
+    pub fn from_hex(s: &str) -> Result<Self, HllError> {
+        let bytes = from_hex(s).ok_or_else(|| HllError::Malformed("invalid hex".to_string()))?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        // RFC 4648 test vectors
+        for (raw, encoded) in [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(to_base64(raw.as_bytes()), encoded);
+            assert_eq!(from_base64(encoded).unwrap(), raw.as_bytes());
+        }
+
+        let bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(from_base64(&to_base64(&bytes)).unwrap(), bytes);
+
+        for invalid in ["Zg=", "Z===", "Zg==Zg==", "Zm9*"] {
+            assert_eq!(from_base64(invalid), None);
+        }
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(to_hex(&[0x00, 0x9f, 0xa0, 0xff]), "009fa0ff");
+        assert_eq!(from_hex("009FA0ff").unwrap(), [0x00, 0x9f, 0xa0, 0xff]);
+        for invalid in ["0", "0g", "+1"] {
+            assert_eq!(from_hex(invalid), None);
+        }
+    }
+
+    #[test]
+    fn test_sketch_text() {
+        let mut hll = HyperLogLog::<12>::new();
+        for i in 0..1000 {
+            hll.add_object(&i);
+        }
+        let base64 = hll.to_base64();
+        assert!(base64.starts_with("U0hMTAEM"));
+        assert_eq!(HyperLogLog::<12>::from_base64(&base64).unwrap(), hll);
+
+        let hex = hll.to_hex();
+        // magic, version, precision
+        assert!(hex.starts_with("53484c4c010c"));
+        assert_eq!(HyperLogLog::<12>::from_hex(&hex).unwrap(), hll);
+        assert_eq!(
+            HyperLogLog::<12>::from_hex(&hex.to_uppercase()).unwrap(),
+            hll
+        );
+
+        assert_eq!(
+            HyperLogLog::<12>::from_base64("U0hM!"),
+            Err(HllError::Malformed("invalid base64".to_string()))
+        );
+        assert_eq!(
+            HyperLogLog::<12>::from_hex("53484c4"),
+            Err(HllError::Malformed("invalid hex".to_string()))
+        );
+        assert_eq!(
+            HyperLogLog::<12>::from_hex("53484c4c"),
+            Err(HllError::UnexpectedEof)
+        );
+    }
+}
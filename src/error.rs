@@ -0,0 +1,88 @@
+use alloc::string::String;
+use core::fmt;
+
+use crate::hyperloglog::MAX_P;
+use crate::hyperloglog::MIN_P;
+
+/// Errors returned by the fallible HyperLogLog APIs
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum HllError {
+    /// P is out of the supported range [4, 18]
+    InvalidPrecision(usize),
+    /// The number of registers doesn't match `1 << P`
+    InvalidRegisterLength { expected: usize, actual: usize },
+    /// A register holds a value larger than `64 - P + 1`
+    InvalidRegisterValue { index: usize, value: u8, max: u8 },
+    /// A register index is larger or equal than the number of registers
+    InvalidRegisterIndex { index: usize, len: usize },
+    /// The serialized bytes don't start with the magic bytes
+    InvalidMagic,
+    /// The serialized format version is not supported by this version of the crate
+    UnsupportedVersion(u8),
+    /// The serialized representation is not supported by this version of the crate
+    UnsupportedRepresentation(u8),
+    /// The serialized P doesn't match the P of the target type
+    PrecisionMismatch { expected: usize, actual: usize },
+    /// The serialized sketch was built by a different hasher
+    HasherMismatch { expected: u8, actual: u8 },
+    /// The serialized bytes end unexpectedly
+    UnexpectedEof,
+    /// There are bytes left after the serialized sketch
+    TrailingBytes(usize),
+    /// The checksum of the serialized bytes doesn't match, they are corrupted
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// The serialized bytes or their container (e.g. a FlatBuffers buffer) failed validation
+    Malformed(String),
+}
+
+impl fmt::Display for HllError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HllError::InvalidPrecision(p) => write!(
+                f,
+                "P ({}) must be larger or equal than {} and smaller or equal than {}",
+                p, MIN_P, MAX_P
+            ),
+            HllError::InvalidRegisterLength { expected, actual } => write!(
+                f,
+                "invalid number of registers, expected {}, got {}",
+                expected, actual
+            ),
+            HllError::InvalidRegisterValue { index, value, max } => write!(
+                f,
+                "invalid value {} of register {}, must be smaller or equal than {}",
+                value, index, max
+            ),
+            HllError::InvalidRegisterIndex { index, len } => write!(
+                f,
+                "invalid register index {}, must be smaller than {}",
+                index, len
+            ),
+            HllError::InvalidMagic => write!(f, "invalid magic bytes"),
+            HllError::UnsupportedVersion(v) => write!(f, "unsupported format version {}", v),
+            HllError::UnsupportedRepresentation(r) => {
+                write!(f, "unsupported representation {}", r)
+            }
+            HllError::PrecisionMismatch { expected, actual } => write!(
+                f,
+                "precision mismatch, expected P ({}), got P ({})",
+                expected, actual
+            ),
+            HllError::HasherMismatch { expected, actual } => write!(
+                f,
+                "hasher mismatch, expected hasher id {}, got {}",
+                expected, actual
+            ),
+            HllError::UnexpectedEof => write!(f, "unexpected end of bytes"),
+            HllError::TrailingBytes(n) => write!(f, "{} trailing bytes", n),
+            HllError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "checksum mismatch, expected {:#010x}, got {:#010x}",
+                expected, actual
+            ),
+            HllError::Malformed(reason) => write!(f, "malformed serialized sketch: {}", reason),
+        }
+    }
+}
+
+impl core::error::Error for HllError {}
@@ -0,0 +1,212 @@
+//! C API, for C/C++/Go services sharing sketches with Rust components.
+//!
+//! Build the shared library with
+//! `cargo rustc --release --features ffi --crate-type cdylib`, the header is
+//! `include/simple_hll.h`, generated by cbindgen (see `CONTRIBUTING.md`).
+//!
+//! Sketches have the default precision `14` and are opaque [`HllSketch`] pointers owned by
+//! the caller, every sketch returned by `hll_new`/`hll_deserialize` must be released with
+//! `hll_free`. `hll_serialize` writes the binary format of [`HyperLogLog::to_bytes`], which
+//! Rust reads with [`HyperLogLog::from_bytes`] and the other way around.
+//!
+//! ```c
+//! HllSketch *hll = hll_new();
+//! hll_add_bytes(hll, (const uint8_t *)"user-1", 6);
+//! size_t len = hll_serialize(hll, NULL, 0);
+//! uint8_t *buf = malloc(len);
+//! hll_serialize(hll, buf, len);
+//! hll_free(hll);
+//! ```
+
+use alloc::boxed::Box;
+
+use crate::HyperLogLog;
+
+/// A sketch of precision `14`, only handled through pointers
+pub struct HllSketch(HyperLogLog);
+
+/// A new empty sketch, release it with `hll_free`
+#[no_mangle]
+pub extern "C" fn hll_new() -> *mut HllSketch {
+    Box::into_raw(Box::new(HllSketch(HyperLogLog::new())))
+}
+
+/// Release a sketch, `NULL` is ignored
+///
+/// # Safety
+///
+/// `hll` must be `NULL` or a sketch returned by this library, not released yet
+#[no_mangle]
+pub unsafe extern "C" fn hll_free(hll: *mut HllSketch) {
+    if !hll.is_null() {
+        // SAFETY: the caller guarantees the sketch was allocated by `Box` and is still live
+        drop(unsafe { Box::from_raw(hll) });
+    }
+}
+
+/// Add a 64-bit hash computed by the caller, the low 14 bits pick the register
+///
+/// # Safety
+///
+/// `hll` must be a live sketch returned by this library
+#[no_mangle]
+pub unsafe extern "C" fn hll_add_hash(hll: *mut HllSketch, hash: u64) {
+    // SAFETY: the caller guarantees the pointer is valid, `NULL` is ignored
+    if let Some(hll) = unsafe { hll.as_mut() } {
+        hll.0.add_hash(hash);
+    }
+}
+
+/// Add `len` bytes hashed with the default hasher, the same as `add_object` of a
+/// `&[u8]` or `Vec<u8>` with these bytes in Rust
+///
+/// # Safety
+///
+/// `hll` must be a live sketch returned by this library, `bytes` must point to `len`
+/// readable bytes, it may be `NULL` if `len` is `0`
+#[no_mangle]
+pub unsafe extern "C" fn hll_add_bytes(hll: *mut HllSketch, bytes: *const u8, len: usize) {
+    // SAFETY: the caller guarantees the pointers are valid, `NULL` is ignored
+    let hll = unsafe { hll.as_mut() };
+    let bytes = unsafe { slice(bytes, len) };
+    let (Some(hll), Some(bytes)) = (hll, bytes) else {
+        return;
+    };
+    hll.0.add_object(&bytes);
+}
+
+/// Merge `src` into `dst`, nothing changes if they are the same sketch
+///
+/// # Safety
+///
+/// `dst` and `src` must be live sketches returned by this library, they may be the same
+#[no_mangle]
+pub unsafe extern "C" fn hll_merge(dst: *mut HllSketch, src: *const HllSketch) {
+    // a sketch merged into itself is unchanged, and it can't be borrowed twice
+    if core::ptr::eq(dst, src) {
+        return;
+    }
+    // SAFETY: the caller guarantees the pointers are valid, `NULL` is ignored
+    let (Some(dst), Some(src)) = (unsafe { dst.as_mut() }, unsafe { src.as_ref() }) else {
+        return;
+    };
+    dst.0.merge(&src.0);
+}
+
+/// The estimated number of distinct items, `0` for `NULL`
+///
+/// # Safety
+///
+/// `hll` must be a live sketch returned by this library
+#[no_mangle]
+pub unsafe extern "C" fn hll_count(hll: *const HllSketch) -> u64 {
+    // SAFETY: the caller guarantees the pointer is valid, `NULL` is ignored
+    unsafe { hll.as_ref() }.map_or(0, |hll| hll.0.count() as u64)
+}
+
+/// Write the binary format of the sketch into `buf` and return its size. Nothing is
+/// written if `cap` is smaller than the size, call it with `NULL` and `0` to get the
+/// size first. Returns `0` for a `NULL` sketch.
+///
+/// # Safety
+///
+/// `hll` must be a live sketch returned by this library, `buf` must point to `cap`
+/// writable bytes, it may be `NULL` if `cap` is `0`
+#[no_mangle]
+pub unsafe extern "C" fn hll_serialize(hll: *const HllSketch, buf: *mut u8, cap: usize) -> usize {
+    // SAFETY: the caller guarantees the pointer is valid, `NULL` is ignored
+    let Some(hll) = (unsafe { hll.as_ref() }) else {
+        return 0;
+    };
+    let bytes = hll.0.to_bytes();
+    if !buf.is_null() && cap >= bytes.len() {
+        // SAFETY: the caller guarantees `buf` has `cap` writable bytes, `bytes` can't overlap
+        // the caller's buffer
+        unsafe { core::ptr::copy_nonoverlapping(bytes.as_ptr(), buf, bytes.len()) };
+    }
+    bytes.len()
+}
+
+/// Read a sketch written by `hll_serialize` or `HyperLogLog::to_bytes`, `NULL` if the bytes
+/// are invalid. Release it with `hll_free`.
+///
+/// # Safety
+///
+/// `bytes` must point to `len` readable bytes, it may be `NULL` if `len` is `0`
+#[no_mangle]
+pub unsafe extern "C" fn hll_deserialize(bytes: *const u8, len: usize) -> *mut HllSketch {
+    // SAFETY: the caller guarantees the pointer is valid
+    match unsafe { slice(bytes, len) }.map(HyperLogLog::from_bytes) {
+        Some(Ok(hll)) => Box::into_raw(Box::new(HllSketch(hll))),
+        _ => core::ptr::null_mut(),
+    }
+}
+
+/// `len` bytes at `ptr`, an empty slice for `NULL` and `0`, `None` for `NULL` and a length
+///
+/// # Safety
+///
+/// `ptr` must point to `len` readable bytes if it isn't `NULL`
+unsafe fn slice<'a>(ptr: *const u8, len: usize) -> Option<&'a [u8]> {
+    match (ptr.is_null(), len) {
+        (true, 0) => Some(&[]),
+        (true, _) => None,
+        // SAFETY: the caller guarantees the bytes are readable
+        (false, _) => Some(unsafe { core::slice::from_raw_parts(ptr, len) }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ffi() {
+        unsafe {
+            let a = hll_new();
+            let b = hll_new();
+            for i in 0..1000_u64 {
+                hll_add_hash(a, <ahash::AHasher as crate::Hasher>::hll_hash(i));
+                let item = format!("item-{}", i);
+                hll_add_bytes(b, item.as_ptr(), item.len());
+            }
+            hll_add_bytes(b, core::ptr::null(), 0);
+            hll_merge(a, b);
+            let before = (*a).0.clone();
+            hll_merge(a, a);
+            assert_eq!((*a).0, before);
+
+            let mut expected = HyperLogLog::<14>::new();
+            for i in 0..1000_u64 {
+                expected.add_object(&i);
+                expected.add_object(&format!("item-{}", i).into_bytes());
+            }
+            expected.add_object(&Vec::<u8>::new());
+            assert_eq!((*a).0, expected);
+            assert_eq!(hll_count(a), expected.count() as u64);
+
+            let len = hll_serialize(a, core::ptr::null_mut(), 0);
+            let mut buf = vec![0; len];
+            assert_eq!(hll_serialize(a, buf.as_mut_ptr(), len - 1), len);
+            assert!(buf.iter().all(|b| *b == 0));
+            assert_eq!(hll_serialize(a, buf.as_mut_ptr(), len), len);
+            assert_eq!(buf, expected.to_bytes());
+
+            let c = hll_deserialize(buf.as_ptr(), buf.len());
+            assert_eq!((*c).0, expected);
+            assert!(hll_deserialize(buf.as_ptr(), buf.len() - 1).is_null());
+            assert!(hll_deserialize(core::ptr::null(), 1).is_null());
+
+            // NULL sketches are ignored
+            hll_add_hash(core::ptr::null_mut(), 1);
+            hll_merge(a, core::ptr::null());
+            assert_eq!(hll_count(core::ptr::null()), 0);
+            assert_eq!(hll_serialize(core::ptr::null(), buf.as_mut_ptr(), len), 0);
+
+            hll_free(a);
+            hll_free(b);
+            hll_free(c);
+            hll_free(core::ptr::null_mut());
+        }
+    }
+}
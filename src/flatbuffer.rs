@@ -0,0 +1,265 @@
+//! FlatBuffers table of the sketch, the schema is in `fbs/simple_hll.fbs`.
+//!
+//! [`HyperLogLogTable`] is a verified view over the buffer, its accessors read the fields
+//! in place and [`HyperLogLogTable::payload`] borrows the register payload without copying.
+
+use ::flatbuffers::FlatBufferBuilder;
+use ::flatbuffers::Follow;
+use ::flatbuffers::ForwardsUOffset;
+use ::flatbuffers::Table;
+use ::flatbuffers::VOffsetT;
+use ::flatbuffers::Vector;
+use ::flatbuffers::Verifiable;
+use ::flatbuffers::Verifier;
+
+use crate::format;
+use crate::format::SliceReader;
+use crate::Hasher;
+use crate::HllError;
+use crate::HyperLogLog;
+
+/// File identifier of the buffer
+pub const FILE_IDENTIFIER: &str = "SHLL";
+
+/// Representation of the payload, same values as the binary format
+pub const REPRESENTATION_EMPTY: u8 = format::REPR_EMPTY;
+pub const REPRESENTATION_SPARSE: u8 = format::REPR_SPARSE;
+pub const REPRESENTATION_DENSE: u8 = format::REPR_DENSE;
+pub const REPRESENTATION_COMPRESSED: u8 = format::REPR_COMPRESSED;
+
+/// Verified view over the `HyperLogLog` table of a FlatBuffers buffer
+#[derive(Clone, Copy, PartialEq)]
+pub struct HyperLogLogTable<'a> {
+    table: Table<'a>,
+}
+
+impl<'a> HyperLogLogTable<'a> {
+    const VT_PRECISION: VOffsetT = 4;
+    const VT_REPRESENTATION: VOffsetT = 6;
+    const VT_HASHER: VOffsetT = 8;
+    const VT_PAYLOAD: VOffsetT = 10;
+
+    /// Verify the buffer and return its root table
+    pub fn root(buf: &'a [u8]) -> Result<Self, HllError> {
+        if !::flatbuffers::buffer_has_identifier(buf, FILE_IDENTIFIER, false) {
+            return Err(HllError::InvalidMagic);
+        }
+        ::flatbuffers::root::<HyperLogLogTable>(buf).map_err(|e| HllError::Malformed(e.to_string()))
+    }
+
+    /// P, the number of registers is `1 << P`
+    pub fn precision(&self) -> u8 {
+        // SAFETY: the buffer was verified in `root`
+        unsafe { self.table.get::<u8>(Self::VT_PRECISION, Some(0)) }.unwrap_or(0)
+    }
+
+    /// One of the `REPRESENTATION_*` constants
+    pub fn representation(&self) -> u8 {
+        // SAFETY: the buffer was verified in `root`
+        unsafe { self.table.get::<u8>(Self::VT_REPRESENTATION, Some(0)) }.unwrap_or(0)
+    }
+
+    /// Id of the hash function, see `Hasher::ID`
+    pub fn hasher(&self) -> u8 {
+        // SAFETY: the buffer was verified in `root`
+        unsafe { self.table.get::<u8>(Self::VT_HASHER, Some(0)) }.unwrap_or(0)
+    }
+
+    /// Same as the payload of the binary format, borrowed from the buffer
+    pub fn payload(&self) -> &'a [u8] {
+        // SAFETY: the buffer was verified in `root`
+        unsafe {
+            self.table
+                .get::<ForwardsUOffset<Vector<'a, u8>>>(Self::VT_PAYLOAD, None)
+        }
+        .map(|v| v.bytes())
+        .unwrap_or(&[])
+    }
+
+    /// Decode the payload and call `f` with the index and value of every non-zero register
+    fn visit_registers<const P: usize>(&self, f: impl FnMut(usize, u8)) -> Result<(), HllError> {
+        if self.precision() as usize != P {
+            return Err(HllError::PrecisionMismatch {
+                expected: P,
+                actual: self.precision() as usize,
+            });
+        }
+        if self.hasher() != ahash::AHasher::ID {
+            return Err(HllError::HasherMismatch {
+                expected: ahash::AHasher::ID,
+                actual: self.hasher(),
+            });
+        }
+        let mut reader = SliceReader::new(self.payload());
+        format::visit_payload::<P>(self.representation(), &mut reader, f)?;
+        reader.finish()
+    }
+
+    /// Guess the number of unique elements directly from the payload, only the
+    /// register histogram is built while decoding. Sparse payloads with duplicate or
+    /// unordered register indexes are rejected and empty entries are skipped, each register
+    /// is counted once.
+    pub fn count<const P: usize>(&self) -> Result<usize, HllError> {
+        format::count_registers::<P>(|f| self.visit_registers::<P>(f))
+    }
+}
+
+impl<'a> Follow<'a> for HyperLogLogTable<'a> {
+    type Inner = HyperLogLogTable<'a>;
+
+    #[inline]
+    unsafe fn follow(buf: &'a [u8], loc: usize) -> Self::Inner {
+        Self {
+            // SAFETY: guaranteed by the caller
+            table: unsafe { Table::new(buf, loc) },
+        }
+    }
+}
+
+impl Verifiable for HyperLogLogTable<'_> {
+    fn run_verifier(v: &mut Verifier, pos: usize) -> Result<(), ::flatbuffers::InvalidFlatbuffer> {
+        v.visit_table(pos)?
+            .visit_field::<u8>("precision", Self::VT_PRECISION, false)?
+            .visit_field::<u8>("representation", Self::VT_REPRESENTATION, false)?
+            .visit_field::<u8>("hasher", Self::VT_HASHER, false)?
+            .visit_field::<ForwardsUOffset<Vector<'_, u8>>>("payload", Self::VT_PAYLOAD, false)?
+            .finish();
+        Ok(())
+    }
+}
+
+impl<const P: usize> HyperLogLog<P> {
+    /// Serialize into a FlatBuffers buffer of the `HyperLogLog` table
+    pub fn to_flatbuffer(&self) -> Vec<u8> {
+        let bytes = self.to_bytes();
+        let (header, payload) = bytes.split_at(format::HEADER_SIZE);
+
+        let mut builder = FlatBufferBuilder::with_capacity(payload.len() + 64);
+        let payload = builder.create_vector(payload);
+        let start = builder.start_table();
+        builder.push_slot_always(HyperLogLogTable::VT_PAYLOAD, payload);
+        builder.push_slot::<u8>(HyperLogLogTable::VT_PRECISION, P as u8, 0);
+        builder.push_slot::<u8>(HyperLogLogTable::VT_REPRESENTATION, header[7], 0);
+        builder.push_slot::<u8>(HyperLogLogTable::VT_HASHER, header[6], 0);
+        let root = builder.end_table(start);
+        builder.finish(root, Some(FILE_IDENTIFIER));
+        builder.finished_data().to_vec()
+    }
+
+    /// Deserialize from a FlatBuffers buffer written by [`Self::to_flatbuffer`]
+    pub fn from_flatbuffer(buf: &[u8]) -> Result<Self, HllError> {
+        HyperLogLog::<P>::try_from(HyperLogLogTable::root(buf)?)
+    }
+}
+
+impl<const P: usize> TryFrom<HyperLogLogTable<'_>> for HyperLogLog<P> {
+    type Error = HllError;
+
+    fn try_from(table: HyperLogLogTable<'_>) -> Result<Self, Self::Error> {
+        let mut registers = vec![0; Self::NUM_REGISTERS];
+        table.visit_registers::<P>(|index, value| registers[index] = value)?;
+        Self::try_with_registers(registers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flatbuffer_round_trip() {
+        let mut hll = HyperLogLog::<14>::new();
+        for (n, repr) in [
+            (0, REPRESENTATION_EMPTY),
+            (100, REPRESENTATION_SPARSE),
+            (10_000, REPRESENTATION_COMPRESSED),
+        ] {
+            for i in 0..n {
+                hll.add_object(&i);
+            }
+            let buf = hll.to_flatbuffer();
+            let table = HyperLogLogTable::root(&buf).unwrap();
+            assert_eq!(table.precision(), 14);
+            assert_eq!(table.representation(), repr);
+            assert_eq!(table.hasher(), ahash::AHasher::ID);
+            assert_eq!(table.payload(), &hll.to_bytes()[format::HEADER_SIZE..]);
+            assert_eq!(table.count::<14>().unwrap(), hll.count());
+            assert_eq!(HyperLogLog::<14>::from_flatbuffer(&buf).unwrap(), hll);
+        }
+
+        let hll = HyperLogLog::<14>::with_registers(vec![3; 1 << 14]);
+        let buf = hll.to_flatbuffer();
+        let table = HyperLogLogTable::root(&buf).unwrap();
+        assert_eq!(table.representation(), REPRESENTATION_DENSE);
+        // the payload is borrowed from the buffer
+        assert!(buf.as_ptr_range().contains(&table.payload().as_ptr()));
+        assert_eq!(HyperLogLog::<14>::try_from(table).unwrap(), hll);
+    }
+
+    #[test]
+    fn test_flatbuffer_invalid() {
+        let mut hll = HyperLogLog::<14>::new();
+        hll.add_object(&1);
+        let buf = hll.to_flatbuffer();
+        assert_eq!(
+            HyperLogLog::<12>::from_flatbuffer(&buf),
+            Err(HllError::PrecisionMismatch {
+                expected: 12,
+                actual: 14
+            })
+        );
+        assert_eq!(
+            HyperLogLog::<14>::from_flatbuffer(&hll.to_bytes()),
+            Err(HllError::InvalidMagic)
+        );
+        assert!(matches!(
+            HyperLogLog::<14>::from_flatbuffer(&buf[..buf.len() - 4]),
+            Err(HllError::Malformed(_))
+        ));
+
+        // duplicate sparse entries
+        let buf = sparse_table(4, &[(0, 1); 40]);
+        let table = HyperLogLogTable::root(&buf).unwrap();
+        assert!(matches!(table.count::<4>(), Err(HllError::Malformed(_))));
+        assert!(matches!(
+            HyperLogLog::<4>::try_from(table),
+            Err(HllError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn test_flatbuffer_zero_entries() {
+        // one rank-3 register, then empty entries which must not count as registers
+        let entries: Vec<_> = (0..=2000)
+            .map(|i| (i, if i == 0 { 3 } else { 0 }))
+            .collect();
+        let buf = sparse_table(14, &entries);
+        let table = HyperLogLogTable::root(&buf).unwrap();
+        let hll = HyperLogLog::<14>::try_from(table).unwrap();
+        assert_eq!(hll.num_empty_registers(), (1 << 14) - 1);
+        assert_eq!(table.count::<14>().unwrap(), hll.count());
+    }
+
+    /// A table with a sparse payload of `entries`, written as is
+    fn sparse_table(precision: u8, entries: &[(u16, u8)]) -> Vec<u8> {
+        let mut payload = (entries.len() as u32).to_le_bytes().to_vec();
+        for (index, value) in entries {
+            payload.extend_from_slice(&index.to_le_bytes());
+            payload.push(*value);
+        }
+        let mut builder = FlatBufferBuilder::new();
+        let payload = builder.create_vector(&payload);
+        let start = builder.start_table();
+        builder.push_slot_always(HyperLogLogTable::VT_PAYLOAD, payload);
+        builder.push_slot::<u8>(HyperLogLogTable::VT_PRECISION, precision, 0);
+        builder.push_slot::<u8>(
+            HyperLogLogTable::VT_REPRESENTATION,
+            REPRESENTATION_SPARSE,
+            0,
+        );
+        builder.push_slot::<u8>(HyperLogLogTable::VT_HASHER, ahash::AHasher::ID, 0);
+        let root = builder.end_table(start);
+        builder.finish(root, Some(FILE_IDENTIFIER));
+        builder.finished_data().to_vec()
+    }
+}
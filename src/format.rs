@@ -0,0 +1,886 @@
+//! # Binary format
+//!
+//! A stable binary format which doesn't depend on serde, all integers are little-endian.
+//!
+//! | offset | size | field                                         |
+//! |--------|------|-----------------------------------------------|
+//! | 0      | 4    | magic `b"SHLL"`                               |
+//! | 4      | 1    | format version, currently `1`                 |
+//! | 5      | 1    | precision `P`                                 |
+//! | 6      | 1    | hasher id, see [`crate::Hasher::ID`]          |
+//! | 7      | 1    | representation: 0 empty, 1 sparse, 2 dense, 3 compressed, bit 7 set if there's a checksum |
+//! | 8      | ..   | payload                                       |
+//! | ..     | 4    | optional CRC-32 (IEEE) of all the previous bytes |
+//!
+//! Payload of each representation:
+//! - empty: nothing.
+//! - sparse: `u32` number of entries, followed by the entries in strictly increasing order of
+//!   register index, each one is the register index (`u16` if `P <= 16`, otherwise `u32`) and
+//!   the `u8` value.
+//! - dense: `1 << P` bytes, one byte per register.
+//! - compressed: the rest of the bytes are varint encoded registers, every non-zero register
+//!   is a LEB128 varint `(zeros << 6) | value`, where `zeros` is the number of zero registers
+//!   before it.
+//!
+//! Representations `4..=127` and bit 6 of the representation byte are reserved for future
+//! versions, readers reject them with [`HllError::UnsupportedRepresentation`], and versions
+//! they don't know with [`HllError::UnsupportedVersion`], so they can fall back gracefully.
+//!
+//! A sketch of precision `P` can be read by a sketch of any lower precision, the registers
+//! are folded like [`HyperLogLog::fold`] does.
+
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::compressed;
+use crate::hyperloglog::fold_register;
+use crate::hyperloglog::MAX_P;
+use crate::metrics;
+use crate::Hasher;
+use crate::HllError;
+use crate::HyperLogLog;
+
+pub(crate) const MAGIC: &[u8; 4] = b"SHLL";
+pub(crate) const VERSION: u8 = 1;
+pub(crate) const HEADER_SIZE: usize = 8;
+pub(crate) const PRECISION_OFFSET: usize = 5;
+#[cfg(feature = "cli")]
+pub(crate) const HASHER_OFFSET: usize = 6;
+pub(crate) const REPR_OFFSET: usize = 7;
+
+pub(crate) const REPR_EMPTY: u8 = 0;
+pub(crate) const REPR_SPARSE: u8 = 1;
+pub(crate) const REPR_DENSE: u8 = 2;
+pub(crate) const REPR_COMPRESSED: u8 = 3;
+
+/// Set in the representation byte if a CRC-32 trailer follows the payload
+const FLAG_CHECKSUM: u8 = 0x80;
+const CHECKSUM_SIZE: usize = 4;
+
+impl<const P: usize> HyperLogLog<P> {
+    /// Serialize into the binary format described in the [`format`](crate::format) module,
+    /// tagged with the id of the default hasher.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_with_hasher::<ahash::AHasher>()
+    }
+
+    /// Same as [`Self::to_bytes`], but tagged with the id of hasher `H`
+    pub fn to_bytes_with_hasher<H: Hasher>(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.serialized_size());
+        self.serialize_into_with_hasher::<H>(&mut bytes);
+        bytes
+    }
+
+    /// Exact number of bytes written by [`Self::to_bytes`] and [`Self::serialize_into`]
+    pub fn serialized_size(&self) -> usize {
+        HEADER_SIZE + self.representation().1
+    }
+
+    /// Same as [`Self::to_bytes`], but appends to `buf`, so one buffer can be reused
+    /// to serialize many sketches.
+    pub fn serialize_into(&self, buf: &mut Vec<u8>) {
+        self.serialize_into_with_hasher::<ahash::AHasher>(buf)
+    }
+
+    /// Same as [`Self::serialize_into`], but tagged with the id of hasher `H`
+    pub fn serialize_into_with_hasher<H: Hasher>(&self, buf: &mut Vec<u8>) {
+        let (repr, payload_len) = self.representation();
+        buf.reserve(HEADER_SIZE + payload_len);
+        metrics::serialized_bytes(HEADER_SIZE + payload_len);
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&[VERSION, P as u8, H::ID, repr]);
+
+        match repr {
+            REPR_SPARSE => {
+                let non_empty = (payload_len - 4) / (sparse_index_size::<P>() + 1);
+                buf.extend_from_slice(&(non_empty as u32).to_le_bytes());
+                for (index, value) in self.registers.iter().enumerate() {
+                    if *value == 0 {
+                        continue;
+                    }
+                    if sparse_index_size::<P>() == 2 {
+                        buf.extend_from_slice(&(index as u16).to_le_bytes());
+                    } else {
+                        buf.extend_from_slice(&(index as u32).to_le_bytes());
+                    }
+                    buf.push(*value);
+                }
+            }
+            REPR_DENSE => buf.extend_from_slice(&self.registers),
+            REPR_COMPRESSED => buf.extend(compressed::iter(&self.registers)),
+            _ => {}
+        }
+    }
+
+    /// The representation and the size of its payload: empty without any register, sparse
+    /// if it's smaller than dense (even when compressed would be smaller still, sparse
+    /// sketches are read without decoding varints), else the smaller of compressed and dense
+    fn representation(&self) -> (u8, usize) {
+        let non_empty = Self::NUM_REGISTERS - self.num_empty_registers();
+        let sparse_len = 4 + non_empty * (sparse_index_size::<P>() + 1);
+        if non_empty == 0 {
+            return (REPR_EMPTY, 0);
+        }
+        if sparse_len < Self::NUM_REGISTERS {
+            return (REPR_SPARSE, sparse_len);
+        }
+        let compressed_len = compressed::encoded_len(&self.registers);
+        if compressed_len < Self::NUM_REGISTERS {
+            (REPR_COMPRESSED, compressed_len)
+        } else {
+            (REPR_DENSE, Self::NUM_REGISTERS)
+        }
+    }
+
+    /// Same as [`Self::to_bytes`], followed by a CRC-32 of the bytes, which is validated
+    /// by every function reading the binary format.
+    pub fn to_bytes_with_checksum(&self) -> Vec<u8> {
+        let mut bytes = self.to_bytes();
+        bytes[REPR_OFFSET] |= FLAG_CHECKSUM;
+        let checksum = crc32(&bytes);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+
+    /// Deserialize from the binary format, the hasher id must match the default hasher.
+    /// Sketches of a higher precision are folded into `P`, a lower precision is an error.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, HllError> {
+        Self::from_bytes_with_hasher::<ahash::AHasher>(bytes)
+    }
+
+    /// Same as [`Self::from_bytes`], but the hasher id must match hasher `H`
+    pub fn from_bytes_with_hasher<H: Hasher>(bytes: &[u8]) -> Result<Self, HllError> {
+        let mut registers = vec![0; Self::NUM_REGISTERS];
+        visit_registers::<P, H>(bytes, |index, value| {
+            registers[index] = registers[index].max(value)
+        })?;
+        Self::try_with_registers(registers)
+    }
+
+    /// Merge a sketch serialized by [`Self::to_bytes`] into this one, registers are
+    /// decoded and merged one by one without materializing the other [`HyperLogLog`].
+    /// `self` is left untouched if the bytes are invalid.
+    pub fn merge_from_slice(&mut self, bytes: &[u8]) -> Result<(), HllError> {
+        self.merge_from_slice_with_hasher::<ahash::AHasher>(bytes)
+    }
+
+    /// Same as [`Self::merge_from_slice`], but the hasher id must match hasher `H`
+    pub fn merge_from_slice_with_hasher<H: Hasher>(
+        &mut self,
+        bytes: &[u8],
+    ) -> Result<(), HllError> {
+        // validate everything before touching any register
+        visit_registers::<P, H>(bytes, |_, _| {})?;
+        visit_registers::<P, H>(bytes, |index, value| {
+            self.registers[index] = self.registers[index].max(value)
+        })?;
+        metrics::merges(1);
+        Ok(())
+    }
+
+    /// Serialize only the registers which increased since `since`, an older state of this
+    /// sketch. The delta is itself a sketch in the binary format, usually sparse or
+    /// compressed, apply it with [`Self::apply_delta`].
+    pub fn serialize_delta(&self, since: &Self) -> Vec<u8> {
+        let registers = self
+            .registers
+            .iter()
+            .zip(&since.registers)
+            .map(|(r, old)| if r > old { *r } else { 0 })
+            .collect();
+        Self { registers }.to_bytes()
+    }
+
+    /// Apply a delta written by [`Self::serialize_delta`], same as [`Self::merge_from_slice`]
+    pub fn apply_delta(&mut self, bytes: &[u8]) -> Result<(), HllError> {
+        self.merge_from_slice(bytes)
+    }
+}
+
+impl<const P: usize> HyperLogLog<P> {
+    /// Guess the number of unique elements of a sketch serialized by [`Self::to_bytes`],
+    /// only the register histogram is built while decoding.
+    pub fn count_from_serialized(bytes: &[u8]) -> Result<usize, HllError> {
+        Self::count_from_serialized_with_hasher::<ahash::AHasher>(bytes)
+    }
+
+    /// Same as [`Self::count_from_serialized`], but the hasher id must match hasher `H`
+    pub fn count_from_serialized_with_hasher<H: Hasher>(bytes: &[u8]) -> Result<usize, HllError> {
+        if bytes
+            .get(PRECISION_OFFSET)
+            .is_some_and(|p| *p as usize != P)
+        {
+            // folded registers may be visited more than once
+            return Ok(Self::from_bytes_with_hasher::<H>(bytes)?.count());
+        }
+        count_registers::<P>(|f| visit_registers::<P, H>(bytes, f))
+    }
+}
+
+/// Estimate from the non-zero registers `visit` passes to `f`, each register at most once.
+/// Only the histogram is built, the empty registers are the ones never visited.
+pub(crate) fn count_registers<const P: usize>(
+    visit: impl FnOnce(&mut dyn FnMut(usize, u8)) -> Result<(), HllError>,
+) -> Result<usize, HllError> {
+    let mut histogram = [0_u32; 64];
+    visit(&mut |_, value| histogram[value as usize] += 1)?;
+    histogram[0] = HyperLogLog::<P>::NUM_REGISTERS as u32 - histogram[1..].iter().sum::<u32>();
+    Ok(HyperLogLog::<P>::estimate(&histogram))
+}
+
+/// Decode the binary format and call `f` with the index and value of every non-zero register,
+/// folded into `P`, so the same index may be visited more than once.
+/// Indexes and values are validated, but `f` may have been called before an error is found.
+pub(crate) fn visit_registers<const P: usize, H: Hasher>(
+    bytes: &[u8],
+    mut f: impl FnMut(usize, u8),
+) -> Result<(), HllError> {
+    let mut reader = SliceReader::new(bytes);
+    let (precision, mut repr) = read_header::<P, H>(&mut reader)?;
+    if repr & FLAG_CHECKSUM != 0 {
+        let len = bytes
+            .len()
+            .checked_sub(CHECKSUM_SIZE)
+            .filter(|len| *len >= HEADER_SIZE)
+            .ok_or(HllError::UnexpectedEof)?;
+        let (bytes, trailer) = bytes.split_at(len);
+        let expected = u32::from_le_bytes(trailer.try_into().unwrap());
+        let actual = crc32(bytes);
+        if expected != actual {
+            return Err(HllError::ChecksumMismatch { expected, actual });
+        }
+        reader = SliceReader::new(&bytes[HEADER_SIZE..]);
+        repr &= !FLAG_CHECKSUM;
+    }
+    visit_payload_with_precision(precision, repr, &mut reader, |index, value| {
+        if precision == P {
+            f(index, value)
+        } else {
+            let (index, value) = fold_register(precision, P, index, value);
+            f(index, value)
+        }
+    })?;
+    reader.finish()
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 (IEEE 802.3), same as zlib's `crc32`
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0_u32, |crc, b| {
+        CRC32_TABLE[((crc ^ *b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Same as [`visit_registers`], but only decodes the payload of representation `repr`,
+/// bytes after the payload are left in `reader`.
+#[cfg(feature = "flatbuffers")]
+pub(crate) fn visit_payload<const P: usize>(
+    repr: u8,
+    reader: &mut SliceReader<'_>,
+    f: impl FnMut(usize, u8),
+) -> Result<(), HllError> {
+    visit_payload_with_precision(P, repr, reader, f)
+}
+
+/// Same as [`visit_payload`], for registers of precision `p`
+fn visit_payload_with_precision(
+    p: usize,
+    repr: u8,
+    reader: &mut SliceReader<'_>,
+    mut f: impl FnMut(usize, u8),
+) -> Result<(), HllError> {
+    // sparse entries and compressed tokens of value 0 are valid but empty, like in dense
+    let mut f = |index, value| {
+        if value != 0 {
+            f(index, value)
+        }
+    };
+    let m = 1 << p;
+    let max = (64 - p + 1) as u8;
+    let check = |index: usize, value: u8| {
+        if index >= m {
+            Err(HllError::InvalidRegisterIndex { index, len: m })
+        } else if value > max {
+            Err(HllError::InvalidRegisterValue { index, value, max })
+        } else {
+            Ok(())
+        }
+    };
+
+    match repr {
+        REPR_EMPTY => {}
+        REPR_SPARSE => {
+            let entries = reader.read_u32()? as usize;
+            let mut next = 0;
+            for _ in 0..entries {
+                let index = if p <= 16 {
+                    reader.read_u16()? as usize
+                } else {
+                    reader.read_u32()? as usize
+                };
+                let value = reader.read_u8()?;
+                check(index, value)?;
+                // every register is visited at most once, counts rely on it
+                if index < next {
+                    return Err(HllError::Malformed(format!(
+                        "sparse register index {} is not larger than the previous one",
+                        index
+                    )));
+                }
+                next = index + 1;
+                f(index, value);
+            }
+        }
+        REPR_DENSE => {
+            let registers = reader.read_slice(m)?;
+            for (index, value) in registers.iter().enumerate() {
+                if *value != 0 {
+                    check(index, *value)?;
+                    f(index, *value);
+                }
+            }
+        }
+        REPR_COMPRESSED => {
+            let rest = reader.read_slice(reader.remaining())?;
+            compressed::visit(rest, m, |index, value| {
+                check(index, value)?;
+                f(index, value);
+                Ok(())
+            })?;
+        }
+        repr => return Err(HllError::UnsupportedRepresentation(repr)),
+    }
+    Ok(())
+}
+
+/// Validate the header and return the precision and the representation,
+/// the precision can be higher than `P`
+pub(crate) fn read_header<const P: usize, H: Hasher>(
+    reader: &mut SliceReader<'_>,
+) -> Result<(usize, u8), HllError> {
+    if reader.read_slice(MAGIC.len())? != MAGIC {
+        return Err(HllError::InvalidMagic);
+    }
+    let version = reader.read_u8()?;
+    if version != VERSION {
+        return Err(HllError::UnsupportedVersion(version));
+    }
+    let precision = reader.read_u8()? as usize;
+    if precision > MAX_P {
+        return Err(HllError::InvalidPrecision(precision));
+    }
+    if precision < P {
+        return Err(HllError::PrecisionMismatch {
+            expected: P,
+            actual: precision,
+        });
+    }
+    let hasher = reader.read_u8()?;
+    if hasher != H::ID {
+        return Err(HllError::HasherMismatch {
+            expected: H::ID,
+            actual: hasher,
+        });
+    }
+    Ok((precision, reader.read_u8()?))
+}
+
+/// Sparse register indexes are stored in `u16` as long as they fit
+#[inline]
+pub(crate) const fn sparse_index_size<const P: usize>() -> usize {
+    if P <= 16 {
+        2
+    } else {
+        4
+    }
+}
+
+/// Bounds checked little-endian reader over a byte slice
+pub(crate) struct SliceReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> SliceReader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub(crate) fn read_slice(&mut self, len: usize) -> Result<&'a [u8], HllError> {
+        if self.bytes.len() < len {
+            return Err(HllError::UnexpectedEof);
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub(crate) fn read_u8(&mut self) -> Result<u8, HllError> {
+        Ok(self.read_slice(1)?[0])
+    }
+
+    pub(crate) fn read_u16(&mut self) -> Result<u16, HllError> {
+        let bytes = self.read_slice(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub(crate) fn read_u32(&mut self) -> Result<u32, HllError> {
+        let bytes = self.read_slice(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn read_u64(&mut self) -> Result<u64, HllError> {
+        let bytes = self.read_slice(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// All the bytes must be consumed
+    pub(crate) fn finish(self) -> Result<(), HllError> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(HllError::TrailingBytes(self.bytes.len()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::HllError;
+    use crate::HyperLogLog;
+
+    fn round_trip<const P: usize>(hll: &HyperLogLog<P>, expected_size: usize) {
+        let bytes = hll.to_bytes();
+        assert_eq!(bytes.len(), expected_size);
+        assert_eq!(&bytes[..4], b"SHLL");
+        assert_eq!(bytes[5] as usize, P);
+        assert_eq!(&HyperLogLog::<P>::from_bytes(&bytes).unwrap(), hll);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut hll = HyperLogLog::<14>::new();
+        round_trip(&hll, 8);
+
+        for i in 0..100 {
+            hll.add_object(&i);
+        }
+        let non_empty = (1 << 14) - hll.num_empty_registers();
+        round_trip(&hll, 8 + 4 + non_empty * 3);
+
+        for i in 0..10_000 {
+            hll.add_object(&i);
+        }
+        let compressed = crate::compressed::encode(&hll.registers).len();
+        assert!(compressed < (1 << 14) * 2 / 3);
+        round_trip(&hll, 8 + compressed);
+
+        // no zero registers to skip, compressed is as large as dense
+        let hll = HyperLogLog::<14>::with_registers(vec![1; 1 << 14]);
+        round_trip(&hll, 8 + (1 << 14));
+
+        // wide sparse indexes
+        let mut hll = HyperLogLog::<18>::new();
+        for i in 0..1000 {
+            hll.add_object(&i);
+        }
+        let non_empty = (1 << 18) - hll.num_empty_registers();
+        round_trip(&hll, 8 + 4 + non_empty * 5);
+    }
+
+    #[test]
+    fn test_golden() {
+        let mut hll = HyperLogLog::<4>::new();
+        hll.add_hash(0x31);
+        hll.add_hash(0x1_0005);
+        assert_eq!(
+            hll.to_bytes(),
+            [b'S', b'H', b'L', b'L', 1, 4, 1, 1, 2, 0, 0, 0, 1, 0, 1, 5, 0, 13]
+        );
+
+        let hll = HyperLogLog::<4>::with_registers(vec![1; 16]);
+        let mut expected = vec![b'S', b'H', b'L', b'L', 1, 4, 1, 2];
+        expected.extend_from_slice(&[1; 16]);
+        assert_eq!(hll.to_bytes(), expected);
+    }
+
+    /// Sketches of the default hasher, committed in `tests/fixtures/corpus`, they must be
+    /// read with the same registers and estimate by every later version.
+    /// Run with `UPDATE_GOLDEN=1` to add new fixtures, existing ones must never change.
+    #[test]
+    fn test_golden_corpus() {
+        fn check<const P: usize>(n: u64, register_sum: u64, estimate: usize) {
+            let path = format!(
+                "{}/tests/fixtures/corpus/p{}_{}.bin",
+                env!("CARGO_MANIFEST_DIR"),
+                P,
+                n
+            );
+            let mut hll = HyperLogLog::<P>::new();
+            for i in 0..n {
+                hll.add_object(&i);
+            }
+            if std::env::var_os("UPDATE_GOLDEN").is_some() && !std::path::Path::new(&path).exists()
+            {
+                std::fs::write(&path, hll.to_bytes()).unwrap();
+            }
+
+            let golden = HyperLogLog::<P>::from_bytes(&std::fs::read(&path).unwrap()).unwrap();
+            let sum: u64 = golden.registers.iter().map(|r| *r as u64).sum();
+            assert_eq!(sum, register_sum, "{}", path);
+            assert_eq!(golden.count(), estimate, "{}", path);
+            // the default hasher still maps the same values to the same registers,
+            // AES-NI builds (e.g. `-C target-cpu=native`) use another ahash implementation
+            if cfg!(not(all(
+                any(target_arch = "x86", target_arch = "x86_64"),
+                target_feature = "aes"
+            ))) {
+                assert!(golden == hll, "{} isn't built by the default hasher", path);
+            }
+        }
+
+        check::<4>(100, 56, 59);
+        check::<14>(0, 0, 0);
+        check::<14>(100, 215, 100);
+        check::<14>(10_000, 16781, 10111);
+        check::<14>(1_000_000, 119262, 1002077);
+        check::<18>(1000, 2012, 998);
+    }
+
+    #[test]
+    fn test_merge_from_slice() {
+        let mut hll = HyperLogLog::<14>::new();
+        for i in 0..1000 {
+            hll.add_object(&i);
+        }
+
+        for n in [0, 100, 10_000, 100_000] {
+            let mut other = HyperLogLog::<14>::new();
+            for i in 500..500 + n {
+                other.add_object(&i);
+            }
+
+            let mut expected = hll.clone();
+            expected.merge(&other);
+            let mut merged = hll.clone();
+            merged.merge_from_slice(&other.to_bytes()).unwrap();
+            assert_eq!(merged, expected);
+        }
+
+        // invalid bytes leave the sketch untouched
+        let mut other = HyperLogLog::<14>::new();
+        other.add_object(&"a");
+        let mut bytes = other.to_bytes();
+        bytes.push(0);
+        let mut merged = hll.clone();
+        assert_eq!(
+            merged.merge_from_slice(&bytes),
+            Err(HllError::TrailingBytes(1))
+        );
+        assert_eq!(merged, hll);
+    }
+
+    #[test]
+    fn test_serialize_into() {
+        let mut buf = vec![];
+        let mut hll = HyperLogLog::<14>::new();
+        let mut representations = vec![];
+        for n in [0, 100, 10_000, 1_000_000] {
+            for i in 0..n {
+                hll.add_object(&i);
+            }
+            let bytes = hll.to_bytes();
+            assert_eq!(hll.serialized_size(), bytes.len());
+            representations.push(bytes[7]);
+
+            // appended, the buffer is reused
+            buf.clear();
+            buf.push(42);
+            hll.serialize_into(&mut buf);
+            assert_eq!(buf[0], 42);
+            assert_eq!(buf[1..], bytes);
+        }
+        assert_eq!(
+            representations,
+            [
+                super::REPR_EMPTY,
+                super::REPR_SPARSE,
+                super::REPR_COMPRESSED,
+                super::REPR_DENSE
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fold() {
+        let mut hll = HyperLogLog::<16>::new();
+        for n in [0, 100, 1000, 100_000] {
+            for i in 0..n {
+                hll.add_object(&i);
+            }
+            let expected = hll.fold::<12>().unwrap();
+            for bytes in [hll.to_bytes(), hll.to_bytes_with_checksum()] {
+                assert_eq!(HyperLogLog::<12>::from_bytes(&bytes).unwrap(), expected);
+                assert_eq!(
+                    HyperLogLog::<12>::count_from_serialized(&bytes).unwrap(),
+                    expected.count()
+                );
+                let mut merged = HyperLogLog::<12>::new();
+                merged.add_object(&-1);
+                merged.merge_from_slice(&bytes).unwrap();
+                let mut expected = expected.clone();
+                expected.add_object(&-1);
+                assert_eq!(merged, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(super::crc32(b"123456789"), 0xcbf43926);
+
+        let mut hll = HyperLogLog::<14>::new();
+        for n in [0, 100, 10_000] {
+            for i in 0..n {
+                hll.add_object(&i);
+            }
+            let bytes = hll.to_bytes_with_checksum();
+            assert_eq!(bytes.len(), hll.to_bytes().len() + 4);
+            assert_eq!(HyperLogLog::<14>::from_bytes(&bytes).unwrap(), hll);
+            assert_eq!(
+                HyperLogLog::<14>::count_from_serialized(&bytes).unwrap(),
+                hll.count()
+            );
+        }
+
+        // a flipped bit in the payload is detected
+        let mut bytes = hll.to_bytes_with_checksum();
+        bytes[100] ^= 1;
+        assert!(matches!(
+            HyperLogLog::<14>::from_bytes(&bytes),
+            Err(HllError::ChecksumMismatch { .. })
+        ));
+        let mut merged = hll.clone();
+        assert!(merged.merge_from_slice(&bytes).is_err());
+
+        // header only
+        let bytes = hll.to_bytes_with_checksum();
+        assert_eq!(
+            HyperLogLog::<14>::from_bytes(&bytes[..10]),
+            Err(HllError::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn test_delta() {
+        let mut hll = HyperLogLog::<14>::new();
+        for i in 0..100_000 {
+            hll.add_object(&i);
+        }
+        let checkpoint = hll.clone();
+        for i in 100_000..100_100 {
+            hll.add_object(&i);
+        }
+
+        let delta = hll.serialize_delta(&checkpoint);
+        assert_eq!(delta[7], super::REPR_SPARSE);
+        assert!(delta.len() < 100 * 3 + 16);
+        let mut restored = checkpoint.clone();
+        restored.apply_delta(&delta).unwrap();
+        assert_eq!(restored, hll);
+
+        // nothing changed
+        let delta = hll.serialize_delta(&hll);
+        assert_eq!(delta.len(), super::HEADER_SIZE);
+        restored.apply_delta(&delta).unwrap();
+        assert_eq!(restored, hll);
+    }
+
+    #[test]
+    fn test_count_from_serialized() {
+        let mut hll = HyperLogLog::<14>::new();
+        for n in [0, 100, 10_000, 100_000] {
+            for i in 0..n {
+                hll.add_object(&i);
+            }
+            let count = HyperLogLog::<14>::count_from_serialized(&hll.to_bytes()).unwrap();
+            assert_eq!(count, hll.count());
+        }
+
+        assert_eq!(
+            HyperLogLog::<14>::count_from_serialized(b"SHLL"),
+            Err(HllError::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn test_sparse_unordered() {
+        let sparse = |indexes: &[u16]| {
+            let mut bytes = b"SHLL\x01\x04\x01\x01".to_vec();
+            bytes.extend_from_slice(&(indexes.len() as u32).to_le_bytes());
+            for index in indexes {
+                bytes.extend_from_slice(&index.to_le_bytes());
+                bytes.push(1);
+            }
+            bytes
+        };
+        let mut hll = HyperLogLog::<4>::new();
+        hll.registers[2] = 1;
+        hll.registers[5] = 1;
+        assert_eq!(sparse(&[2, 5]), hll.to_bytes());
+
+        for indexes in [&[0; 40][..], &[2, 2], &[5, 2]] {
+            let bytes = sparse(indexes);
+            for err in [
+                HyperLogLog::<4>::count_from_serialized(&bytes).unwrap_err(),
+                HyperLogLog::<4>::from_bytes(&bytes).unwrap_err(),
+                HyperLogLog::<4>::new()
+                    .merge_from_slice(&bytes)
+                    .unwrap_err(),
+            ] {
+                assert!(
+                    matches!(&err, HllError::Malformed(reason) if reason.contains("not larger")),
+                    "{:?}",
+                    err
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_zero_entries() {
+        // one rank-3 register, then empty entries which must not count as registers
+        let mut sparse = b"SHLL\x01\x0e\x01".to_vec();
+        sparse.push(super::REPR_SPARSE);
+        sparse.extend_from_slice(&2001_u32.to_le_bytes());
+        for index in 0..=2000_u16 {
+            sparse.extend_from_slice(&index.to_le_bytes());
+            sparse.push(if index == 0 { 3 } else { 0 });
+        }
+        let mut compressed = b"SHLL\x01\x0e\x01".to_vec();
+        compressed.push(super::REPR_COMPRESSED);
+        compressed.extend_from_slice(&[0; 2000]);
+        compressed.push(3);
+
+        for bytes in [sparse, compressed] {
+            let hll = HyperLogLog::<14>::from_bytes(&bytes).unwrap();
+            assert_eq!(hll.num_empty_registers(), (1 << 14) - 1);
+            assert_eq!(
+                HyperLogLog::<14>::count_from_serialized(&bytes).unwrap(),
+                hll.count()
+            );
+            let mut merged = HyperLogLog::<14>::new();
+            merged.merge_from_slice(&bytes).unwrap();
+            assert_eq!(merged, hll);
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut hll = HyperLogLog::<14>::new();
+        hll.add_object(&1);
+        let bytes = hll.to_bytes();
+
+        assert_eq!(
+            HyperLogLog::<14>::from_bytes(&bytes[..3]),
+            Err(HllError::UnexpectedEof)
+        );
+        assert_eq!(
+            HyperLogLog::<14>::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(HllError::UnexpectedEof)
+        );
+        assert_eq!(
+            HyperLogLog::<16>::from_bytes(&bytes),
+            Err(HllError::PrecisionMismatch {
+                expected: 16,
+                actual: 14
+            })
+        );
+        let mut invalid = bytes.clone();
+        invalid[5] = 19;
+        assert_eq!(
+            HyperLogLog::<14>::from_bytes(&invalid),
+            Err(HllError::InvalidPrecision(19))
+        );
+
+        let mut invalid = bytes.clone();
+        invalid[0] = b'X';
+        assert_eq!(
+            HyperLogLog::<14>::from_bytes(&invalid),
+            Err(HllError::InvalidMagic)
+        );
+
+        let mut invalid = bytes.clone();
+        invalid[4] = 2;
+        assert_eq!(
+            HyperLogLog::<14>::from_bytes(&invalid),
+            Err(HllError::UnsupportedVersion(2))
+        );
+
+        let mut invalid = bytes.clone();
+        invalid[6] = 0;
+        assert_eq!(
+            HyperLogLog::<14>::from_bytes(&invalid),
+            Err(HllError::HasherMismatch {
+                expected: 1,
+                actual: 0
+            })
+        );
+
+        let mut invalid = bytes.clone();
+        invalid[7] = 9;
+        assert_eq!(
+            HyperLogLog::<14>::from_bytes(&invalid),
+            Err(HllError::UnsupportedRepresentation(9))
+        );
+        // reserved flag
+        invalid[7] = 0x40 | super::REPR_SPARSE;
+        assert_eq!(
+            HyperLogLog::<14>::from_bytes(&invalid),
+            Err(HllError::UnsupportedRepresentation(0x41))
+        );
+
+        let mut invalid = bytes.clone();
+        invalid.push(0);
+        assert_eq!(
+            HyperLogLog::<14>::from_bytes(&invalid),
+            Err(HllError::TrailingBytes(1))
+        );
+
+        // sparse register index out of range
+        let mut invalid = bytes.clone();
+        invalid[12] = 0xff;
+        invalid[13] = 0xff;
+        assert_eq!(
+            HyperLogLog::<14>::from_bytes(&invalid),
+            Err(HllError::InvalidRegisterIndex {
+                index: 0xffff,
+                len: 1 << 14
+            })
+        );
+    }
+}
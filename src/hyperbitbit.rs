@@ -0,0 +1,282 @@
+//! # HyperBitBit
+//!
+//! Sedgewick's HyperBitBit, "Cardinality Estimation" (AofA 2016): two 64-bit words and a
+//! byte of state. A hash sets bit `hash % 64` of the first word if its run of trailing
+//! ones (after the 6 index bits) is longer than `lg_n`, and of the second word if it's
+//! longer than `lg_n + 1`. Once half of the first word is set, the second word replaces
+//! it and `lg_n` grows by one.
+//!
+//! It is meant for millions of counters of low value each, e.g. one per user, where even a
+//! sparse HyperLogLog is too large. The trade-offs:
+//! - the relative error is around 15-20%, a HyperLogLog with `P = 4` (16 registers) has
+//!   26% but is exact-ish at small cardinalities.
+//! - the estimate is only meaningful above a few thousand items, below it's at least
+//!   `2^(5 + 5.63)`, about 1600, once an item was added.
+//! - merges are approximate, see [`HyperBitBit::merge`].
+//!
+//! The estimate is `2^(lg_n + 5.63 + bits / 32)`, with `bits` the number of bits set in
+//! the first word. The paper has `5.4`, which underestimates by about 15% in simulations
+//! of this implementation, `5.63` is calibrated on them.
+//!
+//! Binary format, all integers are little-endian:
+//!
+//! | offset | size | field                                         |
+//! |--------|------|-----------------------------------------------|
+//! | 0      | 4    | magic `b"SHBB"`                               |
+//! | 4      | 1    | format version, currently `1`                 |
+//! | 5      | 1    | hasher id, see [`crate::Hasher::ID`]          |
+//! | 6      | 1    | `lg_n`                                        |
+//! | 7      | 8    | first word                                    |
+//! | 15     | 8    | second word                                   |
+
+use core::hash::Hash;
+
+use crate::format::SliceReader;
+use crate::CardinalitySketch;
+use crate::Hasher;
+use crate::HllError;
+
+/// Initial `lg_n`, as in the paper
+const INITIAL_LG_N: u8 = 5;
+
+const MAGIC: &[u8; 4] = b"SHBB";
+const VERSION: u8 = 1;
+const SERIALIZED_SIZE: usize = 23;
+
+/// A HyperBitBit sketch, 17 bytes of state, see the [module](self) documentation
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct HyperBitBit {
+    lg_n: u8,
+    sketch: u64,
+    sketch2: u64,
+}
+
+impl Default for HyperBitBit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HyperBitBit {
+    pub const fn new() -> Self {
+        Self {
+            lg_n: INITIAL_LG_N,
+            sketch: 0,
+            sketch2: 0,
+        }
+    }
+
+    #[inline]
+    pub fn add_hash(&mut self, hash: u64) {
+        let bit = 1 << (hash & 63);
+        let run = (!(hash >> 6)).trailing_zeros() as u8;
+        if run > self.lg_n {
+            self.sketch |= bit;
+            if run > self.lg_n + 1 {
+                self.sketch2 |= bit;
+            }
+            self.promote();
+        }
+    }
+
+    #[inline]
+    pub fn add_object<T: Hash>(&mut self, obj: &T) {
+        self.add_object_by_hasher::<T, ahash::AHasher>(obj);
+    }
+
+    #[inline]
+    pub fn add_object_by_hasher<T: Hash, H: Hasher>(&mut self, obj: &T) {
+        self.add_hash(H::hll_hash(obj));
+    }
+
+    /// The second word replaces the first one once half of it is set
+    #[inline]
+    fn promote(&mut self) {
+        while self.sketch.count_ones() > 31 {
+            self.sketch = self.sketch2;
+            self.sketch2 = 0;
+            self.lg_n += 1;
+        }
+    }
+
+    /// Merge the other [`HyperBitBit`] into this one. Approximate: the sketch with the
+    /// smaller `lg_n` only contributes the bits of its words kept at the larger `lg_n`.
+    pub fn merge(&mut self, other: &Self) {
+        let (high, low) = if self.lg_n >= other.lg_n {
+            (*self, *other)
+        } else {
+            (*other, *self)
+        };
+        *self = high;
+        match high.lg_n - low.lg_n {
+            0 => {
+                self.sketch |= low.sketch;
+                self.sketch2 |= low.sketch2;
+            }
+            1 => self.sketch |= low.sketch2,
+            _ => {}
+        }
+        self.promote();
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.sketch == 0 && self.lg_n == INITIAL_LG_N
+    }
+
+    /// Guess the number of unique elements seen by the sketch, see the [module](self)
+    /// documentation for its accuracy.
+    pub fn count(&self) -> usize {
+        if self.is_empty() {
+            return 0;
+        }
+        let bits = self.sketch.count_ones() as f64;
+        (self.lg_n as f64 + 5.63 + bits / 32.0).exp2().round() as usize
+    }
+
+    /// Serialize into the binary format described in the [module](self) documentation,
+    /// tagged with the id of the default hasher.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_with_hasher::<ahash::AHasher>()
+    }
+
+    /// Same as [`Self::to_bytes`], but tagged with the id of hasher `H`
+    pub fn to_bytes_with_hasher<H: Hasher>(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(SERIALIZED_SIZE);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.push(H::ID);
+        bytes.push(self.lg_n);
+        bytes.extend_from_slice(&self.sketch.to_le_bytes());
+        bytes.extend_from_slice(&self.sketch2.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, HllError> {
+        Self::from_bytes_with_hasher::<ahash::AHasher>(bytes)
+    }
+
+    /// Deserialize a sketch built with hasher `H`
+    pub fn from_bytes_with_hasher<H: Hasher>(bytes: &[u8]) -> Result<Self, HllError> {
+        let mut reader = SliceReader::new(bytes);
+        if reader.read_slice(MAGIC.len())? != MAGIC {
+            return Err(HllError::InvalidMagic);
+        }
+        let version = reader.read_u8()?;
+        if version != VERSION {
+            return Err(HllError::UnsupportedVersion(version));
+        }
+        let hasher = reader.read_u8()?;
+        if hasher != H::ID {
+            return Err(HllError::HasherMismatch {
+                expected: H::ID,
+                actual: hasher,
+            });
+        }
+        let hbb = Self {
+            lg_n: reader.read_u8()?,
+            sketch: reader.read_u64()?,
+            sketch2: reader.read_u64()?,
+        };
+        reader.finish()?;
+        // the second word is a subset of the first one, which is at most half set
+        if hbb.lg_n < INITIAL_LG_N
+            || hbb.lg_n > 64
+            || hbb.sketch.count_ones() > 31
+            || hbb.sketch2 & !hbb.sketch != 0
+        {
+            return Err(HllError::Malformed(format!("hyperbitbit: {:?}", hbb)));
+        }
+        Ok(hbb)
+    }
+}
+
+impl CardinalitySketch for HyperBitBit {
+    #[inline]
+    fn add_hash(&mut self, hash: u64) {
+        HyperBitBit::add_hash(self, hash);
+    }
+
+    fn merge(&mut self, other: &Self) {
+        HyperBitBit::merge(self, other);
+    }
+
+    fn count(&self) -> usize {
+        HyperBitBit::count(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        HyperBitBit::is_empty(self)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        HyperBitBit::to_bytes(self)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, HllError> {
+        HyperBitBit::from_bytes(bytes)
+    }
+
+    fn memory_bytes(&self) -> usize {
+        core::mem::size_of::<Self>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hyperbitbit(trial: u64, items: core::ops::Range<u64>) -> HyperBitBit {
+        let mut hbb = HyperBitBit::new();
+        for i in items {
+            hbb.add_object(&(trial, i));
+        }
+        hbb
+    }
+
+    #[test]
+    fn test_hyperbitbit() {
+        assert_eq!(HyperBitBit::new().count(), 0);
+        assert!(HyperBitBit::new().is_empty());
+
+        let trials = 50;
+        for n in [20_000_u64, 200_000] {
+            let ratios: Vec<f64> = (0..trials)
+                .map(|trial| hyperbitbit(trial, 0..n).count() as f64 / n as f64)
+                .collect();
+            let mean = ratios.iter().sum::<f64>() / trials as f64;
+            let error =
+                (ratios.iter().map(|r| (r - 1.0).powi(2)).sum::<f64>() / trials as f64).sqrt();
+            assert!((mean - 1.0).abs() < 0.1, "{}", mean);
+            assert!(error < 0.3, "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut merged = hyperbitbit(0, 0..50_000);
+        merged.merge(&hyperbitbit(0, 50_000..200_000));
+        let ratio = merged.count() as f64 / 200_000.0;
+        assert!((0.5..2.0).contains(&ratio), "{}", ratio);
+
+        let hbb = hyperbitbit(0, 0..200_000);
+        let mut empty = HyperBitBit::new();
+        empty.merge(&hbb);
+        assert_eq!(empty, hbb);
+        assert_eq!(core::mem::size_of::<HyperBitBit>(), 24);
+    }
+
+    #[test]
+    fn test_serialize() {
+        let hbb = hyperbitbit(0, 0..100_000);
+        let mut bytes = hbb.to_bytes();
+        assert_eq!(bytes.len(), SERIALIZED_SIZE);
+        assert_eq!(HyperBitBit::from_bytes(&bytes).unwrap(), hbb);
+
+        bytes[6] = 4;
+        assert!(matches!(
+            HyperBitBit::from_bytes(&bytes).unwrap_err(),
+            HllError::Malformed(_)
+        ));
+    }
+}
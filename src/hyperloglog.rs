@@ -5,19 +5,70 @@
 //! 1. https://github.com/crepererum/pdatastructs.rs/blob/3997ed50f6b6871c9e53c4c5e0f48f431405fc63/src/hyperloglog.rs
 //! 2. https://github.com/apache/arrow-datafusion/blob/f203d863f5c8bc9f133f6dd9b2e34e57ac3cdddc/datafusion/physical-expr/src/aggregate/hyperloglog.rs
 
-use crate::Hasher;
+use alloc::vec;
+use alloc::vec::Vec;
 use core::hash::Hash;
 
+use crate::math;
+use crate::metrics;
+use crate::Hasher;
+use crate::HllError;
+
 /// By default, we use 2**14 registers like redis
 pub const DEFAULT_P: usize = 14_usize;
 
+/// The smallest supported P
+pub const MIN_P: usize = 4_usize;
+
+/// The largest supported P
+pub const MAX_P: usize = 18_usize;
+
+/// Registers merged from all the sources at once by `merge_many`, small enough to stay
+/// in the L1 cache
+const MERGE_BLOCK_SIZE: usize = 4096;
+
+/// Sources merged block by block together by `merge_many`, the tiles of a batch are
+/// merged before the next batch is pulled from the iterator
+const MERGE_BATCH_SIZE: usize = 1024;
+
+/// `alpha * m^2` of the estimate for every precision, with `alpha = 1 / (2 * ln(2))` for
+/// `m` going to infinity
+const ESTIMATE_NUMERATORS: [f64; MAX_P + 1] = {
+    let mut table = [0.0; MAX_P + 1];
+    let mut p = 0;
+    while p <= MAX_P {
+        let m = (1_u64 << p) as f64;
+        table[p] = 0.5 / core::f64::consts::LN_2 * m * m;
+        p += 1;
+    }
+    table
+};
+
+/// `2^-k` for every register value `k`
+const INV_POW2: [f64; 65] = {
+    let mut table = [1.0; 65];
+    let mut k = 1;
+    while k <= 64 {
+        table[k] = table[k - 1] * 0.5;
+        k += 1;
+    }
+    table
+};
+
 /// Note: We don't make HyperLogLog as static struct by keeping `PhantomData<T>`
 /// Callers should take care of its hash function to be unchanged.
 /// P is the bucket number, must be [4, 18]
 /// Q = 64 - P
 /// Register num is 1 << P
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "diesel",
+    derive(diesel::expression::AsExpression, diesel::deserialize::FromSqlRow),
+    diesel(sql_type = diesel::sql_types::Binary)
+)]
 pub struct HyperLogLog<const P: usize = DEFAULT_P> {
+    /// Invariant: always `1 << P` registers, every constructor checks it and the vector
+    /// is never resized, `add_hash` relies on it to skip the bounds check
     pub(crate) registers: Vec<u8>,
 }
 
@@ -27,33 +78,212 @@ impl<const P: usize> Default for HyperLogLog<P> {
     }
 }
 
+/// Index and value of a non-zero register of precision `from` in a sketch of precision `to`.
+/// The `from - to` high bits of the index are the low bits of the rest of the hash, the
+/// value only grows by `from - to` if they are all zero.
+#[inline]
+pub(crate) fn fold_register(from: usize, to: usize, index: usize, value: u8) -> (usize, u8) {
+    let high = index >> to;
+    let value = if high != 0 {
+        high.trailing_zeros() as u8 + 1
+    } else {
+        value + (from - to) as u8
+    };
+    (index & ((1 << to) - 1), value)
+}
+
+impl<const P: usize> TryFrom<Vec<u8>> for HyperLogLog<P> {
+    type Error = HllError;
+
+    /// Reconstruct a [`HyperLogLog`] from its raw dense registers
+    fn try_from(registers: Vec<u8>) -> Result<Self, Self::Error> {
+        Self::try_with_registers(registers)
+    }
+}
+
+impl<const P: usize> TryFrom<&[u8]> for HyperLogLog<P> {
+    type Error = HllError;
+
+    /// Reconstruct a [`HyperLogLog`] from its raw dense registers
+    fn try_from(registers: &[u8]) -> Result<Self, Self::Error> {
+        Self::try_with_registers(registers.to_vec())
+    }
+}
+
 impl<const P: usize> HyperLogLog<P> {
-    /// note that this method should not be invoked in untrusted environment
+    /// Number of registers, `1 << P`
+    pub const NUM_REGISTERS: usize = 1 << P;
+
+    /// Bits of the hash left after the register index
+    const Q: usize = 64 - P;
+
+    /// Selects the register index from the low P bits of the hash
+    pub(crate) const REGISTER_MASK: u64 = (1 << P) - 1;
+
+    /// Evaluated when [`Self::new`] or [`Self::with_registers`] is monomorphized,
+    /// so an invalid P fails the build rather than panicking at runtime.
+    pub(crate) const VALID_PRECISION: () = assert!(
+        P >= MIN_P && P <= MAX_P,
+        "P must be larger or equal than 4 and smaller or equal than 18"
+    );
+
+    /// Invalid P is rejected at compile time:
+    ///
+    /// ```compile_fail
+    /// let hll = simple_hll::HyperLogLog::<3>::new();
+    /// ```
     pub fn new() -> Self {
-        assert!(
-            (P >= 4) & (P <= 18),
-            "P ({}) must be larger or equal than 4 and smaller or equal than 18",
-            P
-        );
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID_PRECISION;
 
         Self {
             registers: vec![0; 1 << P],
         }
     }
 
+    /// Fallible version of [`Self::new`], returns an error if P is out of range
+    /// instead of failing the build
+    pub fn try_new() -> Result<Self, HllError> {
+        Self::check_precision()?;
+
+        Ok(Self {
+            registers: vec![0; 1 << P],
+        })
+    }
+
+    /// note that this method panics if the length of registers is not `1 << P` or a
+    /// register is larger than `64 - P + 1`, use [`Self::try_with_registers`] instead
     pub fn with_registers(registers: Vec<u8>) -> Self {
-        assert_eq!(registers.len(), Self::number_registers());
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID_PRECISION;
+
+        match Self::try_with_registers(registers) {
+            Ok(hll) => hll,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Fallible version of [`Self::with_registers`], checks the number of registers and
+    /// that every value is at most `64 - P + 1`
+    pub fn try_with_registers(registers: Vec<u8>) -> Result<Self, HllError> {
+        Self::check_precision()?;
+        Self::check_register_length(&registers)?;
+        Self::check_register_values(&registers)?;
+
+        Ok(Self { registers })
+    }
+
+    /// Same as [`Self::with_registers`], but register values are not checked, for
+    /// registers known to be valid, e.g. read back from trusted storage, to skip the scan.
+    /// A value larger than `64 - P + 1` doesn't break memory safety but gives absurd
+    /// estimates, see [`Self::validate`]. Still panics if the length of registers is not
+    /// `1 << P`.
+    pub fn with_registers_unchecked(registers: Vec<u8>) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID_PRECISION;
+
+        match Self::check_register_length(&registers) {
+            Ok(()) => Self { registers },
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[inline]
+    fn check_register_length(registers: &[u8]) -> Result<(), HllError> {
+        if registers.len() != Self::NUM_REGISTERS {
+            return Err(HllError::InvalidRegisterLength {
+                expected: Self::NUM_REGISTERS,
+                actual: registers.len(),
+            });
+        }
+        Ok(())
+    }
 
-        Self { registers }
+    /// View of the dense registers, one byte per register.
+    /// It can be turned back into a [`HyperLogLog`] with `TryFrom<&[u8]>`
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.registers
+    }
+
+    #[inline]
+    fn check_precision() -> Result<(), HllError> {
+        if (MIN_P..=MAX_P).contains(&P) {
+            Ok(())
+        } else {
+            Err(HllError::InvalidPrecision(P))
+        }
     }
 
     /// Adds an hash to the HyperLogLog.
     /// hash value is dertermined by caller
     #[inline]
+    #[cfg_attr(no_panic, no_panic::no_panic)]
     pub fn add_hash(&mut self, hash: u64) {
-        let index = (hash & Self::register_mask()) as usize;
-        let one_position = ((hash >> P) | (1_u64 << Self::q())).trailing_zeros() + 1;
-        self.registers[index] = self.registers[index].max(one_position as u8);
+        self.update_register(hash);
+        metrics::inserts(1);
+    }
+
+    /// [`Self::add_hash`] without the metrics, so batches are recorded once
+    #[inline]
+    #[cfg_attr(no_panic, no_panic::no_panic)]
+    fn update_register(&mut self, hash: u64) {
+        let index = (hash & Self::REGISTER_MASK) as usize;
+        let one_position = Self::rank(hash);
+        debug_assert_eq!(self.registers.len(), Self::NUM_REGISTERS);
+        // SAFETY: the index is masked to P bits and there are `1 << P` registers, see the
+        // invariant of `registers`
+        let register = unsafe { self.registers.get_unchecked_mut(index) };
+        *register = (*register).max(one_position);
+    }
+
+    /// Updates register `index` with the `rank` of a hash, for callers which already split
+    /// their hashes, e.g. with the index and rank computed once and reused for several
+    /// sketches.
+    ///
+    /// # Safety
+    ///
+    /// `index` must be smaller than [`Self::NUM_REGISTERS`], and `rank` must not be larger
+    /// than `64 - P + 1`, as computed by [`Self::add_hash`].
+    #[inline]
+    pub unsafe fn add_hash_unchecked(&mut self, index: usize, rank: u8) {
+        debug_assert!(index < Self::NUM_REGISTERS);
+        debug_assert!(rank <= Self::max_register_value());
+        // SAFETY: the caller guarantees the index is in bounds
+        let register = unsafe { self.registers.get_unchecked_mut(index) };
+        *register = (*register).max(rank);
+        metrics::inserts(1);
+    }
+
+    /// Adds many hashes, the same as calling [`Self::add_hash`] for each of them
+    #[cfg_attr(no_panic, no_panic::no_panic)]
+    pub fn add_hashes(&mut self, hashes: &[u64]) {
+        for hash in hashes {
+            self.update_register(*hash);
+        }
+        metrics::inserts(hashes.len());
+    }
+
+    /// Like [`Self::add_hashes`], but the hashes are first sorted by register index in
+    /// place, so registers are updated in order and only once per index. Improves the
+    /// locality of very large batches added to high-P sketches.
+    pub fn add_hashes_sorted(&mut self, hashes: &mut [u64]) {
+        hashes.sort_unstable_by_key(|hash| hash & Self::REGISTER_MASK);
+        for run in hashes.chunk_by(|a, b| (a ^ b) & Self::REGISTER_MASK == 0) {
+            let Some(first) = run.first() else { continue };
+            let index = (first & Self::REGISTER_MASK) as usize;
+            let rank = run.iter().map(|hash| Self::rank(*hash)).max().unwrap_or(0);
+            if let Some(register) = self.registers.get_mut(index) {
+                *register = (*register).max(rank);
+            }
+        }
+        metrics::inserts(hashes.len());
+    }
+
+    /// Position of the first set bit of the hash bits left after the register index
+    #[inline]
+    pub(crate) fn rank(hash: u64) -> u8 {
+        (((hash >> P) | (1_u64 << Self::Q)).trailing_zeros() + 1) as u8
     }
 
     /// Adds an object to the HyperLogLog.
@@ -69,63 +299,264 @@ impl<const P: usize> HyperLogLog<P> {
     }
 
     /// Merge the other [`HyperLogLog`] into this one
+    #[cfg_attr(no_panic, no_panic::no_panic)]
     pub fn merge(&mut self, other: &Self) {
-        for i in 0..self.registers.len() {
-            self.registers[i] = self.registers[i].max(other.registers[i]);
+        // both have `1 << P` registers, see the invariant of `registers`
+        debug_assert_eq!(self.registers.len(), other.registers.len());
+        crate::simd::max_assign(&mut self.registers, &other.registers);
+        metrics::merges(1);
+    }
+
+    /// Merge raw registers into this sketch without validating them, e.g. registers of a
+    /// pool of sketches all built by this crate with the same P. `registers` is read as
+    /// [`Self::NUM_REGISTERS`] bytes whatever its length, so the merge loop runs a constant
+    /// number of times without comparing the lengths.
+    ///
+    /// # Safety
+    ///
+    /// `registers.len()` must be [`Self::NUM_REGISTERS`] (`1 << P`), a shorter slice is read
+    /// out of bounds. No register may be larger than `64 - P + 1`, as accepted by
+    /// [`Self::try_with_registers`] and `TryFrom<Vec<u8>>`.
+    pub unsafe fn merge_unchecked(&mut self, registers: &[u8]) {
+        debug_assert_eq!(registers.len(), Self::NUM_REGISTERS);
+        // SAFETY: the caller guarantees `registers` has `1 << P` registers
+        let registers =
+            unsafe { core::slice::from_raw_parts(registers.as_ptr(), Self::NUM_REGISTERS) };
+        crate::simd::max_assign(&mut self.registers, registers);
+        metrics::merges(1);
+    }
+
+    /// Merge many [`HyperLogLog`]s into this one, equivalent to calling [`Self::merge`] for
+    /// each of them. Registers are merged block by block from all the sources, so the
+    /// destination block stays in the L1 cache instead of being reloaded for every source.
+    /// Sources are taken in batches, any number of them can be streamed in.
+    pub fn merge_many<'a>(&mut self, others: impl IntoIterator<Item = &'a Self>) {
+        let mut others = others.into_iter();
+        let mut batch: Vec<&Self> = Vec::with_capacity(MERGE_BATCH_SIZE);
+        loop {
+            batch.clear();
+            batch.extend((&mut others).take(MERGE_BATCH_SIZE));
+            for (offset, block) in self
+                .registers
+                .chunks_mut(MERGE_BLOCK_SIZE)
+                .enumerate()
+                .map(|(i, block)| (i * MERGE_BLOCK_SIZE, block))
+            {
+                for other in &batch {
+                    if let Some(src) = other.registers.get(offset..offset + block.len()) {
+                        crate::simd::max_assign(block, src);
+                    }
+                }
+            }
+            metrics::merges(batch.len());
+            if batch.len() < MERGE_BATCH_SIZE {
+                return;
+            }
+        }
+    }
+
+    /// The union of all the sketches, e.g. to reduce thousands of per-user sketches into
+    /// one, see [`Self::merge_many`]
+    pub fn union<'a>(sketches: impl IntoIterator<Item = &'a Self>) -> Self {
+        let mut hll = Self::new();
+        hll.merge_many(sketches);
+        hll
+    }
+
+    /// Fold the registers into a sketch of a lower precision `Q`, the result is the same as
+    /// adding the same hashes to a `HyperLogLog<Q>`. `Q` must not be larger than `P`.
+    pub fn fold<const Q: usize>(&self) -> Result<HyperLogLog<Q>, HllError> {
+        if Q > P {
+            return Err(HllError::PrecisionMismatch {
+                expected: Q,
+                actual: P,
+            });
         }
+        let mut registers = vec![0; HyperLogLog::<Q>::NUM_REGISTERS];
+        for (index, value) in self.registers.iter().enumerate() {
+            if *value != 0 {
+                let (index, value) = fold_register(P, Q, index, *value);
+                registers[index] = registers[index].max(value);
+            }
+        }
+        HyperLogLog::<Q>::try_with_registers(registers)
+    }
+
+    /// The registers of a sketch of precision `p`, folded into `P` like [`Self::fold`] if
+    /// `p` is higher, a lower `p` is an error like in the binary format.
+    #[cfg(any(feature = "serde", feature = "borsh"))]
+    pub(crate) fn try_from_precision(p: usize, registers: Vec<u8>) -> Result<Self, HllError> {
+        if p > MAX_P {
+            return Err(HllError::InvalidPrecision(p));
+        }
+        if p < P {
+            return Err(HllError::PrecisionMismatch {
+                expected: P,
+                actual: p,
+            });
+        }
+        if p == P {
+            return Self::try_with_registers(registers);
+        }
+        let m = 1 << p;
+        if registers.len() != m {
+            return Err(HllError::InvalidRegisterLength {
+                expected: m,
+                actual: registers.len(),
+            });
+        }
+        let max = (64 - p + 1) as u8;
+        let mut folded = vec![0; Self::NUM_REGISTERS];
+        for (index, value) in registers.into_iter().enumerate() {
+            if value > max {
+                return Err(HllError::InvalidRegisterValue { index, value, max });
+            }
+            if value != 0 {
+                let (index, value) = fold_register(p, P, index, value);
+                folded[index] = folded[index].max(value);
+            }
+        }
+        Self::try_with_registers(folded)
     }
 
     /// Get the register histogram (each value in register index into
     /// the histogram
     #[inline]
     fn get_histogram(&self) -> [u32; 64] {
-        let mut histogram = [0; 64];
-        // hopefully this can be unrolled
-        for r in &self.registers {
-            histogram[*r as usize] += 1;
-        }
-        histogram
+        crate::simd::histogram(&self.registers)
     }
 
     /// Guess the number of unique elements seen by the HyperLogLog.
     #[inline]
+    #[cfg_attr(no_panic, no_panic::no_panic)]
     pub fn count(&self) -> usize {
-        let histogram = self.get_histogram();
-        let m = Self::number_registers() as f64;
-        let q = Self::q();
-        let mut z = m * hll_tau((m - histogram[q + 1] as f64) / m);
-        for i in histogram[1..=q].iter().rev() {
-            z += *i as f64;
-            z *= 0.5;
-        }
-        z += m * hll_sigma(histogram[0] as f64 / m);
+        metrics::time_count(|| Self::estimate(&self.get_histogram()))
+    }
+
+    /// The counts of many sketches, e.g. for a report over tens of thousands of them.
+    /// Histograms are built by the vectorized kernels on the stack, the only allocation is
+    /// the result, see [`Self::count_many_into`] to reuse it.
+    pub fn count_many<'a>(sketches: impl IntoIterator<Item = &'a Self>) -> Vec<usize> {
+        let mut counts = vec![];
+        Self::count_many_into(sketches, &mut counts);
+        counts
+    }
 
-        (0.5 / 2_f64.ln() * m * m / z).round() as usize
+    /// Like [`Self::count_many`], the counts are appended to `counts`
+    pub fn count_many_into<'a>(
+        sketches: impl IntoIterator<Item = &'a Self>,
+        counts: &mut Vec<usize>,
+    ) {
+        let sketches = sketches.into_iter();
+        counts.reserve(sketches.size_hint().0);
+        counts.extend(sketches.map(|hll| hll.count()));
     }
 
+    /// Estimate the cardinality from the register histogram, `2^-k` and `alpha * m^2` come
+    /// from const tables. `tau` and `sigma` are only evaluated if some registers are
+    /// saturated or empty, they are `0` otherwise, which is the common case of large
+    /// cardinalities.
     #[inline]
-    fn q() -> usize {
-        64 - P
+    pub(crate) fn estimate(histogram: &[u32; 64]) -> usize {
+        let m = Self::NUM_REGISTERS as f64;
+        let q = Self::Q;
+        let mut z = 0.0;
+        for (count, inv_pow2) in histogram[1..=q].iter().zip(&INV_POW2[1..]) {
+            z += *count as f64 * inv_pow2;
+        }
+        if histogram[q + 1] != 0 {
+            z += m * hll_tau((m - histogram[q + 1] as f64) / m) * INV_POW2[q];
+        }
+        if histogram[0] != 0 {
+            z += m * hll_sigma(histogram[0] as f64 / m);
+        }
+
+        math::round(ESTIMATE_NUMERATORS[P] / z) as usize
     }
 
+    /// Each register stores the position of the first set bit in the `Q` hash bits,
+    /// so the value can never exceed `Q + 1`
     #[inline]
-    fn register_mask() -> u64 {
-        Self::number_registers() as u64 - 1
+    pub(crate) fn max_register_value() -> u8 {
+        (Self::Q + 1) as u8
+    }
+
+    fn check_register_values(registers: &[u8]) -> Result<(), HllError> {
+        let max = Self::max_register_value();
+        match registers.iter().position(|r| *r > max) {
+            Some(index) => Err(HllError::InvalidRegisterValue {
+                index,
+                value: registers[index],
+                max,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks every invariant of the sketch: P is in range, there are `1 << P` registers and
+    /// no register is larger than `64 - P + 1`. The in-memory sketch is always dense, there
+    /// is no other metadata. Meant for sketches from untrusted sources built through paths
+    /// which don't validate, e.g. [`Self::merge_unchecked`], returns every violation found
+    /// rather than the first one.
+    pub fn validate(&self) -> Result<(), Vec<HllError>> {
+        let mut errors = vec![];
+        if let Err(e) = Self::check_precision() {
+            errors.push(e);
+        }
+        if self.registers.len() != Self::NUM_REGISTERS {
+            errors.push(HllError::InvalidRegisterLength {
+                expected: Self::NUM_REGISTERS,
+                actual: self.registers.len(),
+            });
+        }
+        let max = Self::max_register_value();
+        errors.extend(
+            self.registers
+                .iter()
+                .enumerate()
+                .filter(|(_, value)| **value > max)
+                .map(|(index, value)| HllError::InvalidRegisterValue {
+                    index,
+                    value: *value,
+                    max,
+                }),
+        );
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
     }
 
     #[inline]
     pub fn number_registers() -> usize {
-        1 << P
+        Self::NUM_REGISTERS
     }
 
     #[inline]
     pub fn error_rate() -> f64 {
-        1.04f64 / (Self::number_registers() as f64).sqrt()
+        1.04f64 / math::sqrt(Self::NUM_REGISTERS as f64)
+    }
+
+    /// Expected relative standard error when estimating `n` distinct elements.
+    /// For small cardinalities the estimate behaves like linear counting, whose
+    /// error is `sqrt(m * (e^t - t - 1)) / n` with `t = n / m` (Whang et al.),
+    /// so we take the smaller of that and [`Self::error_rate`].
+    #[inline]
+    pub fn expected_error_at(n: usize) -> f64 {
+        if n == 0 {
+            return 0.0;
+        }
+        let m = Self::NUM_REGISTERS as f64;
+        let n = n as f64;
+        let t = n / m;
+        let linear_counting = math::sqrt(m * (math::exp(t) - t - 1.0)) / n;
+        linear_counting.min(Self::error_rate())
     }
 
     #[inline]
     pub fn max_byte_size() -> usize {
-        Self::number_registers()
+        Self::NUM_REGISTERS
     }
 
     #[inline]
@@ -172,10 +603,10 @@ fn hll_tau(x: f64) -> f64 {
         let mut z = 1.0 - x;
         let mut x = x;
         loop {
-            x = x.sqrt();
+            x = math::sqrt(x);
             let z_prime = z;
             y *= 0.5;
-            z -= (1.0 - x).powi(2) * y;
+            z -= math::powi(1.0 - x, 2) * y;
             if z_prime == z {
                 break;
             }
@@ -186,6 +617,7 @@ fn hll_tau(x: f64) -> f64 {
 
 #[cfg(test)]
 mod tests {
+    use crate::HllError;
     use crate::HyperLogLog;
 
     const P: usize = 14;
@@ -300,6 +732,259 @@ mod tests {
         compare_with_delta(hll.count(), 1000);
     }
 
+    #[test]
+    fn test_estimate() {
+        fn reference(histogram: &[u32; 64]) -> usize {
+            let m = (1 << P) as f64;
+            let q = 64 - P;
+            let mut z = m * super::hll_tau((m - histogram[q + 1] as f64) / m);
+            for i in histogram[1..=q].iter().rev() {
+                z += *i as f64;
+                z *= 0.5;
+            }
+            z += m * super::hll_sigma(histogram[0] as f64 / m);
+            (0.5 / 2_f64.ln() * m * m / z).round() as usize
+        }
+
+        let mut hll = HyperLogLog::<P>::new();
+        for n in [0_u64, 1, 100, 10_000, 100_000, 1_000_000] {
+            for i in 0..n {
+                hll.add_object(&i);
+            }
+            let histogram = hll.get_histogram();
+            assert_eq!(
+                HyperLogLog::<P>::estimate(&histogram),
+                reference(&histogram)
+            );
+        }
+        // saturated registers
+        let mut histogram = [0; 64];
+        histogram[64 - P + 1] = 10;
+        histogram[3] = (1 << P) - 10;
+        assert_eq!(
+            HyperLogLog::<P>::estimate(&histogram),
+            reference(&histogram)
+        );
+
+        for (k, inv_pow2) in super::INV_POW2.iter().enumerate() {
+            assert_eq!(*inv_pow2, 1.0 / 2_f64.powi(k as i32));
+        }
+        for (p, numerator) in super::ESTIMATE_NUMERATORS.iter().enumerate() {
+            let m = (1 << p) as f64;
+            assert_eq!(*numerator, 0.5 / 2_f64.ln() * m * m);
+        }
+    }
+
+    #[test]
+    fn test_add_hashes() {
+        let mut hashes: Vec<u64> = (0..100_000_u64)
+            .map(|i| <ahash::AHasher as crate::Hasher>::hll_hash(i % 60_000))
+            .collect();
+        let mut expected = HyperLogLog::<P>::new();
+        hashes.iter().for_each(|hash| expected.add_hash(*hash));
+
+        let mut hll = HyperLogLog::<P>::new();
+        hll.add_hashes(&hashes);
+        assert_eq!(hll, expected);
+        let mut hll = HyperLogLog::<P>::new();
+        hll.add_hashes_sorted(&mut hashes);
+        assert_eq!(hll, expected);
+        hll.add_hashes_sorted(&mut []);
+        assert_eq!(hll, expected);
+    }
+
+    #[test]
+    fn test_merge_many() {
+        let sketches: Vec<HyperLogLog<P>> = (0..20_u64)
+            .map(|i| {
+                let mut hll = HyperLogLog::new();
+                for j in i * 1000..i * 1000 + 5000 {
+                    hll.add_object(&j);
+                }
+                hll
+            })
+            .collect();
+
+        let mut expected = HyperLogLog::<P>::new();
+        sketches.iter().for_each(|hll| expected.merge(hll));
+        let mut hll = HyperLogLog::<P>::new();
+        hll.merge_many(&sketches);
+        assert_eq!(hll, expected);
+        compare_with_delta(hll.count(), 24_000);
+        let mut unchecked = HyperLogLog::<P>::new();
+        for other in &sketches {
+            // SAFETY: the registers of a sketch of the same P
+            unsafe { unchecked.merge_unchecked(&other.registers) };
+        }
+        assert_eq!(unchecked, expected);
+        assert_eq!(HyperLogLog::union(&sketches), expected);
+
+        // more sources than a batch
+        let sources: Vec<HyperLogLog<P>> = (0..super::MERGE_BATCH_SIZE as u64 * 2 + 3)
+            .map(|i| {
+                let mut hll = HyperLogLog::new();
+                hll.add_object(&i);
+                hll
+            })
+            .collect();
+        let mut expected = HyperLogLog::<P>::new();
+        sources.iter().for_each(|hll| expected.merge(hll));
+        assert_eq!(HyperLogLog::union(sources.iter()), expected);
+
+        // smaller than a block
+        let mut small = HyperLogLog::<4>::new();
+        small.merge_many([&HyperLogLog::<4>::new()]);
+        assert_eq!(small.num_empty_registers(), 16);
+    }
+
+    #[test]
+    fn test_fold() {
+        let mut hll = HyperLogLog::<14>::new();
+        let mut expected = HyperLogLog::<10>::new();
+        for i in 0..100_000_u64 {
+            let hash = <ahash::AHasher as crate::Hasher>::hll_hash(i);
+            hll.add_hash(hash);
+            expected.add_hash(hash);
+        }
+        // hashes whose 4 high bits of the index are zero, then the rest is zero too
+        hll.add_hash(1 << 14);
+        expected.add_hash(1 << 14);
+        hll.add_hash(3);
+        expected.add_hash(3);
+
+        assert_eq!(hll.fold::<10>().unwrap(), expected);
+        assert_eq!(hll.fold::<14>().unwrap(), hll);
+        assert_eq!(
+            expected.fold::<14>(),
+            Err(HllError::PrecisionMismatch {
+                expected: 14,
+                actual: 10
+            })
+        );
+    }
+
+    #[test]
+    fn test_try_new() {
+        assert!(HyperLogLog::<P>::try_new().is_ok());
+        assert_eq!(
+            HyperLogLog::<3>::try_new(),
+            Err(HllError::InvalidPrecision(3))
+        );
+        assert_eq!(
+            HyperLogLog::<19>::try_new(),
+            Err(HllError::InvalidPrecision(19))
+        );
+    }
+
+    #[test]
+    fn test_try_with_registers() {
+        let hll = HyperLogLog::<P>::try_with_registers(vec![1; NUM_REGISTERS]).unwrap();
+        assert_eq!(hll.num_empty_registers(), 0);
+
+        assert_eq!(
+            HyperLogLog::<P>::try_with_registers(vec![0; 10]),
+            Err(HllError::InvalidRegisterLength {
+                expected: NUM_REGISTERS,
+                actual: 10
+            })
+        );
+
+        let mut registers = vec![1; NUM_REGISTERS];
+        registers[5] = 200;
+        assert_eq!(
+            HyperLogLog::<P>::try_with_registers(registers.clone()),
+            Err(HllError::InvalidRegisterValue {
+                index: 5,
+                value: 200,
+                max: 51
+            })
+        );
+        let hll = HyperLogLog::<P>::with_registers_unchecked(registers);
+        assert_eq!(hll.validate().unwrap_err().len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "register")]
+    fn test_with_registers_invalid_value() {
+        HyperLogLog::<P>::with_registers(vec![52; NUM_REGISTERS]);
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut hll = HyperLogLog::<P>::new();
+        for i in 0..1000 {
+            hll.add_object(&i);
+        }
+        assert_eq!(hll.validate(), Ok(()));
+
+        hll.registers[3] = 200;
+        hll.registers[7] = 52;
+        hll.registers.push(0);
+        assert_eq!(
+            hll.validate(),
+            Err(vec![
+                HllError::InvalidRegisterLength {
+                    expected: NUM_REGISTERS,
+                    actual: NUM_REGISTERS + 1
+                },
+                HllError::InvalidRegisterValue {
+                    index: 3,
+                    value: 200,
+                    max: 51
+                },
+                HllError::InvalidRegisterValue {
+                    index: 7,
+                    value: 52,
+                    max: 51
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_try_from_bytes() {
+        let mut hll = HyperLogLog::<P>::new();
+        for i in 0..1000 {
+            hll.add_object(&i);
+        }
+
+        assert_eq!(hll.as_bytes().len(), NUM_REGISTERS);
+        let from_slice = HyperLogLog::<P>::try_from(hll.as_bytes()).unwrap();
+        assert_eq!(from_slice, hll);
+        let from_vec = HyperLogLog::<P>::try_from(hll.registers.clone()).unwrap();
+        assert_eq!(from_vec, hll);
+
+        assert_eq!(
+            HyperLogLog::<P>::try_from(&[0_u8; 16][..]),
+            Err(HllError::InvalidRegisterLength {
+                expected: NUM_REGISTERS,
+                actual: 16
+            })
+        );
+
+        let mut registers = vec![0; NUM_REGISTERS];
+        registers[7] = 52;
+        assert_eq!(
+            HyperLogLog::<P>::try_from(registers),
+            Err(HllError::InvalidRegisterValue {
+                index: 7,
+                value: 52,
+                max: 51
+            })
+        );
+    }
+
+    #[test]
+    fn test_expected_error_at() {
+        type H = HyperLogLog<P>;
+        assert_eq!(H::expected_error_at(0), 0.0);
+        // linear counting regime is more accurate than the asymptotic error
+        assert!(H::expected_error_at(100) < H::error_rate());
+        // error grows with n and converges to the asymptotic error
+        assert!(H::expected_error_at(1_000) < H::expected_error_at(10_000));
+        assert_eq!(H::expected_error_at(1_000_000), H::error_rate());
+    }
+
     #[test]
     fn test_repetition() {
         let mut hll = HyperLogLog::<P>::new();
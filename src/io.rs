@@ -0,0 +1,196 @@
+use std::io::BufRead;
+use std::io::Write;
+use std::marker::PhantomData;
+
+use crate::Hasher;
+use crate::HyperLogLog;
+
+impl<const P: usize> HyperLogLog<P> {
+    /// Adds every line of the reader to the HyperLogLog, returns the number of lines processed.
+    /// Line endings (`\n` or `\r\n`) are stripped and each line is hashed as a `&[u8]`.
+    pub fn add_from_reader<R: BufRead>(&mut self, reader: R) -> std::io::Result<usize> {
+        self.add_from_reader_delimited(reader, b'\n')
+    }
+
+    /// Adds every record separated by `delimiter` to the HyperLogLog,
+    /// returns the number of records processed.
+    pub fn add_from_reader_delimited<R: BufRead>(
+        &mut self,
+        reader: R,
+        delimiter: u8,
+    ) -> std::io::Result<usize> {
+        self.add_from_reader_by_hasher::<R, ahash::AHasher>(reader, delimiter)
+    }
+
+    pub fn add_from_reader_by_hasher<R: BufRead, H: Hasher>(
+        &mut self,
+        mut reader: R,
+        delimiter: u8,
+    ) -> std::io::Result<usize> {
+        let mut buf = Vec::new();
+        let mut records = 0;
+        loop {
+            buf.clear();
+            if reader.read_until(delimiter, &mut buf)? == 0 {
+                return Ok(records);
+            }
+            self.add_object_by_hasher::<_, H>(&trim_record(&buf, delimiter));
+            records += 1;
+        }
+    }
+}
+
+/// An [`std::io::Write`] sink which splits the written bytes on a delimiter
+/// and adds every record into the inner [`HyperLogLog`].
+/// Records are hashed the same way as `HyperLogLog::add_from_reader_delimited`.
+pub struct HllWriter<const P: usize, H: Hasher = ahash::AHasher> {
+    hll: HyperLogLog<P>,
+    delimiter: u8,
+    pending: Vec<u8>,
+    records: usize,
+    _hasher: PhantomData<H>,
+}
+
+impl<const P: usize> HllWriter<P> {
+    /// Creates a writer splitting records on `\n`
+    pub fn new(hll: HyperLogLog<P>) -> Self {
+        Self::with_delimiter(hll, b'\n')
+    }
+}
+
+impl<const P: usize, H: Hasher> HllWriter<P, H> {
+    pub fn with_delimiter(hll: HyperLogLog<P>, delimiter: u8) -> Self {
+        Self {
+            hll,
+            delimiter,
+            pending: Vec::new(),
+            records: 0,
+            _hasher: PhantomData,
+        }
+    }
+
+    /// The sketch of the complete records written so far
+    pub fn sketch(&self) -> &HyperLogLog<P> {
+        &self.hll
+    }
+
+    /// Number of complete records written so far
+    pub fn records(&self) -> usize {
+        self.records
+    }
+
+    /// Adds the trailing record which is not terminated by the delimiter
+    /// and returns the inner [`HyperLogLog`]
+    pub fn finish(mut self) -> HyperLogLog<P> {
+        if !self.pending.is_empty() {
+            self.hll
+                .add_object_by_hasher::<_, H>(&trim_record(&self.pending, self.delimiter));
+        }
+        self.hll
+    }
+}
+
+impl<const P: usize, H: Hasher> Write for HllWriter<P, H> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut rest = buf;
+        while let Some(pos) = rest.iter().position(|b| *b == self.delimiter) {
+            let (record, tail) = rest.split_at(pos + 1);
+            if self.pending.is_empty() {
+                self.hll
+                    .add_object_by_hasher::<_, H>(&trim_record(record, self.delimiter));
+            } else {
+                self.pending.extend_from_slice(record);
+                self.hll
+                    .add_object_by_hasher::<_, H>(&trim_record(&self.pending, self.delimiter));
+                self.pending.clear();
+            }
+            self.records += 1;
+            rest = tail;
+        }
+        self.pending.extend_from_slice(rest);
+        Ok(buf.len())
+    }
+
+    /// The pending record may be continued by later writes, so nothing is done here
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[inline]
+pub(crate) fn trim_record(record: &[u8], delimiter: u8) -> &[u8] {
+    let record = record.strip_suffix(&[delimiter]).unwrap_or(record);
+    if delimiter == b'\n' {
+        record.strip_suffix(b"\r").unwrap_or(record)
+    } else {
+        record
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use crate::HllWriter;
+    use crate::HyperLogLog;
+
+    const P: usize = 14;
+
+    #[test]
+    fn test_add_from_reader() {
+        let mut input = String::new();
+        for i in 0..10000 {
+            input.push_str(&format!("line-{}\n", i % 100));
+        }
+
+        let mut hll = HyperLogLog::<P>::new();
+        let lines = hll.add_from_reader(input.as_bytes()).unwrap();
+        assert_eq!(lines, 10000);
+        assert_eq!(hll.count(), 100);
+
+        // `\r\n` and a missing trailing newline are the same records
+        let mut other = HyperLogLog::<P>::new();
+        let crlf = input.replace('\n', "\r\n");
+        let lines = other.add_from_reader(crlf.trim_end().as_bytes()).unwrap();
+        assert_eq!(lines, 10000);
+        assert_eq!(hll, other);
+    }
+
+    #[test]
+    fn test_add_from_reader_delimited() {
+        let mut hll = HyperLogLog::<P>::new();
+        let records = hll
+            .add_from_reader_delimited(&b"a,b,c,a,b,c"[..], b',')
+            .unwrap();
+        assert_eq!(records, 6);
+        assert_eq!(hll.count(), 3);
+
+        let mut hll = HyperLogLog::<P>::new();
+        assert_eq!(hll.add_from_reader(&b""[..]).unwrap(), 0);
+        assert_eq!(hll.count(), 0);
+    }
+
+    #[test]
+    fn test_hll_writer() {
+        let mut input = String::new();
+        for i in 0..10000 {
+            input.push_str(&format!("line-{}\n", i % 100));
+        }
+        input.push_str("last");
+
+        let mut expected = HyperLogLog::<P>::new();
+        expected.add_from_reader(input.as_bytes()).unwrap();
+
+        // records are split across writes of arbitrary sizes
+        let mut writer = HllWriter::new(HyperLogLog::<P>::new());
+        for chunk in input.as_bytes().chunks(7) {
+            writer.write_all(chunk).unwrap();
+        }
+        writer.flush().unwrap();
+        assert_eq!(writer.records(), 10000);
+
+        let hll = writer.finish();
+        assert_eq!(hll, expected);
+        assert_eq!(hll.count(), 101);
+    }
+}
@@ -0,0 +1,160 @@
+//! # K Minimum Values
+//!
+//! Keeps the items with the `k` smallest hashes, a uniform sample of the distinct items
+//! which is also an estimate of their number: with `u` the `k`-th smallest hash as a
+//! fraction of the hash space, `(k - 1) / u` is an unbiased estimate (Bar-Yossef et al.,
+//! Beyer et al. "On Synopses for Distinct-Value Estimation Under Multiset Operations").
+
+use core::hash::Hash;
+use std::collections::BTreeMap;
+
+use crate::Hasher;
+
+/// The `k` distinct items with the smallest hashes, see the [module](self) documentation.
+/// Items are compared by hash only, two items with the same hash are the same item.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct KmvSketch<T> {
+    k: usize,
+    /// Invariant: at most `k` items
+    items: BTreeMap<u64, T>,
+}
+
+impl<T: Hash> KmvSketch<T> {
+    /// Keeps `k` items, at least two so the estimate is defined
+    pub fn new(k: usize) -> Self {
+        Self {
+            k: k.max(2),
+            items: BTreeMap::new(),
+        }
+    }
+
+    #[inline]
+    pub fn add(&mut self, item: T) {
+        self.add_by_hasher::<ahash::AHasher>(item);
+    }
+
+    pub fn add_by_hasher<H: Hasher>(&mut self, item: T) {
+        let hash = H::hll_hash(&item);
+        self.add_hashed(hash, item);
+    }
+
+    /// Adds an item with its hash, which must be computed the same way for every item
+    pub fn add_hashed(&mut self, hash: u64, item: T) {
+        if self.items.contains_key(&hash) {
+            return;
+        }
+        if self.items.len() == self.k {
+            match self.items.last_key_value() {
+                Some((largest, _)) if hash < *largest => {
+                    self.items.pop_last();
+                }
+                _ => return,
+            }
+        }
+        self.items.insert(hash, item);
+    }
+
+    /// Merge the other [`KmvSketch`] into this one, keeping the smallest `k` of both
+    pub fn merge(&mut self, other: &Self)
+    where
+        T: Clone,
+    {
+        self.k = self.k.min(other.k);
+        for (hash, item) in &other.items {
+            self.add_hashed(*hash, item.clone());
+        }
+        while self.items.len() > self.k {
+            self.items.pop_last();
+        }
+    }
+
+    /// Number of items kept
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// The items kept, a uniform sample without replacement of the distinct items seen,
+    /// ordered by hash
+    pub fn sample(&self) -> impl Iterator<Item = &T> {
+        self.items.values()
+    }
+
+    /// Unbiased estimate of the number of unique items, exact until `k` were seen
+    pub fn estimate(&self) -> f64 {
+        match self.items.last_key_value() {
+            Some((largest, _)) if self.items.len() == self.k => {
+                // the fraction of the hash space up to and including the k-th hash
+                let u = (*largest as f64 + 1.0) / (u64::MAX as f64 + 1.0);
+                (self.k - 1) as f64 / u
+            }
+            _ => self.items.len() as f64,
+        }
+    }
+
+    /// Guess the number of unique elements seen by the sketch.
+    #[inline]
+    pub fn count(&self) -> usize {
+        self.estimate().round() as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kmv() {
+        let mut kmv = KmvSketch::new(256);
+        assert!(kmv.is_empty());
+        for i in 0..100_u64 {
+            kmv.add(i);
+            kmv.add(i);
+        }
+        assert_eq!(kmv.count(), 100);
+        // duplicates of items kept don't evict others
+        for i in 0..1000_u64 {
+            kmv.add(i);
+            kmv.add(i % 100);
+        }
+        assert_eq!(kmv.len(), 256);
+
+        // the mean of the estimates converges to the number of items
+        let trials = 200;
+        let mean = (0..trials)
+            .map(|trial| {
+                let mut kmv = KmvSketch::new(256);
+                for i in 0..10_000_u64 {
+                    kmv.add((trial, i));
+                }
+                kmv.estimate()
+            })
+            .sum::<f64>()
+            / trials as f64;
+        assert!((mean / 10_000.0 - 1.0).abs() < 0.01, "{}", mean);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut all = KmvSketch::new(128);
+        let mut even = KmvSketch::new(128);
+        let mut odd = KmvSketch::new(128);
+        for i in 0..10_000_u64 {
+            all.add(i);
+            if i % 2 == 0 {
+                even.add(i);
+            } else {
+                odd.add(i);
+            }
+        }
+        even.merge(&odd);
+        assert_eq!(even, all);
+        assert_eq!(all.len(), 128);
+        assert!(all.sample().all(|i| *i < 10_000));
+    }
+}
@@ -1,21 +1,175 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(not(any(feature = "std", feature = "libm")))]
+compile_error!("`no_std` builds need the `libm` feature for the float functions");
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(feature = "std")]
+mod atomic;
+#[cfg(feature = "avro")]
+pub mod avro;
+#[cfg(feature = "cli")]
+pub mod cli;
+mod compressed;
+#[cfg(feature = "std")]
+mod concurrent;
+#[cfg(feature = "std")]
+mod concurrent_map;
+#[cfg(feature = "cpc")]
+mod cpc;
+#[cfg(feature = "datafusion")]
+pub mod datafusion;
+#[cfg(feature = "datafusion_compat")]
+mod datafusion_compat;
+#[cfg(feature = "std")]
+mod datasketches;
+#[cfg(feature = "diesel")]
+mod diesel;
+#[cfg(feature = "std")]
+mod druid;
+#[cfg(feature = "std")]
+mod encoding;
+mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "flatbuffers")]
+pub mod flatbuffer;
+pub mod format;
+#[cfg(feature = "std")]
+pub mod hyperbitbit;
 mod hyperloglog;
+#[cfg(feature = "std")]
+mod io;
+#[cfg(feature = "std")]
+pub mod kmv;
+#[cfg(feature = "std")]
+pub mod linear_counting;
+mod math;
+mod metrics;
+#[cfg(feature = "std")]
+pub mod minhash;
+#[cfg(feature = "std")]
+mod packed;
 
-#[cfg(feature = "serde_borsh")]
+#[cfg(feature = "parquet")]
+pub mod parquet;
+#[cfg(feature = "std")]
+pub mod pcsa;
+#[cfg(feature = "std")]
+mod pipeline;
+#[cfg(feature = "portable")]
+mod portable;
+#[cfg(any(test, feature = "proptest"))]
+pub mod proptest;
+#[cfg(feature = "prost")]
+pub mod proto;
+#[cfg(feature = "arc-swap")]
+pub mod published;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "rayon")]
+mod rayon;
+#[cfg(feature = "std")]
+mod redis;
+#[cfg(feature = "std")]
+mod rollup;
+#[cfg(any(feature = "serde", feature = "borsh"))]
 mod serde;
+#[cfg(feature = "std")]
+mod sharded;
+mod simd;
+#[cfg(feature = "std")]
+mod sketch;
+#[cfg(feature = "std")]
+mod spark;
+#[cfg(feature = "sqlx")]
+mod sqlx;
+#[cfg(feature = "futures")]
+mod stream;
+#[cfg(feature = "serde")]
+pub mod tagged;
+#[cfg(feature = "std")]
+pub mod theta;
+#[cfg(feature = "tokio")]
+mod tokio;
+#[cfg(feature = "std")]
+mod ultraloglog;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "xxh3")]
+mod xxh3;
 
 use ahash::RandomState;
+#[cfg(feature = "std")]
+pub use atomic::AtomicHyperLogLog;
+#[cfg(feature = "std")]
+pub use concurrent::ConcurrentHll;
+#[cfg(feature = "std")]
+pub use concurrent_map::ConcurrentHllMap;
+#[cfg(feature = "cpc")]
+pub use cpc::CpcSketch;
+#[cfg(feature = "datafusion_compat")]
+pub use datafusion_compat::DataFusionHasher;
+pub use error::HllError;
+#[cfg(feature = "std")]
+pub use hyperbitbit::HyperBitBit;
 use hyperloglog::DEFAULT_P;
+#[cfg(feature = "std")]
+pub use io::HllWriter;
+#[cfg(feature = "std")]
+pub use kmv::KmvSketch;
+#[cfg(feature = "std")]
+pub use linear_counting::LinearCounting;
+#[cfg(feature = "metrics")]
+pub use metrics::describe_metrics;
+#[cfg(feature = "std")]
+pub use minhash::MinHash;
+#[cfg(feature = "std")]
+pub use pcsa::Pcsa;
+#[cfg(feature = "std")]
+pub use pipeline::HllPipeline;
+#[cfg(feature = "std")]
+pub use pipeline::HllProducer;
+#[cfg(feature = "portable")]
+pub use portable::Portable;
+#[cfg(feature = "std")]
+pub use rollup::RollupHll;
+#[cfg(feature = "std")]
+pub use sharded::ShardHandle;
+#[cfg(feature = "std")]
+pub use sharded::ShardedHllBuilder;
+#[cfg(feature = "std")]
+pub use sketch::CardinalitySketch;
+#[cfg(feature = "std")]
+pub use theta::ThetaSketch;
+#[cfg(feature = "std")]
+pub use ultraloglog::UltraLogLog;
+#[cfg(feature = "xxh3")]
+pub use xxh3::Xxh3;
 
 pub type HyperLogLog<const P: usize = DEFAULT_P> = hyperloglog::HyperLogLog<P>;
 
 use core::hash::Hash;
 pub trait Hasher {
+    /// Identifies the hash function in the binary format (see `HyperLogLog::to_bytes`),
+    /// sketches built by different hash functions must not be merged.
+    /// `0` means unspecified, custom hashers should pick a value larger than 127.
+    const ID: u8 = 0;
+
     fn hll_hash<T: Hash>(x: T) -> u64
     where
         Self: Sized;
 }
 
-/// Fixed seed
+/// Fixed seed, together with the exact ahash version pinned in `Cargo.toml` it defines
+/// which register every value of persisted sketches went to, neither must ever change.
+/// ahash uses another algorithm if AES-NI is enabled at build time (e.g. with
+/// `-C target-cpu=native`), so sketches built that way must not be merged with the others.
 const SEED: RandomState = RandomState::with_seeds(
     0x355e438b4b1478c7_u64,
     0xd0e8453cd135b473_u64,
@@ -24,6 +178,8 @@ const SEED: RandomState = RandomState::with_seeds(
 );
 
 impl Hasher for ahash::AHasher {
+    const ID: u8 = 1;
+
     fn hll_hash<T: Hash>(x: T) -> u64 {
         SEED.hash_one(x)
     }
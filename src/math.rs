@@ -0,0 +1,51 @@
+//! The float functions of `std` used by the estimators, from `libm` in `no_std` builds.
+//! `libm` is a port of musl's implementations, the results may differ from `std`'s in the
+//! last bit, which doesn't change a rounded estimate in practice.
+
+#[cfg(feature = "std")]
+mod imp {
+    #[inline]
+    pub(crate) fn sqrt(x: f64) -> f64 {
+        x.sqrt()
+    }
+
+    #[inline]
+    pub(crate) fn exp(x: f64) -> f64 {
+        x.exp()
+    }
+
+    #[inline]
+    pub(crate) fn powi(x: f64, n: i32) -> f64 {
+        x.powi(n)
+    }
+
+    #[inline]
+    pub(crate) fn round(x: f64) -> f64 {
+        x.round()
+    }
+}
+
+#[cfg(not(feature = "std"))]
+mod imp {
+    #[inline]
+    pub(crate) fn sqrt(x: f64) -> f64 {
+        libm::sqrt(x)
+    }
+
+    #[inline]
+    pub(crate) fn exp(x: f64) -> f64 {
+        libm::exp(x)
+    }
+
+    #[inline]
+    pub(crate) fn powi(x: f64, n: i32) -> f64 {
+        libm::pow(x, n as f64)
+    }
+
+    #[inline]
+    pub(crate) fn round(x: f64) -> f64 {
+        libm::round(x)
+    }
+}
+
+pub(crate) use imp::*;
@@ -0,0 +1,142 @@
+//! Metrics of the core sketch, emitted through the [`metrics`](::metrics) facade with
+//! feature `metrics`, no-ops otherwise:
+//!
+//! - `simple_hll_inserts_total`: counter of the hashes added.
+//! - `simple_hll_merges_total`: counter of the sketches merged.
+//! - `simple_hll_serialized_bytes`: histogram of the size of the sketches serialized in the
+//!   binary format.
+//! - `simple_hll_count_seconds`: histogram of the latency of `count`.
+//!
+//! Batches are recorded once, e.g. `add_hashes` increments the inserts by the number of
+//! hashes, but every `add_hash` goes through the recorder, prefer the batch APIs on hot
+//! paths.
+
+#[cfg(feature = "metrics")]
+mod imp {
+    use std::time::Instant;
+
+    use ::metrics::Unit;
+
+    const INSERTS: &str = "simple_hll_inserts_total";
+    const MERGES: &str = "simple_hll_merges_total";
+    const SERIALIZED_BYTES: &str = "simple_hll_serialized_bytes";
+    const COUNT_SECONDS: &str = "simple_hll_count_seconds";
+
+    /// Register the units and descriptions of the metrics with the installed recorder
+    pub fn describe_metrics() {
+        ::metrics::describe_counter!(INSERTS, Unit::Count, "Hashes added to HyperLogLogs");
+        ::metrics::describe_counter!(MERGES, Unit::Count, "HyperLogLogs merged");
+        ::metrics::describe_histogram!(
+            SERIALIZED_BYTES,
+            Unit::Bytes,
+            "Size of the serialized HyperLogLogs"
+        );
+        ::metrics::describe_histogram!(
+            COUNT_SECONDS,
+            Unit::Seconds,
+            "Latency of HyperLogLog::count"
+        );
+    }
+
+    #[inline]
+    pub(crate) fn inserts(n: usize) {
+        ::metrics::counter!(INSERTS).increment(n as u64);
+    }
+
+    #[inline]
+    pub(crate) fn merges(n: usize) {
+        ::metrics::counter!(MERGES).increment(n as u64);
+    }
+
+    #[inline]
+    pub(crate) fn serialized_bytes(n: usize) {
+        ::metrics::histogram!(SERIALIZED_BYTES).record(n as f64);
+    }
+
+    #[inline]
+    pub(crate) fn time_count<R>(f: impl FnOnce() -> R) -> R {
+        let start = Instant::now();
+        let result = f();
+        ::metrics::histogram!(COUNT_SECONDS).record(start.elapsed());
+        result
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+mod imp {
+    #[inline(always)]
+    pub(crate) fn inserts(_n: usize) {}
+
+    #[inline(always)]
+    pub(crate) fn merges(_n: usize) {}
+
+    #[inline(always)]
+    pub(crate) fn serialized_bytes(_n: usize) {}
+
+    #[inline(always)]
+    pub(crate) fn time_count<R>(f: impl FnOnce() -> R) -> R {
+        f()
+    }
+}
+
+#[cfg(feature = "metrics")]
+pub use imp::describe_metrics;
+pub(crate) use imp::*;
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use metrics_util::debugging::DebugValue;
+    use metrics_util::debugging::DebuggingRecorder;
+
+    use crate::HyperLogLog;
+
+    #[test]
+    fn test_metrics() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let bytes = ::metrics::with_local_recorder(&recorder, || {
+            super::describe_metrics();
+            let mut hll = HyperLogLog::<12>::new();
+            hll.add_object(&1);
+            hll.add_hashes(&[2, 3, 4]);
+            hll.add_hashes_sorted(&mut [5, 6]);
+            let other = hll.clone();
+            hll.merge(&other);
+            hll.merge_many([&other, &other]);
+            hll.merge_from_slice(&other.to_bytes()).unwrap();
+            hll.count();
+            other.serialized_size()
+        });
+
+        let mut metrics = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(key, unit, _, value)| (key.key().name().to_string(), (unit, value)))
+            .collect::<Vec<_>>();
+        metrics.sort_by(|a, b| a.0.cmp(&b.0));
+        let names = metrics
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "simple_hll_count_seconds",
+                "simple_hll_inserts_total",
+                "simple_hll_merges_total",
+                "simple_hll_serialized_bytes"
+            ]
+        );
+        assert!(matches!(&metrics[0].1 .1, DebugValue::Histogram(v) if v.len() == 1));
+        assert_eq!(metrics[1].1 .1, DebugValue::Counter(6));
+        assert_eq!(metrics[2].1 .1, DebugValue::Counter(4));
+        assert_eq!(
+            metrics[3].1,
+            (
+                Some(::metrics::Unit::Bytes),
+                DebugValue::Histogram(vec![(bytes as f64).into()])
+            )
+        );
+    }
+}
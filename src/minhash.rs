@@ -0,0 +1,159 @@
+//! # MinHash
+//!
+//! One permutation hashing with optimal densification (Shrivastava, "Optimal Densification
+//! for Fast and Accurate Minwise Hashing", 2017): each hash only updates the minimum of
+//! one of `k` bins, and empty bins borrow the minimum of another bin when the signature is
+//! compared. The fraction of equal bins of two signatures estimates the Jaccard similarity
+//! of the two sets, with a standard error of about `1 / sqrt(k)`.
+
+use core::hash::Hash;
+
+use crate::Hasher;
+
+/// Minimum of an empty bin
+const EMPTY: u64 = u64::MAX;
+
+/// A MinHash signature of `k` bins, see the [module](self) documentation
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MinHash {
+    mins: Vec<u64>,
+}
+
+/// SplitMix64 finalizer, picks the bins borrowed by empty bins
+#[inline]
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+impl MinHash {
+    /// A signature of `k` bins, at least one
+    pub fn new(k: usize) -> Self {
+        Self {
+            mins: vec![EMPTY; k.max(1)],
+        }
+    }
+
+    /// Number of bins
+    #[inline]
+    pub fn k(&self) -> usize {
+        self.mins.len()
+    }
+
+    #[inline]
+    fn bin(&self, hash: u64) -> usize {
+        ((hash as u128 * self.mins.len() as u128) >> 64) as usize
+    }
+
+    #[inline]
+    pub fn add_hash(&mut self, hash: u64) {
+        let bin = self.bin(hash);
+        self.mins[bin] = self.mins[bin].min(hash);
+    }
+
+    #[inline]
+    pub fn add_object<T: Hash>(&mut self, obj: &T) {
+        self.add_object_by_hasher::<T, ahash::AHasher>(obj);
+    }
+
+    #[inline]
+    pub fn add_object_by_hasher<T: Hash, H: Hasher>(&mut self, obj: &T) {
+        self.add_hash(H::hll_hash(obj));
+    }
+
+    /// Merge the other [`MinHash`] into this one, the signature of the union of the sets.
+    ///
+    /// Panics if the numbers of bins differ.
+    pub fn merge(&mut self, other: &Self) {
+        assert_eq!(self.k(), other.k(), "signatures of different sizes");
+        for (min, other) in self.mins.iter_mut().zip(&other.mins) {
+            *min = (*min).min(*other);
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.mins.iter().all(|min| *min == EMPTY)
+    }
+
+    /// The minimum of every bin, empty bins borrow the minimum of the first non-empty bin
+    /// of their own pseudo-random sequence of bins, the same for every signature.
+    /// Empty if no hash was added.
+    pub fn signature(&self) -> Vec<u64> {
+        if self.is_empty() {
+            return vec![];
+        }
+        (0..self.k())
+            .map(|bin| {
+                let mut borrowed = bin;
+                let mut attempt = 0_u64;
+                while self.mins[borrowed] == EMPTY {
+                    attempt += 1;
+                    let hash = mix(((bin as u64) << 32) ^ attempt);
+                    borrowed = self.bin(hash);
+                }
+                self.mins[borrowed]
+            })
+            .collect()
+    }
+
+    /// Estimate of the Jaccard similarity `|A ∩ B| / |A ∪ B|` of the two sets, `1.0` if both
+    /// are empty.
+    ///
+    /// Panics if the numbers of bins differ.
+    pub fn jaccard(&self, other: &Self) -> f64 {
+        assert_eq!(self.k(), other.k(), "signatures of different sizes");
+        match (self.is_empty(), other.is_empty()) {
+            (true, true) => return 1.0,
+            (true, false) | (false, true) => return 0.0,
+            _ => {}
+        }
+        let equal = self
+            .signature()
+            .iter()
+            .zip(other.signature())
+            .filter(|(a, b)| **a == *b)
+            .count();
+        equal as f64 / self.k() as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minhash(k: usize, items: impl Iterator<Item = u64>) -> MinHash {
+        let mut minhash = MinHash::new(k);
+        for i in items {
+            minhash.add_object(&i);
+        }
+        minhash
+    }
+
+    #[test]
+    fn test_jaccard() {
+        let a = minhash(1024, 0..20_000);
+        let b = minhash(1024, 10_000..30_000);
+        assert!((a.jaccard(&b) - 1.0 / 3.0).abs() < 0.05);
+        assert_eq!(a.jaccard(&a), 1.0);
+        assert_eq!(a.jaccard(&MinHash::new(1024)), 0.0);
+        assert_eq!(MinHash::new(8).jaccard(&MinHash::new(8)), 1.0);
+
+        let mut union = a.clone();
+        union.merge(&b);
+        assert_eq!(union, minhash(1024, 0..30_000));
+    }
+
+    #[test]
+    fn test_densification() {
+        // fewer items than bins, most bins are empty
+        let a = minhash(1024, 0..100);
+        let b = minhash(1024, 50..150);
+        assert_eq!(a.signature().len(), 1024);
+        assert!(a.signature().iter().all(|min| *min != EMPTY));
+        assert!((a.jaccard(&b) - 1.0 / 3.0).abs() < 0.1);
+        assert_eq!(a.jaccard(&minhash(1024, 0..100)), 1.0);
+        assert!(a.jaccard(&minhash(1024, 1000..1100)) < 0.1);
+    }
+}
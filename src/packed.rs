@@ -0,0 +1,71 @@
+//! 6-bit packed registers, the same layout as redis's dense representation:
+//! register `i` occupies bits `[6 * i, 6 * i + 6)`, least significant bit first.
+
+/// Number of bytes to pack `n` registers
+#[inline]
+pub(crate) const fn packed_len(n: usize) -> usize {
+    (n * 6).div_ceil(8)
+}
+
+/// Pack registers into 6 bits each, every register must be smaller than 64
+#[cfg(test)]
+pub(crate) fn pack(registers: &[u8]) -> Vec<u8> {
+    iter(registers).collect()
+}
+
+/// Iterate over the packed bytes without allocating
+pub(crate) fn iter(registers: &[u8]) -> impl ExactSizeIterator<Item = u8> + Clone + '_ {
+    (0..packed_len(registers.len())).map(|b| byte_at(registers, b))
+}
+
+/// The `b`-th packed byte, it's made of at most two registers
+#[inline]
+fn byte_at(registers: &[u8], b: usize) -> u8 {
+    let bit = b * 8;
+    let (i, shift) = (bit / 6, bit % 6);
+    let mut v = ((registers[i] & 0x3f) as u16) >> shift;
+    if let Some(next) = registers.get(i + 1) {
+        v |= ((*next & 0x3f) as u16) << (6 - shift);
+    }
+    v as u8
+}
+
+/// Unpack `n` registers from `bytes`, which must be `packed_len(n)` long
+pub(crate) fn unpack(bytes: &[u8], n: usize) -> Vec<u8> {
+    assert_eq!(bytes.len(), packed_len(n));
+
+    (0..n).map(|i| get(bytes, i)).collect()
+}
+
+/// Get the `i`-th register from the packed bytes
+#[inline]
+pub(crate) fn get(bytes: &[u8], i: usize) -> u8 {
+    let bit = i * 6;
+    let (byte, shift) = (bit / 8, bit % 8);
+    let mut v = (bytes[byte] as u16) >> shift;
+    if shift > 2 {
+        v |= (bytes[byte + 1] as u16) << (8 - shift);
+    }
+    (v & 0x3f) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_unpack() {
+        for n in [4, 16, 1 << 14] {
+            let registers: Vec<u8> = (0..n).map(|i| (i * 7 % 64) as u8).collect();
+            let bytes = pack(&registers);
+            assert_eq!(bytes.len(), n * 3 / 4);
+            assert_eq!(unpack(&bytes, n), registers);
+        }
+
+        // same bits as redis's HLL_DENSE_SET_REGISTER
+        assert_eq!(
+            pack(&[1, 2, 3, 63]),
+            [0b1000_0001, 0b0011_0000, 0b1111_1100]
+        );
+    }
+}
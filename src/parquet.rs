@@ -0,0 +1,206 @@
+//! Merge Parquet columns of serialized sketches, e.g. to roll up daily sketch files.
+//!
+//! Sketches are serialized in the binary format (see [`crate::format`]) in a `BYTE_ARRAY`
+//! column, null values are skipped. Only the needed columns are read.
+
+use std::collections::HashMap;
+
+use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use ::parquet::arrow::ProjectionMask;
+use ::parquet::file::reader::ChunkReader;
+use arrow_array::cast::AsArray;
+use arrow_array::types::*;
+use arrow_array::Array;
+use arrow_array::ArrayRef;
+
+use crate::HllError;
+use crate::HyperLogLog;
+
+/// Merge every sketch of the binary column `column` into one
+pub fn merge_column<const P: usize, R: ChunkReader + 'static>(
+    reader: R,
+    column: &str,
+) -> Result<HyperLogLog<P>, HllError> {
+    let mut hll = HyperLogLog::<P>::new();
+    for_each_batch(reader, &[column], |columns| {
+        for bytes in binary_values(&columns[0])?.into_iter().flatten() {
+            hll.merge_from_slice(bytes)?;
+        }
+        Ok(())
+    })?;
+    Ok(hll)
+}
+
+/// Merge the sketches of the binary column `column` per value of `group_column`, a string
+/// or an integer column. Groups are keyed by the string form of the value, `None` for nulls.
+pub fn merge_column_grouped<const P: usize, R: ChunkReader + 'static>(
+    reader: R,
+    column: &str,
+    group_column: &str,
+) -> Result<HashMap<Option<String>, HyperLogLog<P>>, HllError> {
+    let mut groups = HashMap::new();
+    for_each_batch(reader, &[column, group_column], |columns| {
+        let keys = group_keys(&columns[1])?;
+        for (bytes, key) in binary_values(&columns[0])?.into_iter().zip(keys) {
+            if let Some(bytes) = bytes {
+                groups
+                    .entry(key)
+                    .or_insert_with(HyperLogLog::<P>::new)
+                    .merge_from_slice(bytes)?;
+            }
+        }
+        Ok(())
+    })?;
+    Ok(groups)
+}
+
+/// Call `f` with the columns `names` of every batch, in the same order
+fn for_each_batch<R: ChunkReader + 'static>(
+    reader: R,
+    names: &[&str],
+    mut f: impl FnMut(&[ArrayRef]) -> Result<(), HllError>,
+) -> Result<(), HllError> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(reader).map_err(malformed)?;
+    for name in names {
+        if builder.schema().index_of(name).is_err() {
+            return Err(HllError::Malformed(format!("parquet: no column {}", name)));
+        }
+    }
+    let mask = ProjectionMask::columns(builder.parquet_schema(), names.iter().copied());
+    let batches = builder.with_projection(mask).build().map_err(malformed)?;
+
+    for batch in batches {
+        let batch = batch.map_err(malformed)?;
+        let columns: Vec<ArrayRef> = names
+            .iter()
+            .map(|name| batch.column_by_name(name).unwrap().clone())
+            .collect();
+        f(&columns)?;
+    }
+    Ok(())
+}
+
+fn binary_values(array: &ArrayRef) -> Result<Vec<Option<&[u8]>>, HllError> {
+    if let Some(array) = array.as_binary_opt::<i32>() {
+        Ok(array.iter().collect())
+    } else if let Some(array) = array.as_binary_opt::<i64>() {
+        Ok(array.iter().collect())
+    } else if let Some(array) = array.as_binary_view_opt() {
+        Ok(array.iter().collect())
+    } else {
+        Err(HllError::Malformed(format!(
+            "parquet: sketches must be binary, not {}",
+            array.data_type()
+        )))
+    }
+}
+
+fn group_keys(array: &ArrayRef) -> Result<Vec<Option<String>>, HllError> {
+    macro_rules! keys {
+        ($array:expr) => {
+            Ok($array.iter().map(|v| v.map(|v| v.to_string())).collect())
+        };
+    }
+    macro_rules! primitive_keys {
+        ($($t:ty),*) => {
+            $(
+                if let Some(array) = array.as_primitive_opt::<$t>() {
+                    return keys!(array);
+                }
+            )*
+        };
+    }
+
+    if let Some(array) = array.as_string_opt::<i32>() {
+        return keys!(array);
+    }
+    if let Some(array) = array.as_string_opt::<i64>() {
+        return keys!(array);
+    }
+    if let Some(array) = array.as_string_view_opt() {
+        return keys!(array);
+    }
+    primitive_keys!(
+        Int8Type, Int16Type, Int32Type, Int64Type, UInt8Type, UInt16Type, UInt32Type, UInt64Type,
+        Date32Type, Date64Type
+    );
+    Err(HllError::Malformed(format!(
+        "parquet: groups must be strings or integers, not {}",
+        array.data_type()
+    )))
+}
+
+fn malformed(e: impl std::fmt::Display) -> HllError {
+    HllError::Malformed(format!("parquet: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ::parquet::arrow::ArrowWriter;
+    use arrow_array::RecordBatch;
+    use arrow_array::StringArray;
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::arrow::to_binary_array;
+
+    fn sketch(range: core::ops::Range<u64>) -> HyperLogLog<12> {
+        let mut hll = HyperLogLog::new();
+        for i in range {
+            hll.add_object(&i);
+        }
+        hll
+    }
+
+    /// One file of two row groups, like a day of hourly sketches
+    fn write(sketches: &[HyperLogLog<12>], groups: Vec<Option<&str>>) -> Bytes {
+        let batch = RecordBatch::try_from_iter([
+            ("sketch", Arc::new(to_binary_array(sketches)) as ArrayRef),
+            ("country", Arc::new(StringArray::from(groups)) as ArrayRef),
+        ])
+        .unwrap();
+        let mut buf = vec![];
+        let mut writer = ArrowWriter::try_new(&mut buf, batch.schema(), None).unwrap();
+        writer.write(&batch.slice(0, 2)).unwrap();
+        writer.flush().unwrap();
+        writer.write(&batch.slice(2, batch.num_rows() - 2)).unwrap();
+        writer.close().unwrap();
+        Bytes::from(buf)
+    }
+
+    #[test]
+    fn test_merge_column() {
+        let sketches = [
+            sketch(0..1000),
+            sketch(500..2000),
+            sketch(0..10),
+            sketch(3000..4000),
+        ];
+        let file = write(&sketches, vec![Some("fr"), Some("de"), None, Some("fr")]);
+
+        let mut expected = HyperLogLog::<12>::new();
+        sketches.iter().for_each(|hll| expected.merge(hll));
+        assert_eq!(
+            merge_column::<12, _>(file.clone(), "sketch").unwrap(),
+            expected
+        );
+
+        let groups = merge_column_grouped::<12, _>(file.clone(), "sketch", "country").unwrap();
+        let mut fr = sketches[0].clone();
+        fr.merge(&sketches[3]);
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[&Some("fr".to_string())], fr);
+        assert_eq!(groups[&Some("de".to_string())], sketches[1]);
+        assert_eq!(groups[&None], sketches[2]);
+
+        assert_eq!(
+            merge_column::<12, _>(file.clone(), "missing"),
+            Err(HllError::Malformed(
+                "parquet: no column missing".to_string()
+            ))
+        );
+        assert!(merge_column::<12, _>(file, "country").is_err());
+    }
+}
@@ -0,0 +1,249 @@
+//! # Probabilistic counting with stochastic averaging
+//!
+//! Flajolet and Martin, "Probabilistic Counting Algorithms for Data Base Applications"
+//! (1985). Each hash sets bit `r` of one of `1 << P` bitmaps, with `r` the number of
+//! trailing zeros of the hash bits left after the bitmap index. With `R` the mean over
+//! the bitmaps of the position of their lowest zero bit, the estimate is
+//! `m / 0.77351 * 2^R`, with a standard error of about `0.78 / sqrt(m)`.
+//!
+//! The estimate is the one of the paper, kept for reproducing legacy systems: it's biased
+//! upwards below about `10 * m` items, where HyperLogLog should be preferred.
+//!
+//! Binary format, all integers are little-endian:
+//!
+//! | offset | size | field                                         |
+//! |--------|------|-----------------------------------------------|
+//! | 0      | 4    | magic `b"SPCS"`                               |
+//! | 4      | 1    | format version, currently `1`                 |
+//! | 5      | 1    | precision `P`                                 |
+//! | 6      | 1    | hasher id, see [`crate::Hasher::ID`]          |
+//! | 7      | ..   | `1 << P` bitmaps, `u32` each                  |
+
+use core::hash::Hash;
+
+use crate::format::SliceReader;
+use crate::hyperloglog::DEFAULT_P;
+use crate::CardinalitySketch;
+use crate::Hasher;
+use crate::HllError;
+use crate::HyperLogLog;
+
+/// Magic constant `phi` of the paper
+const PHI: f64 = 0.77351;
+
+const MAGIC: &[u8; 4] = b"SPCS";
+const VERSION: u8 = 1;
+const HEADER_SIZE: usize = 7;
+
+/// `1 << P` bitmaps of 32 bits, P in `[4, 18]` like [`HyperLogLog`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Pcsa<const P: usize = DEFAULT_P> {
+    bitmaps: Vec<u32>,
+}
+
+impl<const P: usize> Default for Pcsa<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const P: usize> Pcsa<P> {
+    pub fn new() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = HyperLogLog::<P>::VALID_PRECISION;
+
+        Self {
+            bitmaps: vec![0; 1 << P],
+        }
+    }
+
+    #[inline]
+    pub fn add_hash(&mut self, hash: u64) {
+        let index = (hash & HyperLogLog::<P>::REGISTER_MASK) as usize;
+        let r = (hash >> P).trailing_zeros().min(31);
+        self.bitmaps[index] |= 1 << r;
+    }
+
+    #[inline]
+    pub fn add_object<T: Hash>(&mut self, obj: &T) {
+        self.add_object_by_hasher::<T, ahash::AHasher>(obj);
+    }
+
+    #[inline]
+    pub fn add_object_by_hasher<T: Hash, H: Hasher>(&mut self, obj: &T) {
+        self.add_hash(H::hll_hash(obj));
+    }
+
+    /// Merge the other [`Pcsa`] into this one
+    pub fn merge(&mut self, other: &Self) {
+        for (bitmap, other) in self.bitmaps.iter_mut().zip(&other.bitmaps) {
+            *bitmap |= *other;
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.bitmaps.iter().all(|bitmap| *bitmap == 0)
+    }
+
+    /// Guess the number of unique elements seen by the sketch, see the [module](self)
+    /// documentation.
+    pub fn count(&self) -> usize {
+        if self.is_empty() {
+            return 0;
+        }
+        let m = self.bitmaps.len() as f64;
+        let sum: u32 = self
+            .bitmaps
+            .iter()
+            .map(|bitmap| bitmap.trailing_ones())
+            .sum();
+        (m / PHI * (sum as f64 / m).exp2()).round() as usize
+    }
+
+    /// Serialize into the binary format described in the [module](self) documentation,
+    /// tagged with the id of the default hasher.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_with_hasher::<ahash::AHasher>()
+    }
+
+    /// Same as [`Self::to_bytes`], but tagged with the id of hasher `H`
+    pub fn to_bytes_with_hasher<H: Hasher>(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + 4 * self.bitmaps.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.push(P as u8);
+        bytes.push(H::ID);
+        for bitmap in &self.bitmaps {
+            bytes.extend_from_slice(&bitmap.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, HllError> {
+        Self::from_bytes_with_hasher::<ahash::AHasher>(bytes)
+    }
+
+    /// Deserialize a sketch built with hasher `H`
+    pub fn from_bytes_with_hasher<H: Hasher>(bytes: &[u8]) -> Result<Self, HllError> {
+        let mut reader = SliceReader::new(bytes);
+        if reader.read_slice(MAGIC.len())? != MAGIC {
+            return Err(HllError::InvalidMagic);
+        }
+        let version = reader.read_u8()?;
+        if version != VERSION {
+            return Err(HllError::UnsupportedVersion(version));
+        }
+        let precision = reader.read_u8()? as usize;
+        if precision != P {
+            return Err(HllError::PrecisionMismatch {
+                expected: P,
+                actual: precision,
+            });
+        }
+        let hasher = reader.read_u8()?;
+        if hasher != H::ID {
+            return Err(HllError::HasherMismatch {
+                expected: H::ID,
+                actual: hasher,
+            });
+        }
+        let bitmaps = (0..1 << P)
+            .map(|_| reader.read_u32())
+            .collect::<Result<_, _>>()?;
+        reader.finish()?;
+        Ok(Self { bitmaps })
+    }
+}
+
+/// Serialized as the bytes of [`Pcsa::to_bytes`]
+#[cfg(feature = "serde")]
+impl<const P: usize> serde::Serialize for Pcsa<P> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.to_bytes())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, const P: usize> serde::Deserialize<'de> for Pcsa<P> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = crate::serde::deserialize_bytes(deserializer)?;
+        Self::from_bytes(&bytes).map_err(serde::de::Error::custom)
+    }
+}
+
+impl<const P: usize> CardinalitySketch for Pcsa<P> {
+    #[inline]
+    fn add_hash(&mut self, hash: u64) {
+        Pcsa::add_hash(self, hash);
+    }
+
+    fn merge(&mut self, other: &Self) {
+        Pcsa::merge(self, other);
+    }
+
+    fn count(&self) -> usize {
+        Pcsa::count(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        Pcsa::is_empty(self)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        Pcsa::to_bytes(self)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, HllError> {
+        Pcsa::from_bytes(bytes)
+    }
+
+    fn memory_bytes(&self) -> usize {
+        core::mem::size_of::<Self>() + 4 * self.bitmaps.capacity()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcsa(items: core::ops::Range<u64>) -> Pcsa<10> {
+        let mut pcsa = Pcsa::new();
+        for i in items {
+            pcsa.add_object(&i);
+        }
+        pcsa
+    }
+
+    #[test]
+    fn test_pcsa() {
+        assert_eq!(Pcsa::<10>::new().count(), 0);
+
+        let all = pcsa(0..200_000);
+        let ratio = all.count() as f64 / 200_000.0;
+        assert!((ratio - 1.0).abs() < 4.0 * 0.78 / 32.0, "{}", ratio);
+
+        let mut merged = pcsa(0..50_000);
+        merged.merge(&pcsa(50_000..200_000));
+        assert_eq!(merged, all);
+    }
+
+    #[test]
+    fn test_serialize() {
+        let pcsa = pcsa(0..10_000);
+        let bytes = pcsa.to_bytes();
+        assert_eq!(bytes.len(), 7 + 4 * 1024);
+        assert_eq!(Pcsa::<10>::from_bytes(&bytes).unwrap(), pcsa);
+        assert_eq!(
+            Pcsa::<11>::from_bytes(&bytes).unwrap_err(),
+            HllError::PrecisionMismatch {
+                expected: 11,
+                actual: 10
+            }
+        );
+        assert_eq!(
+            Pcsa::<10>::from_bytes(&bytes[..100]).unwrap_err(),
+            HllError::UnexpectedEof
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! Feed one sketch from many producer threads through a bounded channel.
+
+use std::sync::mpsc::sync_channel;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::SyncSender;
+use std::thread::JoinHandle;
+
+use crate::HyperLogLog;
+
+/// Hashes buffered by a producer before they are sent to the consumer
+const BATCH_SIZE: usize = 1024;
+
+/// A consumer thread which adds the hashes pushed by any number of [`HllProducer`]s to
+/// its sketch. Producers send hashes in batches over a bounded channel, they block when
+/// the consumer falls `capacity` batches behind.
+///
+/// ```
+/// use simple_hll::HllPipeline;
+///
+/// let pipeline = HllPipeline::<14>::new(16);
+/// std::thread::scope(|s| {
+///     for t in 0..4_u64 {
+///         let mut producer = pipeline.producer();
+///         s.spawn(move || {
+///             for i in t * 1000..(t + 1) * 1000 {
+///                 producer.push(i.wrapping_mul(0x9e3779b97f4a7c15));
+///             }
+///         });
+///     }
+/// });
+/// let hll = pipeline.finish();
+/// ```
+#[derive(Debug)]
+pub struct HllPipeline<const P: usize> {
+    sender: SyncSender<Vec<u64>>,
+    consumer: JoinHandle<HyperLogLog<P>>,
+}
+
+/// Pushes hashes to a [`HllPipeline`], the pending batch is sent when dropped
+#[derive(Debug)]
+pub struct HllProducer {
+    sender: SyncSender<Vec<u64>>,
+    batch: Vec<u64>,
+}
+
+impl<const P: usize> HllPipeline<P> {
+    /// Spawns the consumer thread, with a channel of `capacity` batches
+    pub fn new(capacity: usize) -> Self {
+        Self::with_sketch(HyperLogLog::new(), capacity)
+    }
+
+    /// Spawns the consumer thread adding to `hll`
+    pub fn with_sketch(hll: HyperLogLog<P>, capacity: usize) -> Self {
+        let (sender, receiver) = sync_channel(capacity);
+        Self {
+            sender,
+            consumer: std::thread::spawn(move || consume(hll, receiver)),
+        }
+    }
+
+    /// A new producer, which can be sent to another thread
+    pub fn producer(&self) -> HllProducer {
+        HllProducer {
+            sender: self.sender.clone(),
+            batch: Vec::with_capacity(BATCH_SIZE),
+        }
+    }
+
+    /// Waits for the consumer to add every batch and returns the sketch. Every producer
+    /// must be dropped first, otherwise this waits for them forever.
+    pub fn finish(self) -> HyperLogLog<P> {
+        drop(self.sender);
+        self.consumer
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+}
+
+fn consume<const P: usize>(
+    mut hll: HyperLogLog<P>,
+    receiver: Receiver<Vec<u64>>,
+) -> HyperLogLog<P> {
+    for batch in receiver {
+        hll.add_hashes(&batch);
+    }
+    hll
+}
+
+impl HllProducer {
+    #[inline]
+    pub fn push(&mut self, hash: u64) {
+        self.batch.push(hash);
+        if self.batch.len() == BATCH_SIZE {
+            self.flush();
+        }
+    }
+
+    pub fn push_all(&mut self, hashes: &[u64]) {
+        for hash in hashes {
+            self.push(*hash);
+        }
+    }
+
+    /// Sends the pending batch, blocking while the channel is full
+    pub fn flush(&mut self) {
+        if !self.batch.is_empty() {
+            let batch = std::mem::replace(&mut self.batch, Vec::with_capacity(BATCH_SIZE));
+            // the consumer only stops once every sender is dropped
+            let _ = self.sender.send(batch);
+        }
+    }
+}
+
+impl Clone for HllProducer {
+    /// A producer with its own, empty batch
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            batch: Vec::with_capacity(BATCH_SIZE),
+        }
+    }
+}
+
+impl Drop for HllProducer {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipeline() {
+        let hashes: Vec<u64> = (0..100_000_u64)
+            .map(|i| i.wrapping_mul(0x9e3779b97f4a7c15))
+            .collect();
+        let mut expected = HyperLogLog::<14>::new();
+        expected.add_hashes(&hashes);
+
+        // a small capacity, producers wait for the consumer
+        let pipeline = HllPipeline::<14>::new(1);
+        std::thread::scope(|s| {
+            for chunk in hashes.chunks(30_000) {
+                let mut producer = pipeline.producer();
+                s.spawn(move || producer.push_all(chunk));
+            }
+        });
+        assert_eq!(pipeline.finish(), expected);
+
+        let pipeline = HllPipeline::with_sketch(expected.clone(), 4);
+        pipeline.producer().push(hashes[0]);
+        assert_eq!(pipeline.finish(), expected);
+    }
+}
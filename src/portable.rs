@@ -0,0 +1,145 @@
+//! A hasher whose output is fully specified, for sketches which are persisted or shared
+//! with other languages.
+//!
+//! The default hasher is AHash with fixed seeds, pinned to an exact version so that
+//! releases of `simple_hll` agree, but AHash itself makes no promise across its versions
+//! and picks another algorithm on CPUs with AES-NI when enabled at build time.
+//! [`Portable`] hashes with xxh3 (seed `0`), which has a frozen specification, and
+//! feeds it a byte stream that doesn't depend on the platform:
+//!
+//! - integers are written as their little-endian bytes, `usize`/`isize` as 8 bytes,
+//!   so 32-bit, 64-bit and big-endian targets agree.
+//! - everything else is what `Hash` writes: `str` is its bytes followed by `0xff`, a slice
+//!   is its length (as a `usize`) followed by its items.
+//!
+//! A `u64` is therefore hashed as `xxh3_64(&x.to_le_bytes())`, a string as
+//! `xxh3_64(s.as_bytes() + [0xff])`, which is easy to reproduce in another language.
+//! Prefer integers, strings and byte slices, derived `Hash` impls of your own types may
+//! change when their fields do.
+//!
+//! ```
+//! use simple_hll::HyperLogLog;
+//! use simple_hll::Portable;
+//!
+//! let mut hll = HyperLogLog::<14>::new();
+//! hll.add_object_by_hasher::<_, Portable>(&"user-1");
+//! let bytes = hll.to_bytes_with_hasher::<Portable>();
+//! assert_eq!(HyperLogLog::<14>::from_bytes_with_hasher::<Portable>(&bytes).unwrap(), hll);
+//! ```
+
+use core::hash::Hash;
+
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::Hasher;
+
+/// xxh3 with seed `0` over a platform independent byte stream, see the [module](self) docs
+pub struct Portable;
+
+impl Hasher for Portable {
+    const ID: u8 = 4;
+
+    fn hll_hash<T: Hash>(x: T) -> u64 {
+        let mut state = State(Xxh3::new());
+        x.hash(&mut state);
+        core::hash::Hasher::finish(&state)
+    }
+}
+
+struct State(Xxh3);
+
+macro_rules! write_le {
+    ($($method:ident: $ty:ty => $as:ty,)*) => {$(
+        #[inline]
+        fn $method(&mut self, i: $ty) {
+            self.0.update(&(i as $as).to_le_bytes());
+        }
+    )*};
+}
+
+impl core::hash::Hasher for State {
+    #[inline]
+    fn finish(&self) -> u64 {
+        self.0.digest()
+    }
+
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    write_le! {
+        write_u16: u16 => u16,
+        write_u32: u32 => u32,
+        write_u64: u64 => u64,
+        write_u128: u128 => u128,
+        write_usize: usize => u64,
+        write_i16: i16 => i16,
+        write_i32: i32 => i32,
+        write_i64: i64 => i64,
+        write_i128: i128 => i128,
+        write_isize: isize => i64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use xxhash_rust::xxh3::xxh3_64;
+
+    use super::*;
+    use crate::HyperLogLog;
+
+    #[test]
+    fn test_portable_byte_stream() {
+        assert_eq!(Portable::hll_hash(42_u64), xxh3_64(&42_u64.to_le_bytes()));
+        assert_eq!(Portable::hll_hash(42_usize), Portable::hll_hash(42_u64));
+        assert_eq!(Portable::hll_hash(-1_isize), Portable::hll_hash(-1_i64));
+        assert_eq!(Portable::hll_hash(7_u32), xxh3_64(&7_u32.to_le_bytes()));
+        assert_eq!(Portable::hll_hash("abc"), xxh3_64(b"abc\xff"));
+        let slice: &[u8] = b"abc";
+        assert_eq!(
+            Portable::hll_hash(slice),
+            xxh3_64(&[3, 0, 0, 0, 0, 0, 0, 0, b'a', b'b', b'c'])
+        );
+    }
+
+    /// Sketches of [`Portable`], committed in `tests/fixtures/corpus`, same as the ones of
+    /// the default hasher but without any exception: every platform builds them exactly.
+    /// Run with `UPDATE_GOLDEN=1` to add new fixtures, existing ones must never change.
+    #[test]
+    fn test_portable_golden() {
+        assert_eq!(Portable::hll_hash(0_u64), 0xc77b3abb6f87acd9);
+        assert_eq!(Portable::hll_hash(u64::MAX), 0x5111c7e47d784413);
+        assert_eq!(Portable::hll_hash("simple_hll"), 0xf0294d28ae6038ea);
+
+        fn check<const P: usize>(n: u64, register_sum: u64, estimate: usize) {
+            let path = format!(
+                "{}/tests/fixtures/corpus/portable_p{}_{}.bin",
+                env!("CARGO_MANIFEST_DIR"),
+                P,
+                n
+            );
+            let mut hll = HyperLogLog::<P>::new();
+            for i in 0..n {
+                hll.add_object_by_hasher::<_, Portable>(&i);
+            }
+            if std::env::var_os("UPDATE_GOLDEN").is_some() && !std::path::Path::new(&path).exists()
+            {
+                std::fs::write(&path, hll.to_bytes_with_hasher::<Portable>()).unwrap();
+            }
+
+            let golden = HyperLogLog::<P>::from_bytes_with_hasher::<Portable>(
+                &std::fs::read(&path).unwrap(),
+            )
+            .unwrap();
+            let sum: u64 = golden.registers.iter().map(|r| *r as u64).sum();
+            assert_eq!(sum, register_sum, "{}", path);
+            assert_eq!(golden.count(), estimate, "{}", path);
+            assert_eq!(golden, hll, "{} isn't built by the portable hasher", path);
+        }
+
+        check::<4>(100, 56, 86);
+        check::<14>(10_000, 16458, 10034);
+        check::<14>(1_000_000, 118621, 986392);
+    }
+}
@@ -0,0 +1,172 @@
+//! proptest strategies generating valid sketches, to property-test code aggregating them.
+//!
+//! ```ignore
+//! use proptest::prelude::*;
+//! use simple_hll::proptest::sketch_with_items;
+//!
+//! proptest! {
+//!     #[test]
+//!     fn estimate_is_close((hll, items) in sketch_with_items::<14>(10_000)) {
+//!         let error = (hll.count() as f64 - items.len() as f64).abs();
+//!         prop_assert!(error <= 0.05 * items.len() as f64 + 10.0);
+//!     }
+//! }
+//! ```
+
+use std::collections::HashSet;
+
+use ::proptest::collection::hash_set;
+use ::proptest::collection::vec;
+use ::proptest::prelude::*;
+
+use crate::HyperLogLog;
+
+/// Any valid sketch: empty, a few non-zero registers, or every register random
+pub fn sketch<const P: usize>() -> impl Strategy<Value = HyperLogLog<P>> {
+    let m = HyperLogLog::<P>::NUM_REGISTERS;
+    let max = HyperLogLog::<P>::max_register_value();
+    prop_oneof![
+        Just(HyperLogLog::<P>::new()),
+        vec((0..m, 1..=max), 1..m.min(64)).prop_map(|entries| {
+            let mut hll = HyperLogLog::<P>::new();
+            for (index, value) in entries {
+                hll.registers[index] = value;
+            }
+            hll
+        }),
+        vec(0..=max, m).prop_map(HyperLogLog::<P>::with_registers),
+    ]
+}
+
+/// A set of up to `max_items` distinct items and the sketch of the items, built with the
+/// default hasher, the exact cardinality is the length of the set.
+pub fn sketch_with_items<const P: usize>(
+    max_items: usize,
+) -> impl Strategy<Value = (HyperLogLog<P>, HashSet<u64>)> {
+    hash_set(any::<u64>(), 0..=max_items).prop_map(|items| {
+        let mut hll = HyperLogLog::<P>::new();
+        for item in &items {
+            hll.add_object(item);
+        }
+        (hll, items)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn test_sketch(hll in sketch::<10>()) {
+            let max = HyperLogLog::<10>::max_register_value();
+            prop_assert!(hll.registers.iter().all(|r| *r <= max));
+            prop_assert_eq!(HyperLogLog::<10>::from_bytes(&hll.to_bytes()).unwrap(), hll);
+        }
+
+        #[test]
+        fn test_sketch_with_items((hll, items) in sketch_with_items::<14>(2000)) {
+            let error = (hll.count() as f64 - items.len() as f64).abs();
+            prop_assert!(error <= 0.05 * items.len() as f64 + 2.0, "{} vs {}", hll.count(), items.len());
+        }
+    }
+
+    /// Algebraic invariants of the sketch, checked at every precision listed in the
+    /// `invariants!` invocation below. Add a property here and it runs for all of them.
+    macro_rules! invariants {
+        ($($name:ident: $p:literal,)*) => {$(
+            mod $name {
+                use super::*;
+
+                const P: usize = $p;
+
+                fn union(a: &HyperLogLog<P>, b: &HyperLogLog<P>) -> HyperLogLog<P> {
+                    let mut hll = a.clone();
+                    hll.merge(b);
+                    hll
+                }
+
+                proptest! {
+                    // full sketches of the larger precisions are slow to generate
+                    #![proptest_config(ProptestConfig::with_cases(64))]
+
+                    #[test]
+                    fn test_merge_commutes(a in sketch::<P>(), b in sketch::<P>()) {
+                        prop_assert_eq!(union(&a, &b), union(&b, &a));
+                    }
+
+                    #[test]
+                    fn test_merge_associates(
+                        a in sketch::<P>(),
+                        b in sketch::<P>(),
+                        c in sketch::<P>(),
+                    ) {
+                        prop_assert_eq!(union(&union(&a, &b), &c), union(&a, &union(&b, &c)));
+                    }
+
+                    #[test]
+                    fn test_merge_idempotent(a in sketch::<P>(), b in sketch::<P>()) {
+                        prop_assert_eq!(union(&a, &a), a.clone());
+                        prop_assert_eq!(union(&HyperLogLog::<P>::new(), &a), a.clone());
+                        let ab = union(&a, &b);
+                        prop_assert_eq!(union(&ab, &b), ab);
+                    }
+
+                    #[test]
+                    fn test_merge_monotonic(a in sketch::<P>(), b in sketch::<P>()) {
+                        let count = union(&a, &b).count();
+                        prop_assert!(count >= a.count() && count >= b.count());
+                    }
+
+                    #[test]
+                    fn test_insert_monotonic(
+                        (mut hll, _) in sketch_with_items::<P>(2000),
+                        items in vec(any::<u64>(), 0..200),
+                    ) {
+                        let mut count = hll.count();
+                        for item in items {
+                            hll.add_object(&item);
+                            prop_assert!(hll.count() >= count);
+                            count = hll.count();
+                        }
+                    }
+
+                    #[test]
+                    fn test_bytes_roundtrip(hll in sketch::<P>()) {
+                        let bytes = hll.to_bytes();
+                        prop_assert_eq!(HyperLogLog::<P>::from_bytes(&bytes).unwrap(), hll.clone());
+                        prop_assert_eq!(
+                            HyperLogLog::<P>::from_bytes(&hll.to_bytes_with_checksum()).unwrap(),
+                            hll
+                        );
+                    }
+
+                    #[cfg(feature = "serde")]
+                    #[test]
+                    fn test_serde_roundtrip(hll in sketch::<P>()) {
+                        let bytes = bincode::serialize(&hll).unwrap();
+                        let decoded: HyperLogLog<P> = bincode::deserialize(&bytes).unwrap();
+                        prop_assert_eq!(decoded, hll.clone());
+                        let json = serde_json::to_string(&hll).unwrap();
+                        let decoded: HyperLogLog<P> = serde_json::from_str(&json).unwrap();
+                        prop_assert_eq!(decoded, hll);
+                    }
+
+                    #[cfg(feature = "borsh")]
+                    #[test]
+                    fn test_borsh_roundtrip(hll in sketch::<P>()) {
+                        let bytes = borsh::to_vec(&hll).unwrap();
+                        prop_assert_eq!(borsh::from_slice::<HyperLogLog<P>>(&bytes).unwrap(), hll);
+                    }
+                }
+            }
+        )*};
+    }
+
+    invariants! {
+        p4: 4,
+        p8: 8,
+        p12: 12,
+        p14: 14,
+    }
+}
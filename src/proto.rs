@@ -0,0 +1,146 @@
+//! Protobuf message of the sketch, the schema is in `proto/simple_hll.proto`.
+
+use crate::format;
+use crate::Hasher;
+use crate::HllError;
+use crate::HyperLogLog;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct HyperLogLogProto {
+    /// P, the number of registers is `1 << P`
+    #[prost(uint32, tag = "1")]
+    pub precision: u32,
+    #[prost(enumeration = "Representation", tag = "2")]
+    pub representation: i32,
+    /// Same as the payload of the binary format, see [`format`]
+    #[prost(bytes = "vec", tag = "3")]
+    pub payload: Vec<u8>,
+    /// Id of the hash function, see [`Hasher::ID`]
+    #[prost(uint32, tag = "4")]
+    pub hasher: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum Representation {
+    Empty = 0,
+    Sparse = 1,
+    Dense = 2,
+    Compressed = 3,
+}
+
+impl<const P: usize> From<&HyperLogLog<P>> for HyperLogLogProto {
+    fn from(hll: &HyperLogLog<P>) -> Self {
+        let mut bytes = hll.to_bytes();
+        let header: Vec<u8> = bytes.drain(..format::HEADER_SIZE).collect();
+        Self {
+            precision: P as u32,
+            representation: header[7] as i32,
+            payload: bytes,
+            hasher: header[6] as u32,
+        }
+    }
+}
+
+impl<const P: usize> TryFrom<HyperLogLogProto> for HyperLogLog<P> {
+    type Error = HllError;
+
+    fn try_from(proto: HyperLogLogProto) -> Result<Self, Self::Error> {
+        HyperLogLog::<P>::try_from(&proto)
+    }
+}
+
+impl<const P: usize> TryFrom<&HyperLogLogProto> for HyperLogLog<P> {
+    type Error = HllError;
+
+    /// A higher precision is folded into `P` like in [`HyperLogLog::from_bytes`]
+    fn try_from(proto: &HyperLogLogProto) -> Result<Self, Self::Error> {
+        let precision = u8::try_from(proto.precision)
+            .map_err(|_| HllError::InvalidPrecision(proto.precision as usize))?;
+        let representation = u8::try_from(proto.representation)
+            .map_err(|_| HllError::UnsupportedRepresentation(u8::MAX))?;
+        let hasher = u8::try_from(proto.hasher).map_err(|_| HllError::HasherMismatch {
+            expected: ahash::AHasher::ID,
+            actual: u8::MAX,
+        })?;
+
+        let mut bytes = Vec::with_capacity(format::HEADER_SIZE + proto.payload.len());
+        bytes.extend_from_slice(format::MAGIC);
+        bytes.extend_from_slice(&[format::VERSION, precision, hasher, representation]);
+        bytes.extend_from_slice(&proto.payload);
+        HyperLogLog::<P>::from_bytes(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::*;
+
+    #[test]
+    fn test_proto_round_trip() {
+        let mut hll = HyperLogLog::<14>::new();
+        for (n, repr) in [
+            (0, Representation::Empty),
+            (100, Representation::Sparse),
+            (10_000, Representation::Compressed),
+        ] {
+            for i in 0..n {
+                hll.add_object(&i);
+            }
+            let proto = HyperLogLogProto::from(&hll);
+            assert_eq!(proto.representation(), repr);
+
+            let bytes = proto.encode_to_vec();
+            let decoded = HyperLogLogProto::decode(bytes.as_slice()).unwrap();
+            assert_eq!(decoded, proto);
+            assert_eq!(HyperLogLog::<14>::try_from(decoded).unwrap(), hll);
+        }
+
+        let hll = HyperLogLog::<14>::with_registers(vec![3; 1 << 14]);
+        let proto = HyperLogLogProto::from(&hll);
+        assert_eq!(proto.representation(), Representation::Dense);
+        assert_eq!(proto.hasher, hll.to_bytes()[6] as u32);
+        assert_eq!(HyperLogLog::<14>::try_from(&proto).unwrap(), hll);
+
+        // a higher precision is folded
+        assert_eq!(
+            HyperLogLog::<12>::try_from(&proto).unwrap(),
+            hll.fold::<12>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_proto_invalid() {
+        let proto = HyperLogLogProto::from(&HyperLogLog::<12>::new());
+        assert_eq!(
+            HyperLogLog::<14>::try_from(&proto),
+            Err(HllError::PrecisionMismatch {
+                expected: 14,
+                actual: 12
+            })
+        );
+        let mut invalid = proto.clone();
+        invalid.precision = 300;
+        assert_eq!(
+            HyperLogLog::<12>::try_from(invalid),
+            Err(HllError::InvalidPrecision(300))
+        );
+        let proto = HyperLogLogProto::from(&HyperLogLog::<14>::new());
+
+        let mut invalid = proto.clone();
+        invalid.representation = 7;
+        assert_eq!(
+            HyperLogLog::<14>::try_from(invalid),
+            Err(HllError::UnsupportedRepresentation(7))
+        );
+
+        let mut invalid = proto;
+        invalid.representation = Representation::Dense as i32;
+        assert_eq!(
+            HyperLogLog::<14>::try_from(invalid),
+            Err(HllError::UnexpectedEof)
+        );
+    }
+}
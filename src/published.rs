@@ -0,0 +1,102 @@
+//! Serve the counts of a sketch updated by one writer, for read-mostly services.
+
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+
+use crate::HyperLogLog;
+
+/// An immutable snapshot and its count, computed once when published
+#[derive(Debug)]
+pub struct Snapshot<const P: usize> {
+    hll: HyperLogLog<P>,
+    count: usize,
+}
+
+impl<const P: usize> Snapshot<P> {
+    pub fn hll(&self) -> &HyperLogLog<P> {
+        &self.hll
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+}
+
+/// The latest snapshot published by a writer, e.g. every second from the sketch it
+/// updates. Readers never lock and never wait for the writer, they keep reading the
+/// previous snapshot while a new one is published.
+#[derive(Debug)]
+pub struct PublishedHll<const P: usize> {
+    current: ArcSwap<Snapshot<P>>,
+}
+
+impl<const P: usize> Default for PublishedHll<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const P: usize> PublishedHll<P> {
+    /// Starts with an empty snapshot
+    pub fn new() -> Self {
+        Self {
+            current: ArcSwap::from_pointee(Snapshot {
+                hll: HyperLogLog::new(),
+                count: 0,
+            }),
+        }
+    }
+
+    /// Replace the snapshot, its count is computed here rather than by every reader
+    pub fn publish(&self, hll: HyperLogLog<P>) {
+        let count = hll.count();
+        self.current.store(Arc::new(Snapshot { hll, count }));
+    }
+
+    /// The latest snapshot, kept alive as long as the `Arc` is
+    pub fn snapshot(&self) -> Arc<Snapshot<P>> {
+        self.current.load_full()
+    }
+
+    /// The count of the latest snapshot
+    pub fn count(&self) -> usize {
+        self.current.load().count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_publish() {
+        let published = PublishedHll::<14>::new();
+        assert_eq!(published.count(), 0);
+
+        let mut hll = HyperLogLog::<14>::new();
+        std::thread::scope(|s| {
+            let reader = s.spawn(|| {
+                // counts only grow, whatever the snapshot read
+                let mut last = 0;
+                for _ in 0..1000 {
+                    let count = published.count();
+                    assert!(count >= last);
+                    last = count;
+                }
+            });
+            for batch in 0..10_u64 {
+                for i in batch * 1000..(batch + 1) * 1000 {
+                    hll.add_object(&i);
+                }
+                published.publish(hll.clone());
+            }
+            reader.join().unwrap();
+        });
+
+        let snapshot = published.snapshot();
+        assert_eq!(snapshot.hll(), &hll);
+        assert_eq!(snapshot.count(), hll.count());
+        assert_eq!(published.count(), hll.count());
+    }
+}
@@ -0,0 +1,191 @@
+//! Python module, so data scientists can read and produce the sketches stored by Rust
+//! services.
+//!
+//! Build the extension module with
+//! `cargo rustc --release --features python,pyo3/extension-module --crate-type cdylib`
+//! and copy `libsimple_hll.so` as `simple_hll.so` (`simple_hll.pyd` on Windows) into the
+//! Python path.
+//!
+//! Items are hashed with the default hasher like [`HyperLogLog::add_object`] in Rust:
+//! a `str` is hashed like a `&str`, `bytes` like a `&[u8]` and an `int` like an `i64`,
+//! and `to_bytes` is the binary format of [`HyperLogLog::to_bytes`].
+//!
+//! ```python
+//! from simple_hll import HyperLogLog
+//!
+//! hll = HyperLogLog()
+//! hll.add("user-1")
+//! hll.update(range(1000))
+//! other = HyperLogLog.from_bytes(blob)
+//! hll.merge(other)
+//! print(hll.count(), len(hll.to_bytes()))
+//! ```
+
+use pyo3::exceptions::PyTypeError;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use pyo3::types::PyInt;
+use pyo3::types::PyString;
+
+use crate::HllError;
+use crate::HyperLogLog;
+
+impl From<HllError> for PyErr {
+    fn from(e: HllError) -> Self {
+        PyValueError::new_err(e.to_string())
+    }
+}
+
+/// A sketch of precision `14`, `simple_hll.HyperLogLog` in Python
+#[pyclass(name = "HyperLogLog", module = "simple_hll", eq)]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PyHyperLogLog {
+    inner: HyperLogLog,
+}
+
+#[pymethods]
+impl PyHyperLogLog {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a `str`, `bytes` or `int` (hashed as an `i64`)
+    fn add(&mut self, item: &Bound<'_, PyAny>) -> PyResult<()> {
+        if let Ok(s) = item.cast::<PyString>() {
+            self.inner.add_object(&s.to_str()?);
+        } else if let Ok(bytes) = item.cast::<PyBytes>() {
+            self.inner.add_object(&bytes.as_bytes());
+        } else if item.is_instance_of::<PyInt>() {
+            self.inner.add_object(&item.extract::<i64>()?);
+        } else {
+            return Err(PyTypeError::new_err(format!(
+                "expected str, bytes or int, got {}",
+                item.get_type().name()?
+            )));
+        }
+        Ok(())
+    }
+
+    /// Add every item of an iterable, see `add`
+    fn update(&mut self, items: &Bound<'_, PyAny>) -> PyResult<()> {
+        for item in items.try_iter()? {
+            self.add(&item?)?;
+        }
+        Ok(())
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.inner.merge(&other.inner);
+    }
+
+    /// The estimated number of distinct items
+    fn count(&self) -> usize {
+        self.inner.count()
+    }
+
+    fn __len__(&self) -> usize {
+        self.inner.count()
+    }
+
+    fn to_bytes<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.inner.to_bytes())
+    }
+
+    /// Read a sketch written by `to_bytes` or `HyperLogLog::to_bytes` in Rust, raises
+    /// `ValueError` if the bytes are invalid
+    #[staticmethod]
+    fn from_bytes(bytes: &[u8]) -> PyResult<Self> {
+        Ok(HyperLogLog::from_bytes(bytes)?.into())
+    }
+
+    fn __bytes__<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        self.to_bytes(py)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("HyperLogLog(count={})", self.inner.count())
+    }
+}
+
+impl From<HyperLogLog> for PyHyperLogLog {
+    fn from(inner: HyperLogLog) -> Self {
+        Self { inner }
+    }
+}
+
+impl From<PyHyperLogLog> for HyperLogLog {
+    fn from(hll: PyHyperLogLog) -> Self {
+        hll.inner
+    }
+}
+
+#[pymodule]
+fn simple_hll(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyHyperLogLog>()
+}
+
+#[cfg(test)]
+mod tests {
+    use pyo3::ffi::c_str;
+
+    use super::*;
+
+    #[test]
+    fn test_python() {
+        Python::initialize();
+        Python::attach(|py| {
+            let module = PyModule::new(py, "simple_hll").unwrap();
+            simple_hll(&module).unwrap();
+            let hll = module
+                .getattr("HyperLogLog")
+                .unwrap()
+                .call0()
+                .unwrap()
+                .cast_into::<PyHyperLogLog>()
+                .unwrap();
+            let locals = pyo3::types::PyDict::new(py);
+            locals.set_item("hll", &hll).unwrap();
+            locals
+                .set_item("HyperLogLog", module.getattr("HyperLogLog").unwrap())
+                .unwrap();
+            py.run(
+                c_str!(
+                    r#"
+hll.add("user-1")
+hll.add(b"user-1")
+hll.update(range(1000))
+other = HyperLogLog.from_bytes(hll.to_bytes())
+assert other == hll
+other.add(-1)
+hll.merge(other)
+assert len(hll) == hll.count()
+try:
+    hll.add(1.5)
+    raise AssertionError
+except TypeError:
+    pass
+try:
+    HyperLogLog.from_bytes(b"SHLL")
+    raise AssertionError
+except ValueError:
+    pass
+"#
+                ),
+                None,
+                Some(&locals),
+            )
+            .unwrap();
+
+            let mut expected = HyperLogLog::new();
+            expected.add_object(&"user-1");
+            expected.add_object(&b"user-1".as_slice());
+            for i in 0..1000_i64 {
+                expected.add_object(&i);
+            }
+            expected.add_object(&-1_i64);
+            assert_eq!(HyperLogLog::from(hll.borrow().clone()), expected);
+        });
+    }
+}
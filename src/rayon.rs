@@ -0,0 +1,66 @@
+//! Build sketches from rayon parallel iterators.
+//!
+//! Every thread adds its items to its own sketch, the sketches are merged at the end, so
+//! there's no locking while inserting. Items are hashed with the default hasher, like
+//! [`HyperLogLog::add_object`].
+
+use core::hash::Hash;
+
+use ::rayon::iter::FromParallelIterator;
+use ::rayon::iter::IntoParallelIterator;
+use ::rayon::iter::ParallelExtend;
+use ::rayon::iter::ParallelIterator;
+
+use crate::HyperLogLog;
+
+impl<const P: usize, T: Hash + Send> FromParallelIterator<T> for HyperLogLog<P> {
+    fn from_par_iter<I>(par_iter: I) -> Self
+    where
+        I: IntoParallelIterator<Item = T>,
+    {
+        par_iter
+            .into_par_iter()
+            .fold(HyperLogLog::new, |mut hll, item| {
+                hll.add_object(&item);
+                hll
+            })
+            .reduce(HyperLogLog::new, |mut hll, other| {
+                hll.merge(&other);
+                hll
+            })
+    }
+}
+
+impl<const P: usize, T: Hash + Send> ParallelExtend<T> for HyperLogLog<P> {
+    fn par_extend<I>(&mut self, par_iter: I)
+    where
+        I: IntoParallelIterator<Item = T>,
+    {
+        self.merge(&Self::from_par_iter(par_iter));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::rayon::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_par_iter() {
+        let mut expected = HyperLogLog::<14>::new();
+        for i in 0..1_000_000_u64 {
+            expected.add_object(&i);
+        }
+
+        let hll: HyperLogLog<14> = (0..1_000_000_u64).into_par_iter().collect();
+        assert_eq!(hll, expected);
+
+        let mut hll: HyperLogLog<14> = (0..500_000_u64).into_par_iter().collect();
+        hll.par_extend((500_000..1_000_000_u64).into_par_iter());
+        assert_eq!(hll, expected);
+
+        let empty: HyperLogLog<14> = Vec::<u64>::new().into_par_iter().collect();
+        assert_eq!(empty, HyperLogLog::new());
+    }
+}
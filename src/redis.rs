@@ -0,0 +1,349 @@
+//! Redis HyperLogLog strings, as returned by `GET` on a key written by `PFADD`.
+//!
+//! | offset | size | field                                              |
+//! |--------|------|----------------------------------------------------|
+//! | 0      | 4    | magic `b"HYLL"`                                    |
+//! | 4      | 1    | encoding: 0 dense, 1 sparse                        |
+//! | 5      | 3    | unused, zero                                       |
+//! | 8      | 8    | cached cardinality, little-endian, MSB set if stale |
+//! | 16     | ..   | registers                                          |
+//!
+//! Redis always uses `P = 14`, dense registers are 6-bit packed, see [`packed`].
+//! Sparse registers are a sequence of opcodes, each one is a run of registers:
+//! - `ZERO`: `00xxxxxx`, `xxxxxx + 1` zero registers.
+//! - `XZERO`: `01xxxxxx yyyyyyyy`, `xxxxxxyyyyyyyy + 1` zero registers.
+//! - `VAL`: `1vvvvvxx`, `xx + 1` registers of value `vvvvv + 1`.
+//!
+//! The runs must cover exactly the `1 << 14` registers.
+//! Redis hashes elements with MurmurHash64A (seed `0xadc83b19`), sketches built with
+//! another hasher can be counted but must not be merged with Redis keys.
+//!
+//! With feature `redis`, sketches of `P = 14` convert from/to redis-rs values, so
+//! `con.get::<_, Option<HyperLogLog>>(key)` loads a key and `con.set(key, &hll)` writes it
+//! back in the dense encoding, on sync and async connections. `PFCOUNT` of the written key
+//! is [`HyperLogLog::count`], Redis estimates with the same estimator.
+
+use crate::format::SliceReader;
+use crate::packed;
+use crate::HllError;
+use crate::HyperLogLog;
+
+const MAGIC: &[u8; 4] = b"HYLL";
+const HEADER_SIZE: usize = 16;
+const ENCODING_DENSE: u8 = 0;
+const ENCODING_SPARSE: u8 = 1;
+
+/// Redis's `HLL_P`
+const REDIS_P: usize = 14;
+
+impl HyperLogLog<REDIS_P> {
+    /// Load the value of a Redis HyperLogLog key
+    pub fn from_redis(bytes: &[u8]) -> Result<Self, HllError> {
+        let mut reader = SliceReader::new(bytes);
+        if reader.read_slice(MAGIC.len())? != MAGIC {
+            return Err(HllError::InvalidMagic);
+        }
+        let encoding = reader.read_u8()?;
+        // unused bytes and the cached cardinality
+        reader.read_slice(HEADER_SIZE - MAGIC.len() - 1)?;
+
+        let registers = match encoding {
+            ENCODING_DENSE => {
+                let m = Self::NUM_REGISTERS;
+                packed::unpack(reader.read_slice(packed::packed_len(m))?, m)
+            }
+            ENCODING_SPARSE => {
+                let opcodes = reader.read_slice(reader.remaining())?;
+                decode_sparse(opcodes, Self::NUM_REGISTERS)?
+            }
+            encoding => return Err(HllError::UnsupportedRepresentation(encoding)),
+        };
+        reader.finish()?;
+        Self::try_from(registers)
+    }
+
+    /// Serialize into a dense Redis HyperLogLog value, which can be written back with `SET`
+    /// and used by `PFCOUNT`/`PFMERGE`. The cached cardinality is marked stale, so Redis
+    /// computes it on the next `PFCOUNT`.
+    pub fn to_redis_dense(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + packed::packed_len(self.registers.len()));
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&[ENCODING_DENSE, 0, 0, 0]);
+        bytes.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0x80]);
+        bytes.extend(packed::iter(&self.registers));
+        bytes
+    }
+}
+
+#[cfg(feature = "redis")]
+impl HyperLogLog<REDIS_P> {
+    /// `GET key`, `None` if the key doesn't exist
+    pub fn get_redis<C, K>(con: &mut C, key: K) -> ::redis::RedisResult<Option<Self>>
+    where
+        C: ::redis::ConnectionLike,
+        K: ::redis::ToRedisArgs,
+    {
+        ::redis::cmd("GET").arg(key).query(con)
+    }
+
+    /// `SET key` to the dense encoding, see [`Self::to_redis_dense`]
+    pub fn set_redis<C, K>(&self, con: &mut C, key: K) -> ::redis::RedisResult<()>
+    where
+        C: ::redis::ConnectionLike,
+        K: ::redis::ToRedisArgs,
+    {
+        ::redis::cmd("SET").arg(key).arg(self).query(con)
+    }
+}
+
+/// A bulk string in either encoding, see [`HyperLogLog::from_redis`]
+#[cfg(feature = "redis")]
+impl ::redis::FromRedisValue for HyperLogLog<REDIS_P> {
+    fn from_redis_value_ref(v: &::redis::Value) -> Result<Self, ::redis::ParsingError> {
+        match v {
+            ::redis::Value::BulkString(bytes) => Self::from_redis(bytes)
+                .map_err(|e| format!("invalid Redis HyperLogLog: {}", e).into()),
+            _ => Err("Redis HyperLogLog is not a bulk string".into()),
+        }
+    }
+
+    fn from_redis_value(v: ::redis::Value) -> Result<Self, ::redis::ParsingError> {
+        Self::from_redis_value_ref(&v)
+    }
+}
+
+/// The dense encoding, see [`HyperLogLog::to_redis_dense`]
+#[cfg(feature = "redis")]
+impl ::redis::ToRedisArgs for HyperLogLog<REDIS_P> {
+    fn write_redis_args<W: ?Sized + ::redis::RedisWrite>(&self, out: &mut W) {
+        out.write_arg(&self.to_redis_dense());
+    }
+}
+
+/// Decode the sparse opcodes into `n` registers
+fn decode_sparse(opcodes: &[u8], n: usize) -> Result<Vec<u8>, HllError> {
+    let mut registers = vec![0; n];
+    let mut index = 0;
+    let mut reader = SliceReader::new(opcodes);
+    while reader.remaining() > 0 {
+        let op = reader.read_u8()?;
+        let (len, value) = match op >> 6 {
+            // ZERO
+            0b00 => ((op & 0x3f) as usize + 1, 0),
+            // XZERO
+            0b01 => (
+                (((op & 0x3f) as usize) << 8 | reader.read_u8()? as usize) + 1,
+                0,
+            ),
+            // VAL
+            _ => ((op & 0x03) as usize + 1, (op >> 2 & 0x1f) + 1),
+        };
+        if index + len > n {
+            return Err(HllError::InvalidRegisterIndex {
+                index: index + len - 1,
+                len: n,
+            });
+        }
+        registers[index..index + len].fill(value);
+        index += len;
+    }
+    if index != n {
+        return Err(HllError::InvalidRegisterLength {
+            expected: n,
+            actual: index,
+        });
+    }
+    Ok(registers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redis_dense() {
+        let mut hll = HyperLogLog::<14>::new();
+        for i in 0..100_000 {
+            hll.add_object(&i);
+        }
+        let bytes = hll.to_redis_dense();
+        // HLL_DENSE_SIZE
+        assert_eq!(bytes.len(), 16 + 12288);
+        assert_eq!(&bytes[..5], b"HYLL\0");
+        assert_eq!(HyperLogLog::<14>::from_redis(&bytes).unwrap(), hll);
+
+        // the cached cardinality is ignored
+        let mut cached = bytes.clone();
+        cached[8..16].copy_from_slice(&1234_u64.to_le_bytes());
+        assert_eq!(HyperLogLog::<14>::from_redis(&cached).unwrap(), hll);
+
+        // register 0 is 1, register 1 is 2, same bits as HLL_DENSE_SET_REGISTER
+        let mut hll = HyperLogLog::<14>::new();
+        hll.registers[0] = 1;
+        hll.registers[1] = 2;
+        assert_eq!(&hll.to_redis_dense()[16..19], [0b1000_0001, 0b0000_0000, 0]);
+    }
+
+    /// Sparse encoding of the registers, same opcodes as redis's `hllDenseToSparse`
+    fn encode_sparse(registers: &[u8]) -> Vec<u8> {
+        let mut bytes = b"HYLL\x01\0\0\0".to_vec();
+        bytes.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0x80]);
+        let mut i = 0;
+        while i < registers.len() {
+            let value = registers[i];
+            let run = registers[i..].iter().take_while(|r| **r == value).count();
+            if value == 0 {
+                let run = run.min(16384);
+                if run > 64 {
+                    bytes.extend_from_slice(&[0x40 | ((run - 1) >> 8) as u8, (run - 1) as u8]);
+                } else {
+                    bytes.push((run - 1) as u8);
+                }
+                i += run;
+            } else {
+                let run = run.min(4);
+                bytes.push(0x80 | (value - 1) << 2 | (run - 1) as u8);
+                i += run;
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_redis_sparse() {
+        // an empty key: a single XZERO covering all the registers
+        assert_eq!(
+            HyperLogLog::<14>::from_redis(&encode_sparse(&[0; 16384])).unwrap(),
+            HyperLogLog::<14>::new()
+        );
+        assert_eq!(encode_sparse(&[0; 16384])[16..], [0x7f, 0xff]);
+
+        let mut hll = HyperLogLog::<14>::new();
+        for i in 0..1000 {
+            hll.add_object(&i);
+        }
+        // VAL only holds values up to 32
+        for r in hll.registers.iter_mut() {
+            *r = (*r).min(32);
+        }
+        hll.registers[16380..].fill(3);
+        let bytes = encode_sparse(&hll.registers);
+        assert!(bytes.len() < hll.to_redis_dense().len());
+        assert_eq!(HyperLogLog::<14>::from_redis(&bytes).unwrap(), hll);
+
+        // ZERO, VAL and XZERO runs
+        let mut registers = vec![0; 16384];
+        registers[2..5].fill(7);
+        let opcodes = [0x01, 0x80 | 6 << 2 | 2, 0x40 | 0x3f, 0xff - 5];
+        let mut bytes = encode_sparse(&[0; 16384]);
+        bytes.truncate(16);
+        bytes.extend_from_slice(&opcodes);
+        assert_eq!(
+            HyperLogLog::<14>::from_redis(&bytes).unwrap(),
+            HyperLogLog::<14>::with_registers(registers)
+        );
+    }
+
+    #[test]
+    fn test_redis_sparse_invalid() {
+        let mut bytes = encode_sparse(&[0; 16384]);
+        bytes.truncate(16);
+
+        // runs don't cover all the registers
+        let mut invalid = bytes.clone();
+        invalid.push(0x00);
+        assert_eq!(
+            HyperLogLog::<14>::from_redis(&invalid),
+            Err(HllError::InvalidRegisterLength {
+                expected: 16384,
+                actual: 1
+            })
+        );
+
+        // runs overflow the registers
+        let mut invalid = bytes.clone();
+        invalid.extend_from_slice(&[0x7f, 0xff, 0x00]);
+        assert_eq!(
+            HyperLogLog::<14>::from_redis(&invalid),
+            Err(HllError::InvalidRegisterIndex {
+                index: 16384,
+                len: 16384
+            })
+        );
+
+        // truncated XZERO
+        let mut invalid = bytes;
+        invalid.push(0x7f);
+        assert_eq!(
+            HyperLogLog::<14>::from_redis(&invalid),
+            Err(HllError::UnexpectedEof)
+        );
+    }
+
+    #[cfg(feature = "redis")]
+    #[test]
+    fn test_redis_values() {
+        use ::redis::FromRedisValue;
+        use ::redis::ToRedisArgs;
+        use ::redis::Value;
+
+        let mut hll = HyperLogLog::<14>::new();
+        for i in 0..1000 {
+            hll.add_object(&i);
+        }
+        assert_eq!(hll.to_redis_args(), [hll.to_redis_dense()]);
+
+        let value = Value::BulkString(encode_sparse(&hll.registers));
+        assert_eq!(
+            HyperLogLog::<14>::from_redis_value_ref(&value).unwrap(),
+            hll
+        );
+        let value = Value::BulkString(hll.to_redis_dense());
+        assert_eq!(HyperLogLog::<14>::from_redis_value(value).unwrap(), hll);
+        // a missing key
+        assert_eq!(
+            Option::<HyperLogLog<14>>::from_redis_value(Value::Nil).unwrap(),
+            None
+        );
+
+        assert!(HyperLogLog::<14>::from_redis_value(Value::Int(1)).is_err());
+        let value = Value::BulkString(b"not a HyperLogLog".to_vec());
+        assert!(HyperLogLog::<14>::from_redis_value(value).is_err());
+    }
+
+    #[test]
+    fn test_redis_invalid() {
+        let bytes = HyperLogLog::<14>::new().to_redis_dense();
+
+        let mut invalid = bytes.clone();
+        invalid[0] = b'h';
+        assert_eq!(
+            HyperLogLog::<14>::from_redis(&invalid),
+            Err(HllError::InvalidMagic)
+        );
+
+        let mut invalid = bytes.clone();
+        invalid[4] = 2;
+        assert_eq!(
+            HyperLogLog::<14>::from_redis(&invalid),
+            Err(HllError::UnsupportedRepresentation(2))
+        );
+
+        assert_eq!(
+            HyperLogLog::<14>::from_redis(&bytes[..bytes.len() - 1]),
+            Err(HllError::UnexpectedEof)
+        );
+
+        // 63 doesn't fit in a register of P = 14
+        let mut invalid = bytes;
+        invalid[16] = 0x3f;
+        assert_eq!(
+            HyperLogLog::<14>::from_redis(&invalid),
+            Err(HllError::InvalidRegisterValue {
+                index: 0,
+                value: 63,
+                max: 51
+            })
+        );
+    }
+}
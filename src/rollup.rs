@@ -0,0 +1,426 @@
+//! Sketches of time buckets at several granularities, e.g. distinct users per minute, hour
+//! and day.
+
+use core::hash::Hash;
+use std::collections::BTreeMap;
+
+use crate::Hasher;
+#[cfg(any(feature = "serde", feature = "borsh"))]
+use crate::HllError;
+use crate::HyperLogLog;
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+struct Level<const P: usize> {
+    width: u64,
+    /// How long buckets are kept after they end
+    retention: Option<u64>,
+    /// Buckets by start timestamp
+    buckets: BTreeMap<u64, HyperLogLog<P>>,
+}
+
+impl<const P: usize> Level<P> {
+    fn start(&self, timestamp: u64) -> u64 {
+        timestamp - timestamp % self.width
+    }
+
+    /// The buckets starting before it ended more than the retention before `watermark`
+    fn expiry(&self, watermark: u64) -> u64 {
+        self.retention.map_or(0, |retention| {
+            watermark
+                .saturating_add(1)
+                .saturating_sub(self.width.saturating_add(retention))
+        })
+    }
+
+    fn bucket_mut(&mut self, start: u64) -> &mut HyperLogLog<P> {
+        self.buckets.entry(start).or_default()
+    }
+}
+
+/// Sketches of time buckets at several granularities, each one a multiple of the previous
+/// one, e.g. minutes, hours and days. Items are added to the bucket of the finest
+/// granularity, once the watermark (the latest timestamp seen) moves past the end of a
+/// bucket, the bucket is folded into the bucket of the next granularity, so a coarse bucket
+/// costs a merge per finer bucket rather than an update per item. Late items, whose
+/// buckets are already folded, are added to the coarser buckets too.
+///
+/// Timestamps are in any unit, e.g. unix seconds, granularities are in the same unit.
+///
+/// Buckets are kept forever unless a retention is set for their granularity with
+/// [`Self::set_retention`], then [`Self::gc`] removes the expired ones.
+///
+/// With feature `serde` or `borsh`, the whole state is serialized, the granularities, their
+/// retention, the watermark and every bucket in the sparse-aware form of [`HyperLogLog`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "borsh", derive(borsh::BorshSerialize))]
+pub struct RollupHll<const P: usize> {
+    /// From the finest to the coarsest granularity
+    levels: Vec<Level<P>>,
+    watermark: u64,
+}
+
+impl<const P: usize> RollupHll<P> {
+    /// Buckets of every granularity, e.g. `&[60, 3600, 86400]` for minutes, hours and days
+    /// of unix seconds.
+    ///
+    /// # Panics
+    ///
+    /// If there's no granularity, the first one is zero, or one isn't a multiple of the
+    /// previous one.
+    pub fn new(granularities: &[u64]) -> Self {
+        assert!(
+            granularities.first().is_some_and(|width| *width > 0),
+            "the first granularity must be larger than 0"
+        );
+        assert!(
+            granularities.windows(2).all(|pair| pair[1] % pair[0] == 0),
+            "every granularity must be a multiple of the previous one"
+        );
+        Self {
+            levels: granularities
+                .iter()
+                .map(|width| Level {
+                    width: *width,
+                    retention: None,
+                    buckets: BTreeMap::new(),
+                })
+                .collect(),
+            watermark: 0,
+        }
+    }
+
+    pub fn granularities(&self) -> impl Iterator<Item = u64> + '_ {
+        self.levels.iter().map(|level| level.width)
+    }
+
+    /// Keep the buckets of `granularity` for `retention` after they end, forever if `None`.
+    /// Expired buckets are removed by [`Self::gc`], late items of expired buckets are only
+    /// added to the coarser buckets still kept.
+    ///
+    /// # Panics
+    ///
+    /// If `granularity` isn't one of the granularities.
+    pub fn set_retention(&mut self, granularity: u64, retention: Option<u64>) {
+        let level = self
+            .levels
+            .iter_mut()
+            .find(|level| level.width == granularity)
+            .expect("unknown granularity");
+        level.retention = retention;
+    }
+
+    /// The latest timestamp seen, by an add or [`Self::advance`]
+    pub fn watermark(&self) -> u64 {
+        self.watermark
+    }
+
+    #[inline]
+    pub fn add_hash(&mut self, timestamp: u64, hash: u64) {
+        self.advance(timestamp);
+        let watermark = self.watermark;
+        for level in &mut self.levels {
+            let start = level.start(timestamp);
+            if start < level.expiry(watermark) {
+                continue;
+            }
+            level.bucket_mut(start).add_hash(hash);
+            // the bucket of the watermark is the only one not folded yet
+            if start == level.start(watermark) {
+                break;
+            }
+        }
+    }
+
+    pub fn add_object<T: Hash>(&mut self, timestamp: u64, obj: &T) {
+        self.add_object_by_hasher::<T, ahash::AHasher>(timestamp, obj);
+    }
+
+    #[inline]
+    pub fn add_object_by_hasher<T: Hash, H: Hasher>(&mut self, timestamp: u64, obj: &T) {
+        self.add_hash(timestamp, H::hll_hash(obj));
+    }
+
+    /// Move the watermark to `now` if it's later, the buckets which ended are folded into
+    /// the next granularity. Every add advances to its timestamp, call it periodically to
+    /// fold the buckets of idle periods too.
+    pub fn advance(&mut self, now: u64) {
+        if now <= self.watermark {
+            return;
+        }
+        for i in 1..self.levels.len() {
+            let (fine, coarse) = self.levels.split_at_mut(i);
+            let (fine, coarse) = (&fine[i - 1], &mut coarse[0]);
+            let ended = fine.start(self.watermark)..fine.start(now);
+            for (start, hll) in fine.buckets.range(ended) {
+                coarse.bucket_mut(coarse.start(*start)).merge(hll);
+            }
+        }
+        self.watermark = now;
+    }
+
+    /// The sketch of the bucket of `granularity` containing `timestamp`, including the
+    /// items of its finer buckets not folded yet. `None` if it has no item, or
+    /// `granularity` isn't one of the granularities.
+    pub fn get(&self, granularity: u64, timestamp: u64) -> Option<HyperLogLog<P>> {
+        let i = self
+            .levels
+            .iter()
+            .position(|level| level.width == granularity)?;
+        let start = self.levels[i].start(timestamp);
+        let mut hll = self.levels[i].buckets.get(&start).cloned();
+        for fine in &self.levels[..i] {
+            let unfolded = fine.start(self.watermark);
+            if (start..start + granularity).contains(&unfolded) {
+                if let Some(bucket) = fine.buckets.get(&unfolded) {
+                    hll.get_or_insert_with(HyperLogLog::new).merge(bucket);
+                }
+            }
+        }
+        hll
+    }
+
+    /// The estimate of [`Self::get`], `0` if it's `None`
+    pub fn count(&self, granularity: u64, timestamp: u64) -> usize {
+        self.get(granularity, timestamp)
+            .map_or(0, |hll| hll.count())
+    }
+
+    /// Remove the buckets which expired according to the retention of their granularity, and
+    /// return them as `(granularity, start, sketch)` to be archived, e.g. in a database.
+    /// Expired buckets are already folded into the coarser granularity.
+    pub fn gc(&mut self) -> Vec<(u64, u64, HyperLogLog<P>)> {
+        let watermark = self.watermark;
+        let mut expired = vec![];
+        for level in &mut self.levels {
+            let kept = level.buckets.split_off(&level.expiry(watermark));
+            let removed = core::mem::replace(&mut level.buckets, kept);
+            expired.extend(
+                removed
+                    .into_iter()
+                    .map(|(start, hll)| (level.width, start, hll)),
+            );
+        }
+        expired
+    }
+
+    /// The buckets of `granularity` by start timestamp. Unlike [`Self::get`], the bucket of
+    /// the watermark only holds the finer buckets folded so far, the others are complete.
+    pub fn buckets(&self, granularity: u64) -> impl Iterator<Item = (u64, &HyperLogLog<P>)> {
+        self.levels
+            .iter()
+            .filter(move |level| level.width == granularity)
+            .flat_map(|level| level.buckets.iter().map(|(start, hll)| (*start, hll)))
+    }
+}
+
+/// The serialized fields of [`RollupHll`], the granularities are checked like in
+/// [`RollupHll::new`] before use
+#[cfg(any(feature = "serde", feature = "borsh"))]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "borsh", derive(borsh::BorshDeserialize))]
+struct State<const P: usize> {
+    levels: Vec<Level<P>>,
+    watermark: u64,
+}
+
+#[cfg(any(feature = "serde", feature = "borsh"))]
+impl<const P: usize> TryFrom<State<P>> for RollupHll<P> {
+    type Error = HllError;
+
+    fn try_from(state: State<P>) -> Result<Self, HllError> {
+        let widths = || state.levels.iter().map(|level| level.width);
+        if state.levels.is_empty() || widths().any(|width| width == 0) {
+            return Err(HllError::Malformed(
+                "rollup: every granularity must be larger than 0".to_string(),
+            ));
+        }
+        if !state
+            .levels
+            .windows(2)
+            .all(|pair| pair[1].width % pair[0].width == 0)
+        {
+            return Err(HllError::Malformed(
+                "rollup: every granularity must be a multiple of the previous one".to_string(),
+            ));
+        }
+        Ok(Self {
+            levels: state.levels,
+            watermark: state.watermark,
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, const P: usize> serde::Deserialize<'de> for RollupHll<P> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        State::deserialize(deserializer)?
+            .try_into()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl<const P: usize> borsh::BorshDeserialize for RollupHll<P> {
+    fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        State::deserialize_reader(reader)?
+            .try_into()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = 3600;
+    const DAY: u64 = 86400;
+
+    #[test]
+    fn test_rollup() {
+        let mut rollup = RollupHll::<12>::new(&[MINUTE, HOUR, DAY]);
+        let mut hours = vec![HyperLogLog::<12>::new(); 3];
+        let mut day = HyperLogLog::<12>::new();
+        // a user every 10 seconds over 3 hours
+        for t in (DAY..DAY + 3 * HOUR).step_by(10) {
+            rollup.add_object(t, &t);
+            hours[((t - DAY) / HOUR) as usize].add_object(&t);
+            day.add_object(&t);
+        }
+        assert_eq!(rollup.watermark(), DAY + 3 * HOUR - 10);
+
+        // the first two hours are folded, the last one only partially
+        let folded = rollup.buckets(HOUR).collect::<Vec<_>>();
+        assert_eq!(folded.len(), 3);
+        assert_eq!(folded[0], (DAY, &hours[0]));
+        assert_eq!(folded[1], (DAY + HOUR, &hours[1]));
+        assert!(folded[2].1 != &hours[2]);
+        assert_eq!(rollup.get(HOUR, DAY + 2 * HOUR + 5).unwrap(), hours[2]);
+        assert_eq!(rollup.get(DAY, DAY).unwrap(), day);
+        assert_eq!(rollup.count(DAY, DAY + 1), day.count());
+        assert_eq!(rollup.buckets(MINUTE).count(), 180);
+
+        // a late item is added to the folded buckets too
+        rollup.add_object(DAY + 5, &"late");
+        hours[0].add_object(&"late");
+        day.add_object(&"late");
+        assert_eq!(rollup.buckets(HOUR).next().unwrap().1, &hours[0]);
+        assert_eq!(rollup.get(DAY, DAY).unwrap(), day);
+        assert_eq!(rollup.watermark(), DAY + 3 * HOUR - 10);
+
+        // idle, the last hour and the day are folded
+        rollup.advance(2 * DAY);
+        assert_eq!(rollup.buckets(HOUR).nth(2).unwrap().1, &hours[2]);
+        assert_eq!(rollup.buckets(DAY).collect::<Vec<_>>(), [(DAY, &day)]);
+
+        assert_eq!(rollup.get(HOUR, 0), None);
+        assert_eq!(rollup.get(2 * HOUR, DAY), None);
+        assert_eq!(rollup.count(DAY, 3 * DAY), 0);
+        assert_eq!(
+            rollup.granularities().collect::<Vec<_>>(),
+            [MINUTE, HOUR, DAY]
+        );
+    }
+
+    #[test]
+    fn test_rollup_retention() {
+        let mut rollup = RollupHll::<12>::new(&[MINUTE, HOUR, DAY]);
+        rollup.set_retention(MINUTE, Some(HOUR));
+        rollup.set_retention(HOUR, Some(HOUR));
+        for t in (0..3 * HOUR).step_by(10) {
+            rollup.add_object(t, &t);
+        }
+        rollup.advance(3 * HOUR);
+        let hour = rollup.get(HOUR, 0).unwrap();
+        let day = rollup.get(DAY, 0).unwrap();
+
+        // the minutes of the first two hours and the first two hours ended an hour ago
+        let expired = rollup.gc();
+        assert_eq!(expired.len(), 122);
+        assert!(expired[..120]
+            .iter()
+            .enumerate()
+            .all(|(i, (width, start, _))| (*width, *start) == (MINUTE, i as u64 * MINUTE)));
+        assert_eq!(expired[120], (HOUR, 0, hour));
+        assert_eq!(rollup.buckets(MINUTE).count(), 60);
+        assert_eq!(rollup.buckets(HOUR).count(), 1);
+        assert!(rollup.gc().is_empty());
+
+        // a late item of an expired bucket is only added to the day
+        rollup.add_object(5, &"late");
+        assert_eq!(rollup.get(MINUTE, 5), None);
+        assert_eq!(rollup.get(HOUR, 5), None);
+        assert!(rollup.get(DAY, 0).unwrap() != day);
+
+        rollup.set_retention(MINUTE, None);
+        rollup.advance(DAY);
+        assert_eq!(rollup.gc().len(), 1);
+        assert_eq!(rollup.buckets(MINUTE).count(), 60);
+        assert_eq!(rollup.buckets(DAY).count(), 1);
+    }
+
+    #[cfg(any(feature = "serde", feature = "borsh"))]
+    fn rollup() -> RollupHll<12> {
+        let mut rollup = RollupHll::<12>::new(&[MINUTE, HOUR, DAY]);
+        rollup.set_retention(MINUTE, Some(HOUR));
+        for t in (0..3 * HOUR).step_by(10) {
+            rollup.add_object(t, &t);
+        }
+        rollup
+    }
+
+    #[cfg(any(feature = "serde", feature = "borsh"))]
+    fn assert_same(read: &RollupHll<12>, rollup: &RollupHll<12>) {
+        assert_eq!(read.watermark(), rollup.watermark());
+        for width in rollup.granularities() {
+            assert!(read.buckets(width).eq(rollup.buckets(width)));
+        }
+        let (mut read, mut rollup) = (read.clone(), rollup.clone());
+        read.advance(DAY);
+        rollup.advance(DAY);
+        // the retention too
+        assert_eq!(read.gc(), rollup.gc());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_rollup_serde() {
+        let rollup = rollup();
+        let json = serde_json::to_string(&rollup).unwrap();
+        // the single item minutes keep their sparse form
+        assert!(json.contains(r#""0":{"Precision":[12,{"Sparse":"#));
+        assert_same(&serde_json::from_str(&json).unwrap(), &rollup);
+
+        let bytes = bincode::serialize(&rollup).unwrap();
+        assert_same(&bincode::deserialize(&bytes).unwrap(), &rollup);
+
+        let json = json.replacen(r#""width":3600"#, r#""width":90"#, 1);
+        let error = serde_json::from_str::<RollupHll<12>>(&json).unwrap_err();
+        assert!(error.to_string().contains("multiple"), "{}", error);
+    }
+
+    #[cfg(feature = "borsh")]
+    #[test]
+    fn test_rollup_borsh() {
+        let rollup = rollup();
+        let bytes = borsh::to_vec(&rollup).unwrap();
+        assert_same(&borsh::from_slice(&bytes).unwrap(), &rollup);
+
+        let mut invalid = RollupHll::<12>::new(&[MINUTE]);
+        invalid.levels[0].width = 0;
+        let error = borsh::from_slice::<RollupHll<12>>(&borsh::to_vec(&invalid).unwrap());
+        assert!(error.unwrap_err().to_string().contains("larger than 0"));
+    }
+
+    #[test]
+    #[should_panic(expected = "multiple")]
+    fn test_rollup_invalid() {
+        RollupHll::<12>::new(&[MINUTE, 90]);
+    }
+}
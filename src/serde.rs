@@ -1,101 +1,866 @@
+#[cfg(feature = "borsh")]
+use std::io::Write;
+use std::marker::PhantomData;
+
+#[cfg(feature = "borsh")]
+use borsh::BorshSerialize;
+#[cfg(feature = "serde")]
+use serde::ser::SerializeSeq;
+
+use crate::compressed;
+#[cfg(feature = "serde")]
+use crate::encoding;
+use crate::hyperloglog::MAX_P;
+use crate::packed;
+use crate::HllError;
 use crate::HyperLogLog;
 
-#[derive(serde::Serialize, borsh::BorshSerialize)]
+/// New variants must be appended to keep the variant tags stable.
+/// `Full` is not produced anymore but still accepted for backward compatibility.
+/// Binary formats wrap the registers in `Precision`, so they can be read into a sketch of
+/// another precision.
+///
+/// The payloads are serialized lazily from the registers, in exactly the same shape
+/// as the owned `Vec`s of [`HyperLogLogVariant`], without allocating.
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "borsh", derive(borsh::BorshSerialize))]
 enum HyperLogLogVariantRef<'a> {
     Empty,
-    Sparse { data: Vec<(u16, u8)> },
+    Sparse {
+        data: SparseRegisters<'a, u16>,
+    },
+    #[allow(dead_code)]
     Full(&'a Vec<u8>),
+    /// Dense registers packed into 6 bits each
+    Packed(PackedRegisters<'a>),
+    /// Sparse registers for P > 16, whose indexes don't fit in `u16`
+    SparseWide {
+        data: SparseRegisters<'a, u32>,
+    },
+    /// Varint encoded registers, see [`compressed`]
+    Compressed(CompressedRegisters<'a>),
+    /// The precision of the sketch and one of the variants above
+    Precision(u8, &'a HyperLogLogVariantRef<'a>),
+}
+
+trait SparseIndex {
+    fn from_index(index: usize) -> Self;
+
+    fn to_index(self) -> usize;
+}
+
+impl SparseIndex for u16 {
+    fn from_index(index: usize) -> Self {
+        index as u16
+    }
+
+    fn to_index(self) -> usize {
+        self as usize
+    }
+}
+
+impl SparseIndex for u32 {
+    fn from_index(index: usize) -> Self {
+        index as u32
+    }
+
+    fn to_index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Non-zero registers, serialized as `Vec<(I, u8)>`
+struct SparseRegisters<'a, I> {
+    registers: &'a [u8],
+    len: usize,
+    _index: PhantomData<I>,
 }
 
-#[derive(serde::Deserialize, borsh::BorshDeserialize)]
+impl<'a, I: SparseIndex> SparseRegisters<'a, I> {
+    fn new(registers: &'a [u8], len: usize) -> Self {
+        Self {
+            registers,
+            len,
+            _index: PhantomData,
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (I, u8)> + 'a {
+        self.registers
+            .iter()
+            .enumerate()
+            .filter(|(_, &value)| value != 0)
+            .map(|(index, &value)| (I::from_index(index), value))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<I: SparseIndex + serde::Serialize> serde::Serialize for SparseRegisters<'_, I> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.len))?;
+        for entry in self.iter() {
+            seq.serialize_element(&entry)?;
+        }
+        seq.end()
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl<I: SparseIndex + BorshSerialize> BorshSerialize for SparseRegisters<'_, I> {
+    /// Entries are buffered on the stack, instead of one small write per entry
+    fn serialize<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        (self.len as u32).serialize(writer)?;
+        const BUF_SIZE: usize = 1024;
+        let mut buf = [0_u8; BUF_SIZE];
+        let mut used = 0;
+        for entry in self.iter() {
+            // an entry takes at most 5 bytes
+            if BUF_SIZE - used < 5 {
+                writer.write_all(&buf[..used])?;
+                used = 0;
+            }
+            let mut free = &mut buf[used..];
+            entry.serialize(&mut free)?;
+            used = BUF_SIZE - free.len();
+        }
+        writer.write_all(&buf[..used])
+    }
+}
+
+/// Registers packed into 6 bits each, serialized as `Vec<u8>`
+struct PackedRegisters<'a>(&'a [u8]);
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for PackedRegisters<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_bytes(
+            serializer,
+            packed::packed_len(self.0.len()),
+            packed::iter(self.0),
+        )
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl BorshSerialize for PackedRegisters<'_> {
+    fn serialize<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        write_bytes(
+            writer,
+            packed::packed_len(self.0.len()),
+            packed::iter(self.0),
+        )
+    }
+}
+
+/// Varint encoded registers, serialized as `Vec<u8>`
+struct CompressedRegisters<'a> {
+    registers: &'a [u8],
+    len: usize,
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for CompressedRegisters<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_bytes(serializer, self.len, compressed::iter(self.registers))
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl BorshSerialize for CompressedRegisters<'_> {
+    fn serialize<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        write_bytes(writer, self.len, compressed::iter(self.registers))
+    }
+}
+
+/// Serialize as serde bytes, one blob in formats with a native binary type (MessagePack,
+/// CBOR...), the same bytes as a `Vec<u8>` in the others (bincode, bitcode...)
+#[cfg(feature = "serde")]
+fn serialize_bytes<S: serde::Serializer>(
+    serializer: S,
+    len: usize,
+    bytes: impl Iterator<Item = u8>,
+) -> Result<S::Ok, S::Error> {
+    let mut buf = Vec::with_capacity(len);
+    buf.extend(bytes);
+    serializer.serialize_bytes(&buf)
+}
+
+/// Serde bytes or a sequence of `u8`
+#[cfg(feature = "serde")]
+struct BytesVisitor;
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str("bytes")
+    }
+
+    fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(v.to_vec())
+    }
+
+    fn visit_byte_buf<E: serde::de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(v)
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        // don't trust the size hint of untrusted input
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(1 << 16));
+        while let Some(b) = seq.next_element()? {
+            bytes.push(b);
+        }
+        Ok(bytes)
+    }
+}
+
+/// Deserialize serde bytes, or a sequence of `u8` as written by older versions
+#[cfg(feature = "serde")]
+pub(crate) fn deserialize_bytes<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<u8>, D::Error> {
+    deserializer.deserialize_byte_buf(BytesVisitor)
+}
+
+/// Same as writing a `Vec<u8>` with borsh, buffered on the stack
+#[cfg(feature = "borsh")]
+fn write_bytes<W: Write>(
+    writer: &mut W,
+    len: usize,
+    mut bytes: impl Iterator<Item = u8>,
+) -> std::io::Result<()> {
+    writer.write_all(&(len as u32).to_le_bytes())?;
+    let mut buf = [0_u8; 256];
+    loop {
+        let mut n = 0;
+        for (slot, b) in buf.iter_mut().zip(&mut bytes) {
+            *slot = b;
+            n += 1;
+        }
+        if n == 0 {
+            return Ok(());
+        }
+        writer.write_all(&buf[..n])?;
+    }
+}
+
+/// Variants written by newer versions are reported as [`HllError::UnsupportedRepresentation`]
+/// instead of a generic error, the serde implementation is written by hand for that.
+/// The registers are read whatever their precision, they're checked by [`Payload::into_sketch`].
+#[cfg_attr(feature = "borsh", derive(borsh::BorshDeserialize))]
 enum HyperLogLogVariant {
     Empty,
-    Sparse { data: Vec<(u16, u8)> },
+    Sparse { data: SparseInput<u16> },
     Full(Vec<u8>),
+    Packed(Vec<u8>),
+    SparseWide { data: SparseInput<u32> },
+    Compressed(Vec<u8>),
 }
 
-impl<const P: usize> From<HyperLogLogVariant> for HyperLogLog<P> {
-    fn from(value: HyperLogLogVariant) -> Self {
-        match value {
-            HyperLogLogVariant::Empty => HyperLogLog::<P>::new(),
-            HyperLogLogVariant::Sparse { data } => {
-                let mut registers = vec![0; 1 << P];
-                for (index, val) in data {
-                    registers[index as usize] = val;
+/// Tag of the `Precision` variant, which wraps one of the other variants
+#[cfg(feature = "borsh")]
+const PRECISION_TAG: u8 = 6;
+
+/// A variant and the precision of the sketch, `None` if the payload was written before the
+/// precision was carried, it's then read as precision `P`
+struct Payload {
+    precision: Option<u8>,
+    variant: HyperLogLogVariant,
+}
+
+impl Payload {
+    /// Registers of a higher precision are folded into `P` like the binary format does
+    fn into_sketch<const P: usize>(self) -> Result<HyperLogLog<P>, HllError> {
+        let p = match self.precision {
+            None => P,
+            Some(p) => {
+                let p = p as usize;
+                // before allocating the registers
+                if p > MAX_P {
+                    return Err(HllError::InvalidPrecision(p));
+                }
+                if p < P {
+                    return Err(HllError::PrecisionMismatch {
+                        expected: P,
+                        actual: p,
+                    });
                 }
+                p
+            }
+        };
+        let m = 1 << p;
+        let registers = match self.variant {
+            HyperLogLogVariant::Empty => vec![0; m],
+            HyperLogLogVariant::Sparse { data } => data.into_registers(m)?,
+            HyperLogLogVariant::SparseWide { data } => data.into_registers(m)?,
+            HyperLogLogVariant::Full(registers) => registers,
+            HyperLogLogVariant::Packed(data) => {
+                if data.len() != packed::packed_len(m) {
+                    return Err(HllError::InvalidRegisterLength {
+                        expected: m,
+                        actual: data.len() * 8 / 6,
+                    });
+                }
+                packed::unpack(&data, m)
+            }
+            HyperLogLogVariant::Compressed(data) => compressed::decode(&data, m)?,
+        };
+        HyperLogLog::<P>::try_from_precision(p, registers)
+    }
+}
+
+/// Non-zero registers read as `Vec<(I, u8)>`, written straight into the dense registers
+/// without collecting the entries first. The precision isn't known yet, the registers grow
+/// up to the largest index.
+struct SparseInput<I> {
+    registers: Vec<u8>,
+    _index: PhantomData<I>,
+}
+
+impl<I: SparseIndex> SparseInput<I> {
+    fn new() -> Self {
+        Self {
+            registers: vec![],
+            _index: PhantomData,
+        }
+    }
 
-                HyperLogLog::<P>::with_registers(registers)
+    /// The values are checked by [`Payload::into_sketch`]
+    fn set(&mut self, index: I, value: u8) -> Result<(), HllError> {
+        let index = index.to_index();
+        let len = 1 << MAX_P;
+        if index >= len {
+            return Err(HllError::InvalidRegisterIndex { index, len });
+        }
+        if index >= self.registers.len() {
+            self.registers.resize(index + 1, 0);
+        }
+        self.registers[index] = value;
+        Ok(())
+    }
+
+    /// The `m` registers, every index must be smaller than `m`
+    fn into_registers(mut self, m: usize) -> Result<Vec<u8>, HllError> {
+        if self.registers.len() > m {
+            return Err(HllError::InvalidRegisterIndex {
+                index: self.registers.len() - 1,
+                len: m,
+            });
+        }
+        self.registers.resize(m, 0);
+        Ok(self.registers)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, I> serde::Deserialize<'de> for SparseInput<I>
+where
+    I: SparseIndex + serde::Deserialize<'de>,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct EntriesVisitor<I>(PhantomData<I>);
+
+        impl<'de, I> serde::de::Visitor<'de> for EntriesVisitor<I>
+        where
+            I: SparseIndex + serde::Deserialize<'de>,
+        {
+            type Value = SparseInput<I>;
+
+            fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                f.write_str("sequence of register indexes and values")
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<Self::Value, A::Error> {
+                let mut input = SparseInput::new();
+                while let Some((index, value)) = seq.next_element::<(I, u8)>()? {
+                    input.set(index, value).map_err(serde::de::Error::custom)?;
+                }
+                Ok(input)
+            }
+        }
+
+        deserializer.deserialize_seq(EntriesVisitor(PhantomData))
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl<I> borsh::BorshDeserialize for SparseInput<I>
+where
+    I: SparseIndex + borsh::BorshDeserialize,
+{
+    fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        let len = u32::deserialize_reader(reader)?;
+        let mut input = SparseInput::new();
+        for _ in 0..len {
+            let (index, value) = <(I, u8)>::deserialize_reader(reader)?;
+            input
+                .set(index, value)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        }
+        Ok(input)
+    }
+}
+
+#[cfg(feature = "serde")]
+const VARIANTS: &[&str] = &[
+    "Empty",
+    "Sparse",
+    "Full",
+    "Packed",
+    "SparseWide",
+    "Compressed",
+    "Precision",
+];
+
+/// Index of a variant of [`HyperLogLogVariant`], by index or by name
+#[cfg(feature = "serde")]
+struct VariantTag(usize);
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for VariantTag {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TagVisitor;
+
+        impl serde::de::Visitor<'_> for TagVisitor {
+            type Value = VariantTag;
+
+            fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                f.write_str("variant identifier")
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                if v < VARIANTS.len() as u64 {
+                    Ok(VariantTag(v as usize))
+                } else {
+                    let tag = v.min(u8::MAX as u64) as u8;
+                    Err(E::custom(HllError::UnsupportedRepresentation(tag)))
+                }
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                match VARIANTS.iter().position(|name| *name == v) {
+                    Some(index) => Ok(VariantTag(index)),
+                    None => Err(E::custom(format_args!(
+                        "unsupported representation {:?}",
+                        v
+                    ))),
+                }
+            }
+
+            fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+                match core::str::from_utf8(v) {
+                    Ok(v) => self.visit_str(v),
+                    Err(_) => Err(E::custom("invalid variant identifier")),
+                }
+            }
+        }
+
+        deserializer.deserialize_identifier(TagVisitor)
+    }
+}
+
+/// Serde bytes as written by [`serialize_bytes`], or a sequence of `u8` as written by
+/// older versions
+#[cfg(feature = "serde")]
+struct Bytes(Vec<u8>);
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Bytes {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_bytes(deserializer).map(Bytes)
+    }
+}
+
+/// The `data` field of the sparse variants
+#[cfg(feature = "serde")]
+struct SparseVisitor<T>(PhantomData<T>);
+
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de>> serde::de::Visitor<'de> for SparseVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str("struct variant with field data")
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        seq.next_element()?
+            .ok_or_else(|| serde::de::Error::invalid_length(0, &self))
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut data = None;
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "data" {
+                data = Some(map.next_value()?);
+            } else {
+                map.next_value::<serde::de::IgnoredAny>()?;
             }
-            HyperLogLogVariant::Full(registers) => HyperLogLog::<P>::with_registers(registers),
         }
+        data.ok_or_else(|| serde::de::Error::missing_field("data"))
+    }
+}
+
+/// The variants, `Precision` only at the top level
+#[cfg(feature = "serde")]
+struct VariantVisitor {
+    nested: bool,
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::Visitor<'de> for VariantVisitor {
+    type Value = Payload;
+
+    fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str("enum HyperLogLogVariant")
+    }
+
+    fn visit_enum<A: serde::de::EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        use serde::de::Error;
+        use serde::de::VariantAccess;
+
+        let (tag, variant) = data.variant::<VariantTag>()?;
+        let variant = match tag.0 {
+            0 => {
+                variant.unit_variant()?;
+                HyperLogLogVariant::Empty
+            }
+            1 => HyperLogLogVariant::Sparse {
+                data: variant.struct_variant(&["data"], SparseVisitor(PhantomData))?,
+            },
+            2 => HyperLogLogVariant::Full(variant.newtype_variant::<Bytes>()?.0),
+            3 => HyperLogLogVariant::Packed(variant.newtype_variant::<Bytes>()?.0),
+            4 => HyperLogLogVariant::SparseWide {
+                data: variant.struct_variant(&["data"], SparseVisitor(PhantomData))?,
+            },
+            5 => HyperLogLogVariant::Compressed(variant.newtype_variant::<Bytes>()?.0),
+            _ if self.nested => {
+                return Err(A::Error::custom(HllError::Malformed(
+                    "nested Precision variant".to_string(),
+                )))
+            }
+            _ => return variant.tuple_variant(2, PrecisionVisitor),
+        };
+        Ok(Payload {
+            precision: None,
+            variant,
+        })
+    }
+}
+
+/// The fields of the `Precision` variant
+#[cfg(feature = "serde")]
+struct PrecisionVisitor;
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::Visitor<'de> for PrecisionVisitor {
+    type Value = Payload;
+
+    fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str("tuple variant Precision")
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        use serde::de::Error;
+
+        let precision = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+        let nested: Nested = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::invalid_length(1, &self))?;
+        Ok(Payload {
+            precision: Some(precision),
+            variant: nested.0,
+        })
+    }
+}
+
+/// The variant wrapped by `Precision`
+#[cfg(feature = "serde")]
+struct Nested(HyperLogLogVariant);
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Nested {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let payload = deserializer.deserialize_enum(
+            "HyperLogLogVariant",
+            VARIANTS,
+            VariantVisitor { nested: true },
+        )?;
+        Ok(Nested(payload.variant))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Payload {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_enum(
+            "HyperLogLogVariant",
+            VARIANTS,
+            VariantVisitor { nested: false },
+        )
     }
 }
 
 impl<'a, const P: usize> From<&'a HyperLogLog<P>> for HyperLogLogVariantRef<'a> {
     fn from(hll: &'a HyperLogLog<P>) -> Self {
-        let none_empty_registers = HyperLogLog::<P>::number_registers() - hll.num_empty_registers();
+        let none_empty_registers = HyperLogLog::<P>::NUM_REGISTERS - hll.num_empty_registers();
+        // Register indexes don't fit in u16 if P > 16
+        let wide = P > 16;
+        // each register in sparse format will occupy 3 bytes, 2 for register index and 1 for register value,
+        // or 5 bytes if the index is stored in u32.
+        let sparse_size = if wide { 5 } else { 3 };
+        let packed_len = packed::packed_len(HyperLogLog::<P>::NUM_REGISTERS);
 
         if none_empty_registers == 0 {
             HyperLogLogVariantRef::Empty
-        } else if none_empty_registers * 3 <= HyperLogLog::<P>::number_registers() {
+        } else if none_empty_registers * sparse_size <= packed_len {
             // If the number of empty registers is larger enough, we can use sparse serialize to reduce the binary size
-            // each register in sparse format will occupy 3 bytes, 2 for register index and 1 for register value.
-            let sparse_data: Vec<(u16, u8)> = hll
-                .registers
-                .iter()
-                .enumerate()
-                .filter_map(|(index, &value)| {
-                    if value != 0 {
-                        Some((index as u16, value))
-                    } else {
-                        None
-                    }
-                })
-                .collect();
-
-            HyperLogLogVariantRef::Sparse { data: sparse_data }
+            if wide {
+                HyperLogLogVariantRef::SparseWide {
+                    data: SparseRegisters::new(&hll.registers, none_empty_registers),
+                }
+            } else {
+                HyperLogLogVariantRef::Sparse {
+                    data: SparseRegisters::new(&hll.registers, none_empty_registers),
+                }
+            }
         } else {
-            HyperLogLogVariantRef::Full(&hll.registers)
+            // half-full sketches are usually smaller with the compressed encoding
+            let len = compressed::encoded_len(&hll.registers);
+            if len < packed_len {
+                HyperLogLogVariantRef::Compressed(CompressedRegisters {
+                    registers: &hll.registers,
+                    len,
+                })
+            } else {
+                HyperLogLogVariantRef::Packed(PackedRegisters(&hll.registers))
+            }
         }
     }
 }
 
+/// Human-readable formats (JSON, YAML...) store a dense sketch as a base64 string of its
+/// 6-bit packed registers, a sparse sketch as the variant, e.g.
+/// `{"Precision":[14,{"Sparse":{"data":[[17,2]]}}]}`, and an empty sketch is still the
+/// `"Empty"` unit variant. Binary formats store the variant, see [`HyperLogLogVariantRef`].
+#[cfg(feature = "serde")]
 impl<const P: usize> serde::Serialize for HyperLogLog<P> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
         let v: HyperLogLogVariantRef<'_> = self.into();
-        v.serialize(serializer)
+        match v {
+            HyperLogLogVariantRef::Empty if serializer.is_human_readable() => {
+                serde::Serialize::serialize(&v, serializer)
+            }
+            HyperLogLogVariantRef::Packed(_) | HyperLogLogVariantRef::Compressed(_)
+                if serializer.is_human_readable() =>
+            {
+                serializer.collect_str(&Base64Registers(&self.registers))
+            }
+            _ => serde::Serialize::serialize(
+                &HyperLogLogVariantRef::Precision(P as u8, &v),
+                serializer,
+            ),
+        }
+    }
+}
+
+/// Displays the registers as base64 of the packed registers
+#[cfg(feature = "serde")]
+struct Base64Registers<'a>(&'a [u8]);
+
+#[cfg(feature = "serde")]
+impl core::fmt::Display for Base64Registers<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        encoding::write_base64(f, packed::iter(self.0))
+    }
+}
+
+/// Human-readable formats accept both the base64 string and the variants, dispatched on
+/// the shape of the value so that the errors of the variants are kept
+#[cfg(feature = "serde")]
+struct HumanReadableVisitor;
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::Visitor<'de> for HumanReadableVisitor {
+    type Value = Payload;
+
+    fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str("base64 registers or enum HyperLogLogVariant")
+    }
+
+    fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        if v == "Empty" {
+            return Ok(Payload {
+                precision: None,
+                variant: HyperLogLogVariant::Empty,
+            });
+        }
+        let data = encoding::from_base64(v).ok_or_else(|| E::custom("invalid base64 registers"))?;
+        Ok(Payload {
+            precision: packed_precision(data.len()),
+            variant: HyperLogLogVariant::Packed(data),
+        })
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        VariantVisitor { nested: false }
+            .visit_enum(serde::de::value::MapAccessDeserializer::new(map))
+    }
+
+    fn visit_enum<A: serde::de::EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        VariantVisitor { nested: false }.visit_enum(data)
     }
 }
 
+#[cfg(feature = "serde")]
 impl<'de, const P: usize> serde::Deserialize<'de> for HyperLogLog<P> {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        let v = HyperLogLogVariant::deserialize(deserializer)?;
-        Ok(v.into())
+        use serde::de::Error;
+
+        let payload = if deserializer.is_human_readable() {
+            deserializer.deserialize_any(HumanReadableVisitor)?
+        } else {
+            Payload::deserialize(deserializer)?
+        };
+        payload.into_sketch().map_err(D::Error::custom)
     }
 }
 
-impl<const P: usize> borsh::BorshSerialize for HyperLogLog<P> {
-    fn serialize<W: std::io::prelude::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+/// The precision of `len` packed registers, `None` if it's not the packed length of any
+/// precision, the length is then reported as invalid for `P`
+#[cfg(feature = "serde")]
+fn packed_precision(len: usize) -> Option<u8> {
+    let m = len * 8 / 6;
+    (m.is_power_of_two() && packed::packed_len(m) == len && m <= 1 << MAX_P)
+        .then(|| m.trailing_zeros() as u8)
+}
+
+/// Schema of the human-readable form: `"Empty"`, or the base64 of the packed registers.
+/// The enum variants still accepted by the deserializer aren't part of the schema.
+#[cfg(feature = "schemars")]
+impl<const P: usize> schemars::JsonSchema for HyperLogLog<P> {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
+        format!("HyperLogLog_p{}", P).into()
+    }
+
+    fn json_schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        let len = packed::packed_len(HyperLogLog::<P>::NUM_REGISTERS).div_ceil(3) * 4;
+        let (variant, index_max) = if P > 16 {
+            ("SparseWide", u32::MAX as usize)
+        } else {
+            ("Sparse", u16::MAX as usize)
+        };
+        let max = HyperLogLog::<P>::max_register_value();
+        let entries = schemars::json_schema!({
+            "type": "array",
+            "items": {
+                "type": "array",
+                "prefixItems": [
+                    { "type": "integer", "minimum": 0, "maximum": index_max },
+                    { "type": "integer", "minimum": 0, "maximum": max }
+                ],
+                "minItems": 2,
+                "maxItems": 2
+            }
+        });
+        schemars::json_schema!({
+            "description": format!(
+                "HyperLogLog sketch of 2^{} registers, \"Empty\", the [index, value] pairs of the non-zero registers of a sparse sketch, or the base64 of the 6-bit packed registers of a dense sketch",
+                P
+            ),
+            "anyOf": [
+                { "const": "Empty" },
+                {
+                    "type": "string",
+                    "contentEncoding": "base64",
+                    "minLength": len,
+                    "maxLength": len,
+                    "pattern": "^[A-Za-z0-9+/]*={0,2}$"
+                },
+                {
+                    "type": "object",
+                    "properties": {
+                        "Precision": {
+                            "type": "array",
+                            "prefixItems": [
+                                { "const": P },
+                                {
+                                    "type": "object",
+                                    "properties": {
+                                        variant: {
+                                            "type": "object",
+                                            "properties": { "data": entries },
+                                            "required": ["data"]
+                                        }
+                                    },
+                                    "required": [variant]
+                                }
+                            ],
+                            "minItems": 2,
+                            "maxItems": 2
+                        }
+                    },
+                    "required": ["Precision"]
+                }
+            ]
+        })
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl<const P: usize> BorshSerialize for HyperLogLog<P> {
+    fn serialize<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
         let v: HyperLogLogVariantRef<'_> = self.into();
-        v.serialize(writer)
+        HyperLogLogVariantRef::Precision(P as u8, &v).serialize(writer)
     }
 }
 
+#[cfg(feature = "borsh")]
 impl<const P: usize> borsh::BorshDeserialize for HyperLogLog<P> {
     fn deserialize_reader<R: std::io::prelude::Read>(reader: &mut R) -> std::io::Result<Self> {
-        let v = HyperLogLogVariant::deserialize_reader(reader)?;
-        Ok(v.into())
+        use borsh::de::EnumExt;
+
+        let invalid = |e| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+        let tag = u8::deserialize_reader(reader)?;
+        let (precision, tag) = if tag == PRECISION_TAG {
+            let precision = u8::deserialize_reader(reader)?;
+            let tag = u8::deserialize_reader(reader)?;
+            if tag == PRECISION_TAG {
+                return Err(invalid(HllError::Malformed(
+                    "nested Precision variant".to_string(),
+                )));
+            }
+            (Some(precision), tag)
+        } else {
+            (None, tag)
+        };
+        if tag > PRECISION_TAG {
+            return Err(invalid(HllError::UnsupportedRepresentation(tag)));
+        }
+        let payload = Payload {
+            precision,
+            variant: HyperLogLogVariant::deserialize_variant(reader, tag)?,
+        };
+        payload.into_sketch().map_err(invalid)
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "serde", feature = "borsh"))]
 mod tests {
+    use super::HyperLogLogVariantRef;
+    use crate::HllError;
     use crate::HyperLogLog;
 
     const P: usize = 14;
@@ -114,6 +879,590 @@ mod tests {
         json_serde_equal(&hll);
     }
 
+    #[test]
+    fn test_packed() {
+        let mut hll = HyperLogLog::<P>::new();
+        for i in 0..100000 {
+            hll.add_object(&i);
+        }
+
+        let bytes = borsh::to_vec(&hll).unwrap();
+        // precision variant tag and P, 1 byte variant tag, 4 bytes length
+        assert_eq!(bytes.len(), 2 + 1 + 4 + (1 << P) * 3 / 4);
+        assert_eq!(bytes[..3], [6, P as u8, 3]);
+        assert_eq!(borsh::from_slice::<HyperLogLog<P>>(&bytes).unwrap(), hll);
+        json_serde_equal(&hll);
+    }
+
+    #[test]
+    fn test_sparse_wide() {
+        let mut hll = HyperLogLog::<18>::new();
+        for i in 0..10000 {
+            hll.add_object(&i);
+        }
+        // registers beyond u16::MAX must survive the round trip
+        assert!(hll.registers[u16::MAX as usize + 1..]
+            .iter()
+            .any(|r| *r != 0));
+
+        let bytes = borsh::to_vec(&hll).unwrap();
+        assert_eq!(bytes[2], 4);
+        assert_eq!(borsh::from_slice::<HyperLogLog<18>>(&bytes).unwrap(), hll);
+        json_serde_equal(&hll);
+
+        let mut hll = HyperLogLog::<16>::new();
+        for i in 0..1000 {
+            hll.add_object(&i);
+        }
+        let bytes = borsh::to_vec(&hll).unwrap();
+        assert_eq!(bytes[2], 1);
+        assert_eq!(borsh::from_slice::<HyperLogLog<16>>(&bytes).unwrap(), hll);
+    }
+
+    #[test]
+    fn test_compressed() {
+        let mut hll = HyperLogLog::<P>::new();
+        for i in 0..8000 {
+            hll.add_object(&i);
+        }
+
+        let bytes = borsh::to_vec(&hll).unwrap();
+        assert_eq!(bytes[2], 5);
+        // smaller than the packed variant
+        assert!(bytes.len() < (1 << P) * 3 / 4);
+        assert_eq!(borsh::from_slice::<HyperLogLog<P>>(&bytes).unwrap(), hll);
+        json_serde_equal(&hll);
+
+        // corrupted payload is an error instead of a panic
+        let bytes = [5, 4, 0, 0, 0, 0xff, 0xff, 0xff, 0x01];
+        assert!(borsh::from_slice::<HyperLogLog<P>>(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_invalid() {
+        fn borsh_error<const P: usize>(v: &Variant) -> String {
+            let bytes = borsh::to_vec(v).unwrap();
+            borsh::from_slice::<HyperLogLog<P>>(&bytes)
+                .unwrap_err()
+                .to_string()
+        }
+
+        // index out of range
+        let v = Variant::Sparse {
+            data: vec![(1, 1), (1 << 10, 1)],
+        };
+        assert_eq!(
+            borsh_error::<10>(&v),
+            HllError::InvalidRegisterIndex {
+                index: 1 << 10,
+                len: 1 << 10
+            }
+            .to_string()
+        );
+        let json = serde_json::to_string(&v).unwrap();
+        assert!(serde_json::from_str::<HyperLogLog<10>>(&json).is_err());
+
+        // register value larger than 64 - P + 1
+        let v = Variant::SparseWide {
+            data: vec![(3, 56)],
+        };
+        assert_eq!(
+            borsh_error::<10>(&v),
+            HllError::InvalidRegisterValue {
+                index: 3,
+                value: 56,
+                max: 55
+            }
+            .to_string()
+        );
+        let v = Variant::Packed(vec![0xff; (1 << 10) * 3 / 4]);
+        assert!(borsh_error::<10>(&v).starts_with("invalid value 63 of register 0"));
+
+        // wrong number of registers
+        let v = Variant::Full(vec![1; 100]);
+        assert_eq!(
+            borsh_error::<10>(&v),
+            HllError::InvalidRegisterLength {
+                expected: 1 << 10,
+                actual: 100
+            }
+            .to_string()
+        );
+        let bytes = bincode::serialize(&v).unwrap();
+        assert!(bincode::deserialize::<HyperLogLog<10>>(&bytes).is_err());
+        // an error rather than a panic, too short or too long
+        for len in [100, (1 << 10) + 1] {
+            let json = serde_json::to_string(&Variant::Full(vec![1; len])).unwrap();
+            let error = serde_json::from_str::<HyperLogLog<10>>(&json).unwrap_err();
+            assert!(error.to_string().starts_with(
+                &HllError::InvalidRegisterLength {
+                    expected: 1 << 10,
+                    actual: len
+                }
+                .to_string()
+            ));
+        }
+    }
+
+    #[test]
+    fn test_unsupported_variant() {
+        // a variant appended by a newer version
+        for bytes in [&[7, 0, 0, 0, 0][..], &[6, P as u8, 7, 0, 0, 0, 0]] {
+            let error = borsh::from_slice::<HyperLogLog<P>>(bytes).unwrap_err();
+            assert_eq!(
+                error.get_ref().unwrap().downcast_ref::<HllError>(),
+                Some(&HllError::UnsupportedRepresentation(7))
+            );
+        }
+
+        let error = bincode::deserialize::<HyperLogLog<P>>(&[7, 0, 0, 0]).unwrap_err();
+        assert_eq!(error.to_string(), "unsupported representation 7");
+
+        let error = serde_json::from_str::<HyperLogLog<P>>(r#"{"Sketch":[1]}"#).unwrap_err();
+        assert!(error.is_data());
+        assert!(
+            error
+                .to_string()
+                .contains(r#"unsupported representation "Sketch""#),
+            "{}",
+            error
+        );
+        let json = r#"{"Precision":[12,{"Sparse":{"data":[[1,2]]}}]}"#;
+        let error = serde_json::from_str::<HyperLogLog<P>>(json).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("precision mismatch, expected P (14), got P (12)"),
+            "{}",
+            error
+        );
+        let bytes = rmp_serde::to_vec(&serde_json::json!({ "Sketch": [1] })).unwrap();
+        let error = rmp_serde::from_slice::<HyperLogLog<P>>(&bytes).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains(r#"unsupported representation "Sketch""#),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_same_shape_as_owned() {
+        #[derive(serde::Serialize, borsh::BorshSerialize)]
+        enum Owned {
+            Empty,
+            Sparse { data: Vec<(u16, u8)> },
+            Full(Vec<u8>),
+            Packed(Vec<u8>),
+            SparseWide { data: Vec<(u32, u8)> },
+            Compressed(Vec<u8>),
+            Precision(u8, Box<Owned>),
+        }
+
+        fn check<const P: usize>(hll: &HyperLogLog<P>, expected_tag: u8) {
+            let sparse = hll
+                .registers
+                .iter()
+                .enumerate()
+                .filter(|(_, &value)| value != 0);
+            let owned = match HyperLogLogVariantRef::from(hll) {
+                HyperLogLogVariantRef::Empty => Owned::Empty,
+                HyperLogLogVariantRef::Sparse { .. } => Owned::Sparse {
+                    data: sparse.map(|(i, &v)| (i as u16, v)).collect(),
+                },
+                HyperLogLogVariantRef::Full(r) => Owned::Full(r.clone()),
+                HyperLogLogVariantRef::Packed(_) => {
+                    Owned::Packed(crate::packed::pack(&hll.registers))
+                }
+                HyperLogLogVariantRef::SparseWide { .. } => Owned::SparseWide {
+                    data: sparse.map(|(i, &v)| (i as u32, v)).collect(),
+                },
+                HyperLogLogVariantRef::Compressed(_) => {
+                    Owned::Compressed(crate::compressed::encode(&hll.registers))
+                }
+                HyperLogLogVariantRef::Precision(..) => unreachable!(),
+            };
+            let owned = Owned::Precision(P as u8, Box::new(owned));
+
+            let bytes = borsh::to_vec(hll).unwrap();
+            assert_eq!(bytes[..3], [6, P as u8, expected_tag]);
+            assert_eq!(bytes, borsh::to_vec(&owned).unwrap());
+            assert_eq!(
+                bincode::serialize(hll).unwrap(),
+                bincode::serialize(&owned).unwrap()
+            );
+        }
+
+        let mut hll = HyperLogLog::<P>::new();
+        check(&hll, 0);
+        for i in 0..1000 {
+            hll.add_object(&i);
+        }
+        check(&hll, 1);
+        for i in 0..10000 {
+            hll.add_object(&i);
+        }
+        check(&hll, 5);
+        check(&HyperLogLog::<P>::with_registers(vec![7; 1 << P]), 3);
+
+        let mut hll = HyperLogLog::<18>::new();
+        for i in 0..1000 {
+            hll.add_object(&i);
+        }
+        check(&hll, 4);
+    }
+
+    #[test]
+    fn test_human_readable() {
+        let mut hll = HyperLogLog::<P>::new();
+        assert_eq!(serde_json::to_string(&hll).unwrap(), r#""Empty""#);
+
+        for i in 0..100 {
+            hll.add_object(&i);
+        }
+        let json = serde_json::to_string(&hll).unwrap();
+        assert!(json.starts_with(r#"{"Precision":[14,{"Sparse":{"data":[["#));
+        json_serde_equal(&hll);
+        let mut wide = HyperLogLog::<18>::new();
+        wide.add_object(&1);
+        let json = serde_json::to_string(&wide).unwrap();
+        assert!(json.starts_with(r#"{"Precision":[18,{"SparseWide":{"data":[["#));
+        assert!(json.len() < 64, "{}", json);
+        json_serde_equal(&wide);
+
+        let mut dense = hll.clone();
+        for i in 0..100_000 {
+            dense.add_object(&i);
+        }
+        let json = serde_json::to_string(&dense).unwrap();
+        // base64 of the packed registers
+        assert_eq!(json.len(), 2 + (1 << P));
+        assert!(json.starts_with('"'));
+        json_serde_equal(&dense);
+
+        // old payloads without the precision are still accepted
+        let sparse = borsh::from_slice::<Variant>(&borsh::to_vec(&hll).unwrap()[2..]).unwrap();
+        let json = serde_json::to_string(&sparse).unwrap();
+        assert!(json.starts_with(r#"{"Sparse":"#));
+        assert_eq!(serde_json::from_str::<HyperLogLog<P>>(&json).unwrap(), hll);
+
+        for invalid in [r#""Zm9v!""#, r#""Zm9v""#, r#""Full""#] {
+            assert!(serde_json::from_str::<HyperLogLog<P>>(invalid).is_err());
+        }
+    }
+
+    #[cfg(feature = "schemars")]
+    #[test]
+    fn test_json_schema() {
+        let schema = serde_json::to_value(schemars::schema_for!(HyperLogLog<P>)).unwrap();
+        assert_eq!(schema["title"], "HyperLogLog_p14");
+        assert_eq!(schema["anyOf"][0]["const"], "Empty");
+
+        let mut hll = HyperLogLog::<P>::new();
+        hll.add_object(&1);
+        let json = serde_json::to_value(&hll).unwrap();
+        let sparse = &schema["anyOf"][2]["properties"]["Precision"]["prefixItems"];
+        assert_eq!(json["Precision"][0], sparse[0]["const"]);
+        assert_eq!(sparse[1]["required"][0], "Sparse");
+        let entry = &sparse[1]["properties"]["Sparse"]["properties"]["data"]["items"];
+        assert_eq!(entry["prefixItems"][1]["maximum"], 64 - P + 1);
+
+        let hll = HyperLogLog::<P>::with_registers(vec![3; 1 << P]);
+        let json = serde_json::to_value(&hll).unwrap();
+        let len = json.as_str().unwrap().len();
+        assert_eq!(schema["anyOf"][1]["minLength"], len);
+        assert_eq!(schema["anyOf"][1]["maxLength"], len);
+    }
+
+    #[test]
+    fn test_msgpack() {
+        let mut hll = HyperLogLog::<P>::new();
+        msgpack_serde_equal(&hll);
+        for i in 0..1000 {
+            hll.add_object(&i);
+        }
+        msgpack_serde_equal(&hll);
+        for i in 0..10000 {
+            hll.add_object(&i);
+        }
+        // map, variant name, array of the precision and the variant
+        let precision = 1 + 1 + "Precision".len() + 1 + 1;
+        // compressed registers are stored as one bin blob: map, variant name, bin 16 header
+        let bytes = msgpack_serde_equal(&hll);
+        assert_eq!(
+            bytes.len(),
+            precision
+                + 1
+                + 1
+                + "Compressed".len()
+                + 3
+                + crate::compressed::encoded_len(&hll.registers)
+        );
+
+        let hll = HyperLogLog::<P>::with_registers(vec![50; 1 << P]);
+        let bytes = msgpack_serde_equal(&hll);
+        assert_eq!(
+            bytes.len(),
+            precision + 1 + 1 + "Packed".len() + 3 + (1 << P) * 3 / 4
+        );
+
+        // an array of integers, as written by older versions, is accepted too
+        #[derive(serde::Serialize)]
+        enum Owned {
+            #[allow(dead_code)]
+            Empty,
+            #[allow(dead_code)]
+            Sparse {
+                data: Vec<(u16, u8)>,
+            },
+            #[allow(dead_code)]
+            Full(Vec<u8>),
+            Packed(Vec<u8>),
+            #[allow(dead_code)]
+            SparseWide {
+                data: Vec<(u32, u8)>,
+            },
+            #[allow(dead_code)]
+            Compressed(Vec<u8>),
+            Precision(u8, Box<Owned>),
+        }
+        let packed = crate::packed::pack(&hll.registers);
+        let owned = Owned::Precision(P as u8, Box::new(Owned::Packed(packed)));
+        let array = rmp_serde::to_vec(&owned).unwrap();
+        assert!(array.len() > bytes.len());
+        assert_eq!(
+            rmp_serde::from_slice::<HyperLogLog<P>>(&array).unwrap(),
+            hll
+        );
+    }
+
+    /// bitcode is not self-describing: `deserialize_any` is not supported, so the
+    /// untagged human-readable path must never be taken.
+    #[test]
+    fn test_bitcode() {
+        #[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug)]
+        struct Snapshot {
+            id: u64,
+            hll: HyperLogLog<P>,
+            wide: HyperLogLog<18>,
+        }
+
+        let mut snapshot = Snapshot {
+            id: 42,
+            hll: HyperLogLog::new(),
+            wide: HyperLogLog::new(),
+        };
+        for n in [0, 100, 10_000, 100_000] {
+            for i in 0..n {
+                snapshot.hll.add_object(&i);
+                snapshot.wide.add_object(&i);
+            }
+            let bytes = bitcode::serialize(&snapshot).unwrap();
+            assert_eq!(bitcode::deserialize::<Snapshot>(&bytes).unwrap(), snapshot);
+        }
+
+        // the variant and the payload are checked like any other format
+        let bytes = bitcode::serialize(&snapshot.hll).unwrap();
+        assert_eq!(
+            bitcode::deserialize::<HyperLogLog<12>>(&bytes).unwrap(),
+            snapshot.hll.fold::<12>().unwrap()
+        );
+        assert!(bitcode::deserialize::<HyperLogLog<16>>(&bytes).is_err());
+        assert!(bitcode::deserialize::<HyperLogLog<P>>(&bytes[..bytes.len() / 2]).is_err());
+    }
+
+    /// Byte sequences are stored as bin blobs by default
+    fn msgpack_serde_equal<const P: usize>(hll: &HyperLogLog<P>) -> Vec<u8> {
+        let bytes = rmp_serde::to_vec(hll).unwrap();
+        if hll.num_empty_registers() * 2 < HyperLogLog::<P>::NUM_REGISTERS {
+            // about as small as the binary format
+            assert!(bytes.len() < hll.to_bytes().len() + 32);
+        }
+        assert_eq!(
+            &rmp_serde::from_slice::<HyperLogLog<P>>(&bytes).unwrap(),
+            hll
+        );
+        bytes
+    }
+
+    #[derive(serde::Serialize, borsh::BorshSerialize, borsh::BorshDeserialize)]
+    enum Variant {
+        Empty,
+        Sparse { data: Vec<(u16, u8)> },
+        Full(Vec<u8>),
+        Packed(Vec<u8>),
+        SparseWide { data: Vec<(u32, u8)> },
+    }
+
+    /// Deterministic hashes, so the golden sketches don't depend on the default hasher
+    fn golden_sketch<const P: usize>(n: usize) -> HyperLogLog<P> {
+        let mut hll = HyperLogLog::<P>::new();
+        let mut state = 0_u64;
+        for _ in 0..n {
+            // splitmix64
+            state = state.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            hll.add_hash(z ^ (z >> 31));
+        }
+        hll
+    }
+
+    /// The bincode (1.x, default options) layout is guaranteed to be stable:
+    /// `u32` variant tag, `u64` sequence lengths, little-endian fixed-size integers.
+    /// Run with `UPDATE_GOLDEN=1` to add new fixtures, existing ones must never change,
+    /// the fixtures in `legacy` were written without the precision and are only read.
+    #[test]
+    fn test_bincode_golden() {
+        fn check<const P: usize>(name: &str, hll: &HyperLogLog<P>, expected_tag: u32) {
+            let path = format!(
+                "{}/tests/fixtures/bincode/{}.bin",
+                env!("CARGO_MANIFEST_DIR"),
+                name
+            );
+            let bytes = bincode::serialize(hll).unwrap();
+            if std::env::var_os("UPDATE_GOLDEN").is_some() && !std::path::Path::new(&path).exists()
+            {
+                std::fs::write(&path, &bytes).unwrap();
+            }
+
+            let golden = std::fs::read(&path).unwrap();
+            assert_eq!(golden[..4], 6_u32.to_le_bytes(), "{}", name);
+            assert_eq!(golden[4], P as u8, "{}", name);
+            assert_eq!(golden[5..9], expected_tag.to_le_bytes(), "{}", name);
+            assert!(golden == bytes, "{} doesn't match the golden fixture", name);
+            assert_eq!(
+                &bincode::deserialize::<HyperLogLog<P>>(&golden).unwrap(),
+                hll,
+                "{}",
+                name
+            );
+
+            let legacy = std::fs::read(format!(
+                "{}/tests/fixtures/bincode/legacy/{}.bin",
+                env!("CARGO_MANIFEST_DIR"),
+                name
+            ))
+            .unwrap();
+            assert_eq!(legacy[..4], expected_tag.to_le_bytes(), "{}", name);
+            assert_eq!(legacy, golden[5..], "{}", name);
+            assert_eq!(
+                &bincode::deserialize::<HyperLogLog<P>>(&legacy).unwrap(),
+                hll,
+                "{}",
+                name
+            );
+        }
+
+        check("empty", &golden_sketch::<14>(0), 0);
+        check("sparse", &golden_sketch::<14>(100), 1);
+        check("packed", &golden_sketch::<14>(100_000), 3);
+        check("sparse_wide", &golden_sketch::<18>(100), 4);
+        check("compressed", &golden_sketch::<14>(10_000), 5);
+
+        // `Full` is only read, it was written by older versions
+        let full = std::fs::read(format!(
+            "{}/tests/fixtures/bincode/full_p4.bin",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let mut expected = 2_u32.to_le_bytes().to_vec();
+        expected.extend_from_slice(&16_u64.to_le_bytes());
+        expected.extend(1..=16);
+        assert_eq!(full, expected);
+        assert_eq!(
+            bincode::deserialize::<HyperLogLog<4>>(&full).unwrap(),
+            HyperLogLog::<4>::with_registers((1..=16).collect())
+        );
+    }
+
+    #[test]
+    fn test_precision() {
+        let mut hll = HyperLogLog::<14>::new();
+        for n in [100, 10_000, 100_000] {
+            for i in 0..n {
+                hll.add_object(&i);
+            }
+            let folded = hll.fold::<12>().unwrap();
+            let bytes = bincode::serialize(&hll).unwrap();
+            assert_eq!(
+                bincode::deserialize::<HyperLogLog<12>>(&bytes).unwrap(),
+                folded
+            );
+            let bytes = borsh::to_vec(&hll).unwrap();
+            assert_eq!(
+                borsh::from_slice::<HyperLogLog<12>>(&bytes).unwrap(),
+                folded
+            );
+            let json = serde_json::to_string(&hll).unwrap();
+            assert_eq!(
+                serde_json::from_str::<HyperLogLog<12>>(&json).unwrap(),
+                folded
+            );
+            let bytes = rmp_serde::to_vec(&hll).unwrap();
+            assert_eq!(
+                rmp_serde::from_slice::<HyperLogLog<12>>(&bytes).unwrap(),
+                folded
+            );
+        }
+
+        // a lower precision is an error, not reinterpreted registers
+        let mut hll = HyperLogLog::<12>::new();
+        hll.add_object(&1);
+        let error = HllError::PrecisionMismatch {
+            expected: 14,
+            actual: 12,
+        };
+        let bytes = bincode::serialize(&hll).unwrap();
+        let e = bincode::deserialize::<HyperLogLog<14>>(&bytes).unwrap_err();
+        assert_eq!(e.to_string(), error.to_string());
+        let bytes = borsh::to_vec(&hll).unwrap();
+        let e = borsh::from_slice::<HyperLogLog<14>>(&bytes).unwrap_err();
+        assert_eq!(
+            e.get_ref().unwrap().downcast_ref::<HllError>(),
+            Some(&error)
+        );
+        for i in 0..10_000 {
+            hll.add_object(&i);
+        }
+        let json = serde_json::to_string(&hll).unwrap();
+        let e = serde_json::from_str::<HyperLogLog<14>>(&json).unwrap_err();
+        assert!(e.to_string().starts_with(&error.to_string()), "{}", e);
+
+        // invalid precisions
+        let bytes = borsh::to_vec(&hll).unwrap();
+        let mut invalid = bytes.clone();
+        invalid[1] = 19;
+        let e = borsh::from_slice::<HyperLogLog<12>>(&invalid).unwrap_err();
+        assert_eq!(
+            e.get_ref().unwrap().downcast_ref::<HllError>(),
+            Some(&HllError::InvalidPrecision(19))
+        );
+        invalid[1] = 6;
+        invalid[2] = 6;
+        assert!(borsh::from_slice::<HyperLogLog<12>>(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_read_full() {
+        let mut hll = HyperLogLog::<P>::new();
+        for i in 0..100000 {
+            hll.add_object(&i);
+        }
+
+        // payloads written before the packed variant was introduced
+        let full = HyperLogLogVariantRef::Full(&hll.registers);
+        let bytes = borsh::to_vec(&full).unwrap();
+        assert_eq!(borsh::from_slice::<HyperLogLog<P>>(&bytes).unwrap(), hll);
+        let json = serde_json::to_vec(&full).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<HyperLogLog<P>>(&json).unwrap(),
+            hll
+        );
+    }
+
     fn json_serde_equal<T>(t: &T)
     where
         T: serde::Serialize + for<'a> serde::Deserialize<'a> + Eq,
@@ -122,27 +1471,4 @@ mod tests {
         let new_t: T = serde_json::from_slice(&val).unwrap();
         assert!(t == &new_t)
     }
-
-    // #[test]
-    // #[cfg(target_os = "linux")]
-    // fn test_back_compat() {
-    //     let mut hll = HyperLogLog::<P>::new();
-
-    //     for i in 0..1000000 {
-    //         hll.add_object(&i);
-    //     }
-
-    //     for i in 0..1000000 {
-    //         hll.add_object(&i);
-    //     }
-
-    //     let s: u64 = hll.registers.iter().map(|x| *x as u64).sum();
-    //     assert_eq!(s, 118702);
-
-    //     for i in 0..2000000 {
-    //         hll.add_object(&i);
-    //     }
-    //     let s: u64 = hll.registers.iter().map(|x| *x as u64).sum();
-    //     assert_eq!(s, 135525);
-    // }
 }
@@ -0,0 +1,125 @@
+//! Build one sketch from many threads of a custom thread pool.
+
+use core::hash::Hash;
+use std::sync::Mutex;
+
+use crate::Hasher;
+use crate::HyperLogLog;
+
+/// Hands out a [`ShardHandle`] per thread, each fills its own sketch without locking,
+/// and merges it into the result once when dropped.
+///
+/// ```
+/// use simple_hll::HyperLogLog;
+/// use simple_hll::ShardedHllBuilder;
+///
+/// let builder = ShardedHllBuilder::<14>::new();
+/// std::thread::scope(|s| {
+///     for t in 0..4_u64 {
+///         let mut handle = builder.handle();
+///         s.spawn(move || {
+///             for i in t * 1000..(t + 1) * 1000 {
+///                 handle.add_object(&i);
+///             }
+///         });
+///     }
+/// });
+/// let hll: HyperLogLog<14> = builder.finish();
+/// ```
+#[derive(Debug, Default)]
+pub struct ShardedHllBuilder<const P: usize> {
+    merged: Mutex<HyperLogLog<P>>,
+}
+
+/// The sketch of one thread, see [`ShardedHllBuilder`]
+#[derive(Debug)]
+pub struct ShardHandle<'a, const P: usize> {
+    builder: &'a ShardedHllBuilder<P>,
+    hll: HyperLogLog<P>,
+}
+
+impl<const P: usize> ShardedHllBuilder<P> {
+    pub fn new() -> Self {
+        Self {
+            merged: Mutex::new(HyperLogLog::new()),
+        }
+    }
+
+    /// A new handle, with an empty sketch of its own
+    pub fn handle(&self) -> ShardHandle<'_, P> {
+        ShardHandle {
+            builder: self,
+            hll: HyperLogLog::new(),
+        }
+    }
+
+    /// The union of the sketches of every handle, which are all dropped by then
+    pub fn finish(self) -> HyperLogLog<P> {
+        self.merged
+            .into_inner()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<const P: usize> ShardHandle<'_, P> {
+    #[inline]
+    pub fn add_hash(&mut self, hash: u64) {
+        self.hll.add_hash(hash);
+    }
+
+    #[inline]
+    pub fn add_object<T: Hash>(&mut self, obj: &T) {
+        self.hll.add_object(obj);
+    }
+
+    #[inline]
+    pub fn add_object_by_hasher<T: Hash, H: Hasher>(&mut self, obj: &T) {
+        self.hll.add_object_by_hasher::<T, H>(obj);
+    }
+
+    /// Merge a sketch into the one of this handle
+    pub fn merge(&mut self, other: &HyperLogLog<P>) {
+        self.hll.merge(other);
+    }
+}
+
+impl<const P: usize> Drop for ShardHandle<'_, P> {
+    fn drop(&mut self) {
+        // merging can't panic halfway, a poisoned sketch is still consistent
+        self.builder
+            .merged
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .merge(&self.hll);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sharded_builder() {
+        let mut expected = HyperLogLog::<14>::new();
+        for i in 0..100_000_u64 {
+            expected.add_object(&i);
+        }
+
+        let builder = ShardedHllBuilder::<14>::new();
+        std::thread::scope(|s| {
+            for t in 0..4_u64 {
+                let mut handle = builder.handle();
+                s.spawn(move || {
+                    for i in (t * 25_000)..((t + 1) * 25_000) {
+                        handle.add_object(&i);
+                    }
+                });
+            }
+        });
+        assert_eq!(builder.finish(), expected);
+
+        let builder = ShardedHllBuilder::<14>::new();
+        drop(builder.handle());
+        assert_eq!(builder.finish(), HyperLogLog::new());
+    }
+}
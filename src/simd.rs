@@ -0,0 +1,494 @@
+//! Kernels over the registers, vectorized where the target allows it.
+//!
+//! On x86_64 the fastest kernel supported by the CPU is picked at runtime (AVX-512BW, AVX2,
+//! then the SSE2 baseline, the histogram has no AVX-512 kernel), so distributed binaries don't need `-C target-cpu=native`.
+//! WebAssembly has no runtime detection, the simd128 kernels are used when the module is
+//! built with `-C target-feature=+simd128`. NEON is part of the aarch64 baseline, its
+//! kernels are always used there.
+//! Every kernel has a scalar fallback, the vectorized versions must return exactly the
+//! same results, which the tests check against the naive loops.
+//! Runtime detection reads `cpuid` directly rather than calling `is_x86_feature_detected!`,
+//! so it works without `std`, and doesn't call into `std`, which would keep the callers from
+//! being proven panic-free (see `cfg(no_panic)` in `CONTRIBUTING.md`).
+
+/// Count the registers of each value
+#[inline]
+pub(crate) fn histogram(registers: &[u8]) -> [u32; 64] {
+    #[cfg(target_arch = "x86_64")]
+    {
+        // the AVX-512 version of the AVX2 kernel measured slower, it's not worth it
+        if cpu::has_avx2() {
+            // SAFETY: the CPU supports avx2
+            return unsafe { avx2::histogram(registers) };
+        }
+    }
+    #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+    {
+        neon::histogram(registers)
+    }
+    #[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+    {
+        simd128::histogram(registers)
+    }
+    #[cfg(not(any(
+        all(target_arch = "aarch64", target_feature = "neon"),
+        all(target_arch = "wasm32", target_feature = "simd128")
+    )))]
+    {
+        histogram_scalar(registers)
+    }
+}
+
+/// Set every register of `dst` to the max of itself and the same register of `src`,
+/// only the common prefix is merged if the lengths differ
+#[inline]
+pub(crate) fn max_assign(dst: &mut [u8], src: &[u8]) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if cpu::has_avx512bw() {
+            // SAFETY: the CPU supports avx512bw
+            return unsafe { avx512::max_assign(dst, src) };
+        }
+        if cpu::has_avx2() {
+            // SAFETY: the CPU supports avx2
+            return unsafe { avx2::max_assign(dst, src) };
+        }
+        sse2::max_assign(dst, src)
+    }
+    #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+    {
+        neon::max_assign(dst, src)
+    }
+    #[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+    {
+        simd128::max_assign(dst, src)
+    }
+    #[cfg(not(any(
+        target_arch = "x86_64",
+        all(target_arch = "aarch64", target_feature = "neon"),
+        all(target_arch = "wasm32", target_feature = "simd128")
+    )))]
+    {
+        max_assign_scalar(dst, src)
+    }
+}
+
+/// Smallest and largest register values, `(u8::MAX, 0)` if there are no registers. Always
+/// inlined so it is vectorized with the features of the calling kernel
+#[cfg(any(
+    target_arch = "x86_64",
+    all(target_arch = "aarch64", target_feature = "neon"),
+    all(target_arch = "wasm32", target_feature = "simd128")
+))]
+#[inline(always)]
+fn min_max(registers: &[u8]) -> (u8, u8) {
+    registers
+        .iter()
+        .fold((u8::MAX, 0), |(min, max), r| (min.min(*r), max.max(*r)))
+}
+
+/// Fixed size chunks without bounds checks, which the compiler can vectorize on its own
+pub(crate) fn max_assign_scalar(dst: &mut [u8], src: &[u8]) {
+    let mut dst_chunks = dst.chunks_exact_mut(32);
+    let mut src_chunks = src.chunks_exact(32);
+    for (d, s) in (&mut dst_chunks).zip(&mut src_chunks) {
+        for (d, s) in d.iter_mut().zip(s) {
+            *d = (*d).max(*s);
+        }
+    }
+    for (d, s) in dst_chunks
+        .into_remainder()
+        .iter_mut()
+        .zip(src_chunks.remainder())
+    {
+        *d = (*d).max(*s);
+    }
+}
+
+/// Four interleaved histograms, so that runs of equal values don't serialize on the
+/// same counter
+pub(crate) fn histogram_scalar(registers: &[u8]) -> [u32; 64] {
+    let mut histograms = [[0u32; 64]; 4];
+    let mut chunks = registers.chunks_exact(4);
+    for chunk in &mut chunks {
+        histograms[0][(chunk[0] & 63) as usize] += 1;
+        histograms[1][(chunk[1] & 63) as usize] += 1;
+        histograms[2][(chunk[2] & 63) as usize] += 1;
+        histograms[3][(chunk[3] & 63) as usize] += 1;
+    }
+    for r in chunks.remainder() {
+        histograms[0][(*r & 63) as usize] += 1;
+    }
+
+    let mut histogram = histograms[0];
+    for h in &histograms[1..] {
+        for (a, b) in histogram.iter_mut().zip(h) {
+            *a += b;
+        }
+    }
+    histogram
+}
+
+/// The x86_64 features of the kernels, detected once and cached
+#[cfg(target_arch = "x86_64")]
+mod cpu {
+    use core::arch::x86_64::__cpuid;
+    use core::arch::x86_64::__cpuid_count;
+    use core::arch::x86_64::_xgetbv;
+    use core::sync::atomic::AtomicU8;
+    use core::sync::atomic::Ordering;
+
+    const AVX2: u8 = 1;
+    const AVX512BW: u8 = 1 << 1;
+    const DETECTED: u8 = 1 << 7;
+
+    static FEATURES: AtomicU8 = AtomicU8::new(0);
+
+    #[inline]
+    pub(super) fn has_avx2() -> bool {
+        features() & AVX2 != 0
+    }
+
+    #[inline]
+    pub(super) fn has_avx512bw() -> bool {
+        features() & AVX512BW != 0
+    }
+
+    #[inline]
+    fn features() -> u8 {
+        let features = FEATURES.load(Ordering::Relaxed);
+        if features & DETECTED != 0 {
+            return features;
+        }
+        // racing threads detect the same features, storing them twice is harmless
+        let features = detect() | DETECTED;
+        FEATURES.store(features, Ordering::Relaxed);
+        features
+    }
+
+    /// Same checks as `is_x86_feature_detected!`: the CPU must support the instructions
+    /// and the OS must save their registers on context switches (XCR0)
+    #[cold]
+    fn detect() -> u8 {
+        let leaf1 = __cpuid(1);
+        let osxsave = leaf1.ecx & (1 << 27) != 0;
+        let avx = leaf1.ecx & (1 << 28) != 0;
+        if !osxsave || !avx || __cpuid(0).eax < 7 {
+            return 0;
+        }
+        // SAFETY: OSXSAVE is set, xgetbv is enabled
+        let xcr0 = unsafe { xgetbv() };
+        let leaf7 = __cpuid_count(7, 0);
+
+        let mut features = 0;
+        // XMM and YMM state
+        if xcr0 & 0b110 == 0b110 && leaf7.ebx & (1 << 5) != 0 {
+            features |= AVX2;
+        }
+        // and the opmask and ZMM state, AVX512BW requires AVX512F
+        if xcr0 & 0b1110_0110 == 0b1110_0110
+            && leaf7.ebx & (1 << 16) != 0
+            && leaf7.ebx & (1 << 30) != 0
+        {
+            features |= AVX512BW;
+        }
+        features
+    }
+
+    #[target_feature(enable = "xsave")]
+    unsafe fn xgetbv() -> u64 {
+        _xgetbv(0)
+    }
+}
+
+/// SSE2 is part of the x86_64 baseline, always available there
+#[cfg(target_arch = "x86_64")]
+mod sse2 {
+    use core::arch::x86_64::*;
+
+    pub(super) fn max_assign(dst: &mut [u8], src: &[u8]) {
+        let mut dst_chunks = dst.chunks_exact_mut(16);
+        let mut src_chunks = src.chunks_exact(16);
+        // SAFETY: sse2 is always enabled on x86_64, loads and stores are unaligned and in bounds
+        unsafe {
+            for (d, s) in (&mut dst_chunks).zip(&mut src_chunks) {
+                let a = _mm_loadu_si128(d.as_ptr() as *const __m128i);
+                let b = _mm_loadu_si128(s.as_ptr() as *const __m128i);
+                _mm_storeu_si128(d.as_mut_ptr() as *mut __m128i, _mm_max_epu8(a, b));
+            }
+        }
+        super::max_assign_scalar(dst_chunks.into_remainder(), src_chunks.remainder());
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use core::arch::x86_64::*;
+
+    /// Above this many distinct values, one compare per value and chunk costs more than
+    /// the scalar loop
+    const MAX_VALUES: u8 = 24;
+    /// The per-lane counters are bytes, flush them before they can overflow
+    const BLOCK_CHUNKS: usize = 255;
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn max_assign(dst: &mut [u8], src: &[u8]) {
+        let mut dst_chunks = dst.chunks_exact_mut(32);
+        let mut src_chunks = src.chunks_exact(32);
+        for (d, s) in (&mut dst_chunks).zip(&mut src_chunks) {
+            // SAFETY: loads and stores are unaligned and in bounds
+            unsafe {
+                let a = _mm256_loadu_si256(d.as_ptr() as *const __m256i);
+                let b = _mm256_loadu_si256(s.as_ptr() as *const __m256i);
+                _mm256_storeu_si256(d.as_mut_ptr() as *mut __m256i, _mm256_max_epu8(a, b));
+            }
+        }
+        super::max_assign_scalar(dst_chunks.into_remainder(), src_chunks.remainder());
+    }
+
+    /// Registers of a sketch are concentrated on a few values around `log2(n / m)`, so
+    /// count each value of the range `[min, max]` with a vector compare per chunk.
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn histogram(registers: &[u8]) -> [u32; 64] {
+        let (min, max) = super::min_max(registers);
+        if max.saturating_sub(min) >= MAX_VALUES {
+            return super::histogram_scalar(registers);
+        }
+
+        let mut histogram = [0u32; 64];
+        let chunks = registers.chunks_exact(32);
+        let remainder = chunks.remainder();
+        let chunks = &registers[..registers.len() - remainder.len()];
+        for block in chunks.chunks(32 * BLOCK_CHUNKS) {
+            for value in min..=max {
+                let needle = _mm256_set1_epi8(value as i8);
+                let mut counts = _mm256_setzero_si256();
+                for chunk in block.chunks_exact(32) {
+                    // SAFETY: the load is unaligned and in bounds
+                    let v = unsafe { _mm256_loadu_si256(chunk.as_ptr() as *const __m256i) };
+                    // equal lanes are -1, subtracting counts them
+                    counts = _mm256_sub_epi8(counts, _mm256_cmpeq_epi8(v, needle));
+                }
+                // sums each group of 8 bytes into a u64 lane
+                let sums = _mm256_sad_epu8(counts, _mm256_setzero_si256());
+                histogram[(value & 63) as usize] += (_mm256_extract_epi64::<0>(sums)
+                    + _mm256_extract_epi64::<1>(sums)
+                    + _mm256_extract_epi64::<2>(sums)
+                    + _mm256_extract_epi64::<3>(sums))
+                    as u32;
+            }
+        }
+        for r in remainder {
+            histogram[(*r & 63) as usize] += 1;
+        }
+        histogram
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx512 {
+    use core::arch::x86_64::*;
+
+    #[target_feature(enable = "avx512bw")]
+    pub(super) unsafe fn max_assign(dst: &mut [u8], src: &[u8]) {
+        let mut dst_chunks = dst.chunks_exact_mut(64);
+        let mut src_chunks = src.chunks_exact(64);
+        for (d, s) in (&mut dst_chunks).zip(&mut src_chunks) {
+            // SAFETY: loads and stores are unaligned and in bounds
+            unsafe {
+                let a = _mm512_loadu_si512(d.as_ptr() as *const __m512i);
+                let b = _mm512_loadu_si512(s.as_ptr() as *const __m512i);
+                _mm512_storeu_si512(d.as_mut_ptr() as *mut __m512i, _mm512_max_epu8(a, b));
+            }
+        }
+        super::max_assign_scalar(dst_chunks.into_remainder(), src_chunks.remainder());
+    }
+}
+
+#[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+mod neon {
+    use core::arch::aarch64::*;
+
+    /// Above this many distinct values, one compare per value and chunk costs more than
+    /// the scalar loop
+    const MAX_VALUES: u8 = 16;
+    /// The per-lane counters are bytes, flush them before they can overflow
+    const BLOCK_CHUNKS: usize = 255;
+
+    pub(super) fn max_assign(dst: &mut [u8], src: &[u8]) {
+        let mut dst_chunks = dst.chunks_exact_mut(16);
+        let mut src_chunks = src.chunks_exact(16);
+        for (d, s) in (&mut dst_chunks).zip(&mut src_chunks) {
+            // SAFETY: neon is enabled at compile time, loads and stores are in bounds
+            unsafe {
+                let a = vld1q_u8(d.as_ptr());
+                let b = vld1q_u8(s.as_ptr());
+                vst1q_u8(d.as_mut_ptr(), vmaxq_u8(a, b));
+            }
+        }
+        super::max_assign_scalar(dst_chunks.into_remainder(), src_chunks.remainder());
+    }
+
+    /// Same approach as the AVX2 kernel, equal lanes are all ones and are subtracted from
+    /// byte counters
+    pub(super) fn histogram(registers: &[u8]) -> [u32; 64] {
+        let (min, max) = super::min_max(registers);
+        if max.saturating_sub(min) >= MAX_VALUES {
+            return super::histogram_scalar(registers);
+        }
+
+        let mut histogram = [0u32; 64];
+        let chunks = registers.chunks_exact(16);
+        let remainder = chunks.remainder();
+        let chunks = &registers[..registers.len() - remainder.len()];
+        for block in chunks.chunks(16 * BLOCK_CHUNKS) {
+            for value in min..=max {
+                // SAFETY: neon is enabled at compile time, loads are in bounds
+                unsafe {
+                    let needle = vdupq_n_u8(value);
+                    let mut counts = vdupq_n_u8(0);
+                    for chunk in block.chunks_exact(16) {
+                        let v = vld1q_u8(chunk.as_ptr());
+                        counts = vsubq_u8(counts, vceqq_u8(v, needle));
+                    }
+                    histogram[(value & 63) as usize] += vaddlvq_u8(counts) as u32;
+                }
+            }
+        }
+        for r in remainder {
+            histogram[(*r & 63) as usize] += 1;
+        }
+        histogram
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+mod simd128 {
+    use core::arch::wasm32::*;
+
+    /// Above this many distinct values, one compare per value and chunk costs more than
+    /// the scalar loop
+    const MAX_VALUES: u8 = 16;
+
+    pub(super) fn max_assign(dst: &mut [u8], src: &[u8]) {
+        let mut dst_chunks = dst.chunks_exact_mut(16);
+        let mut src_chunks = src.chunks_exact(16);
+        for (d, s) in (&mut dst_chunks).zip(&mut src_chunks) {
+            // SAFETY: simd128 is enabled at compile time, loads and stores are in bounds
+            unsafe {
+                let a = v128_load(d.as_ptr() as *const v128);
+                let b = v128_load(s.as_ptr() as *const v128);
+                v128_store(d.as_mut_ptr() as *mut v128, u8x16_max(a, b));
+            }
+        }
+        super::max_assign_scalar(dst_chunks.into_remainder(), src_chunks.remainder());
+    }
+
+    /// Same approach as the x86_64 kernels, the compare results are counted from their
+    /// bit mask
+    pub(super) fn histogram(registers: &[u8]) -> [u32; 64] {
+        let (min, max) = super::min_max(registers);
+        if max.saturating_sub(min) >= MAX_VALUES {
+            return super::histogram_scalar(registers);
+        }
+
+        let mut histogram = [0u32; 64];
+        let mut chunks = registers.chunks_exact(16);
+        for chunk in &mut chunks {
+            // SAFETY: simd128 is enabled at compile time, the load is in bounds
+            let v = unsafe { v128_load(chunk.as_ptr() as *const v128) };
+            for value in min..=max {
+                let mask = u8x16_bitmask(u8x16_eq(v, u8x16_splat(value)));
+                histogram[(value & 63) as usize] += mask.count_ones();
+            }
+        }
+        for r in chunks.remainder() {
+            histogram[(*r & 63) as usize] += 1;
+        }
+        histogram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use xxhash_rust::xxh3::xxh3_64;
+
+    use super::*;
+
+    fn registers(len: usize, seed: u64, high: u64) -> Vec<u8> {
+        (0..len as u64)
+            .map(|i| (xxh3_64(&(i ^ seed).to_le_bytes()) % (high + 1)) as u8)
+            .collect()
+    }
+
+    fn naive_histogram(registers: &[u8]) -> [u32; 64] {
+        let mut histogram = [0; 64];
+        for r in registers {
+            histogram[*r as usize] += 1;
+        }
+        histogram
+    }
+
+    #[test]
+    fn test_histogram() {
+        for len in [0, 1, 31, 32, 33, 64, 65, 16 * 1024, 32 * 255 + 7, 1 << 18] {
+            // narrow ranges take the vectorized path, wide ones the fallback
+            for (low, high) in [(0u64, 1u64), (0, 4), (3, 12), (0, 61)] {
+                let registers: Vec<u8> = (0..len as u64)
+                    .map(|i| (low + xxh3_64(&i.to_le_bytes()) % (high - low + 1)) as u8)
+                    .collect();
+                let expected = naive_histogram(&registers);
+                assert_eq!(histogram(&registers), expected);
+                assert_eq!(histogram_scalar(&registers), expected);
+                #[cfg(target_arch = "x86_64")]
+                unsafe {
+                    if std::arch::is_x86_feature_detected!("avx2") {
+                        assert_eq!(avx2::histogram(&registers), expected);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_max_assign() {
+        for len in [0, 1, 15, 16, 31, 32, 33, 63, 64, 65, 100, 16 * 1024] {
+            let a = registers(len, 0, 61);
+            let b = registers(len, 1 << 40, 61);
+            let expected: Vec<u8> = a.iter().zip(&b).map(|(a, b)| *a.max(b)).collect();
+
+            let mut dst = a.clone();
+            max_assign(&mut dst, &b);
+            assert_eq!(dst, expected);
+            let mut dst = a.clone();
+            max_assign_scalar(&mut dst, &b);
+            assert_eq!(dst, expected);
+            #[cfg(target_arch = "x86_64")]
+            unsafe {
+                let mut dst = a.clone();
+                sse2::max_assign(&mut dst, &b);
+                assert_eq!(dst, expected);
+                if std::arch::is_x86_feature_detected!("avx2") {
+                    let mut dst = a.clone();
+                    avx2::max_assign(&mut dst, &b);
+                    assert_eq!(dst, expected);
+                }
+                if std::arch::is_x86_feature_detected!("avx512bw") {
+                    let mut dst = a.clone();
+                    avx512::max_assign(&mut dst, &b);
+                    assert_eq!(dst, expected);
+                }
+            }
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_cpu_features() {
+        assert_eq!(cpu::has_avx2(), std::arch::is_x86_feature_detected!("avx2"));
+        assert_eq!(
+            cpu::has_avx512bw(),
+            std::arch::is_x86_feature_detected!("avx512bw")
+        );
+    }
+}
@@ -0,0 +1,127 @@
+//! A trait over the cardinality sketches of this crate.
+
+use core::hash::Hash;
+
+use crate::Hasher;
+use crate::HllError;
+use crate::HyperLogLog;
+
+/// Operations shared by the cardinality sketches, so engines can switch sketch families
+/// from their configuration with a generic `S: CardinalitySketch`.
+///
+/// ```
+/// use simple_hll::CardinalitySketch;
+/// use simple_hll::HyperLogLog;
+/// use simple_hll::UltraLogLog;
+///
+/// fn distinct<S: CardinalitySketch + Default>(items: &[&str]) -> usize {
+///     let mut sketch = S::default();
+///     for item in items {
+///         sketch.add_object(item);
+///     }
+///     sketch.count()
+/// }
+///
+/// assert_eq!(distinct::<HyperLogLog<12>>(&["a", "b", "a"]), 2);
+/// assert_eq!(distinct::<UltraLogLog<12>>(&["a", "b", "a"]), 2);
+/// ```
+pub trait CardinalitySketch: Clone {
+    /// Adds an hash, computed the same way for every item
+    fn add_hash(&mut self, hash: u64);
+
+    #[inline]
+    fn add_object<T: Hash>(&mut self, obj: &T) {
+        self.add_object_by_hasher::<T, ahash::AHasher>(obj);
+    }
+
+    #[inline]
+    fn add_object_by_hasher<T: Hash, H: Hasher>(&mut self, obj: &T) {
+        self.add_hash(H::hll_hash(obj));
+    }
+
+    /// Merge the other sketch into this one, the sketch of the union
+    fn merge(&mut self, other: &Self);
+
+    /// Guess the number of unique elements seen by the sketch.
+    fn count(&self) -> usize;
+
+    fn is_empty(&self) -> bool;
+
+    /// Serialize into the binary format of the sketch, tagged with the default hasher
+    fn to_bytes(&self) -> Vec<u8>;
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, HllError>;
+
+    /// Bytes used by the sketch, inline and on the heap
+    fn memory_bytes(&self) -> usize;
+}
+
+impl<const P: usize> CardinalitySketch for HyperLogLog<P> {
+    #[inline]
+    fn add_hash(&mut self, hash: u64) {
+        HyperLogLog::add_hash(self, hash);
+    }
+
+    fn merge(&mut self, other: &Self) {
+        HyperLogLog::merge(self, other);
+    }
+
+    fn count(&self) -> usize {
+        HyperLogLog::count(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.registers.iter().all(|r| *r == 0)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        HyperLogLog::to_bytes(self)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, HllError> {
+        HyperLogLog::from_bytes(bytes)
+    }
+
+    fn memory_bytes(&self) -> usize {
+        core::mem::size_of::<Self>() + self.registers.capacity()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HyperBitBit;
+    use crate::LinearCounting;
+    use crate::Pcsa;
+    use crate::ThetaSketch;
+    use crate::UltraLogLog;
+
+    /// Union of two sketches of overlapping ranges, through the trait only
+    fn check<S: CardinalitySketch + PartialEq + core::fmt::Debug>(empty: S, tolerance: f64) {
+        assert!(empty.is_empty());
+        assert_eq!(empty.count(), 0);
+
+        let mut a = empty.clone();
+        let mut b = empty;
+        for i in 0..60_000_u64 {
+            a.add_object(&i);
+            b.add_object(&(i + 40_000));
+        }
+        a.merge(&b);
+        assert!(!a.is_empty());
+        let ratio = a.count() as f64 / 100_000.0;
+        assert!((ratio - 1.0).abs() < tolerance, "{}", ratio);
+        assert!(a.memory_bytes() >= core::mem::size_of::<S>());
+        assert_eq!(S::from_bytes(&a.to_bytes()).unwrap(), a);
+    }
+
+    #[test]
+    fn test_sketches() {
+        check(HyperLogLog::<12>::new(), 0.05);
+        check(UltraLogLog::<12>::new(), 0.05);
+        check(ThetaSketch::new(), 0.1);
+        check(Pcsa::<12>::new(), 0.05);
+        check(LinearCounting::new(1 << 20), 0.01);
+        check(HyperBitBit::new(), 0.5);
+    }
+}
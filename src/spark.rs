@@ -0,0 +1,120 @@
+//! Aggregation buffer of Spark's `approx_count_distinct` (`HyperLogLogPlusPlus`).
+//!
+//! The registers are packed into `i64` words, 10 registers of 6 bits per word: register `i`
+//! occupies bits `[6 * (i % 10), 6 * (i % 10) + 6)` of word `i / 10`, the 4 high bits of
+//! every word are unused. Spark's precision is `ceil(2 * log2(1.106 / relativeSD))`,
+//! `P = 9` for the default `relativeSD` of `0.05`.
+//!
+//! Spark hashes values with XxHash64 (seed `42`), and maps a hash differently from
+//! [`HyperLogLog::add_hash`]: the index is the `P` high bits and the register is the number
+//! of leading zeros of the remaining bits plus one, use [`HyperLogLog::add_spark_hash`]
+//! to build sketches which can be merged with Spark's.
+
+use crate::HllError;
+use crate::HyperLogLog;
+
+const REGISTER_SIZE: usize = 6;
+const REGISTERS_PER_WORD: usize = 64 / REGISTER_SIZE;
+const REGISTER_WORD_MASK: i64 = 0x3f;
+
+impl<const P: usize> HyperLogLog<P> {
+    /// Number of `i64` words of Spark's aggregation buffer
+    pub fn spark_num_words() -> usize {
+        Self::NUM_REGISTERS.div_ceil(REGISTERS_PER_WORD)
+    }
+
+    /// Adds an hash computed like Spark's `HyperLogLogPlusPlus`
+    #[inline]
+    pub fn add_spark_hash(&mut self, hash: u64) {
+        let index = (hash >> (64 - P)) as usize;
+        let w_padding = 1_u64 << (P - 1);
+        let pw = ((hash << P) | w_padding).leading_zeros() + 1;
+        self.registers[index] = self.registers[index].max(pw as u8);
+    }
+
+    /// Load the words of Spark's aggregation buffer
+    pub fn from_spark_words(words: &[i64]) -> Result<Self, HllError> {
+        // the length is checked in words, registers past `1 << P` are ignored
+        if words.len() != Self::spark_num_words() {
+            return Err(HllError::InvalidRegisterLength {
+                expected: Self::spark_num_words(),
+                actual: words.len(),
+            });
+        }
+        let registers: Vec<u8> = (0..Self::NUM_REGISTERS)
+            .map(|i| {
+                let shift = REGISTER_SIZE * (i % REGISTERS_PER_WORD);
+                (words[i / REGISTERS_PER_WORD] >> shift & REGISTER_WORD_MASK) as u8
+            })
+            .collect();
+        Self::try_from(registers)
+    }
+
+    /// Serialize into the words of Spark's aggregation buffer
+    pub fn to_spark_words(&self) -> Vec<i64> {
+        self.registers
+            .chunks(REGISTERS_PER_WORD)
+            .map(|chunk| {
+                chunk.iter().enumerate().fold(0_i64, |word, (i, r)| {
+                    word | (*r as i64 & REGISTER_WORD_MASK) << (REGISTER_SIZE * i)
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spark_words() {
+        let mut hll = HyperLogLog::<9>::new();
+        assert_eq!(HyperLogLog::<9>::spark_num_words(), 52);
+        for i in 0..10_000 {
+            hll.add_object(&i);
+        }
+        let words = hll.to_spark_words();
+        assert_eq!(words.len(), 52);
+        assert_eq!(HyperLogLog::<9>::from_spark_words(&words).unwrap(), hll);
+
+        let mut hll = HyperLogLog::<9>::new();
+        hll.registers[0] = 1;
+        hll.registers[9] = 55;
+        hll.registers[10] = 2;
+        let words = hll.to_spark_words();
+        assert_eq!(words[0], 1 | 55 << 54);
+        assert_eq!(words[1], 2);
+    }
+
+    #[test]
+    fn test_add_spark_hash() {
+        let mut hll = HyperLogLog::<9>::new();
+        // index 0b1_0000_0001, then 3 zeros
+        hll.add_spark_hash(0b1_0000_0001_0001 << 51);
+        assert_eq!(hll.registers[257], 4);
+        // all the remaining bits are zero
+        hll.add_spark_hash(5 << 55);
+        assert_eq!(hll.registers[5], 64 - 9 + 1);
+        assert_eq!(hll.num_empty_registers(), 512 - 2);
+    }
+
+    #[test]
+    fn test_spark_invalid() {
+        assert_eq!(
+            HyperLogLog::<9>::from_spark_words(&[0; 51]),
+            Err(HllError::InvalidRegisterLength {
+                expected: 52,
+                actual: 51
+            })
+        );
+        assert_eq!(
+            HyperLogLog::<9>::from_spark_words(&[63; 52]),
+            Err(HllError::InvalidRegisterValue {
+                index: 0,
+                value: 63,
+                max: 56
+            })
+        );
+    }
+}
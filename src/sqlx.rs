@@ -0,0 +1,118 @@
+//! `sqlx` codecs, a sketch is a binary column (`BYTEA`, `BLOB`) holding the binary format
+//! of [`HyperLogLog::to_bytes`], for every database whose binary type is `Vec<u8>`.
+//!
+//! ```ignore
+//! sqlx::query("INSERT INTO daily (day, users) VALUES ($1, $2)")
+//!     .bind(day)
+//!     .bind(&hll)
+//!     .execute(&pool)
+//!     .await?;
+//! let users: HyperLogLog = sqlx::query_scalar("SELECT users FROM daily WHERE day = $1")
+//!     .bind(day)
+//!     .fetch_one(&pool)
+//!     .await?;
+//! ```
+
+use ::sqlx::encode::IsNull;
+use ::sqlx::error::BoxDynError;
+use ::sqlx::Database;
+use ::sqlx::Decode;
+use ::sqlx::Encode;
+use ::sqlx::Type;
+
+use crate::HyperLogLog;
+
+impl<DB: Database, const P: usize> Type<DB> for HyperLogLog<P>
+where
+    Vec<u8>: Type<DB>,
+{
+    fn type_info() -> DB::TypeInfo {
+        <Vec<u8> as Type<DB>>::type_info()
+    }
+
+    fn compatible(ty: &DB::TypeInfo) -> bool {
+        <Vec<u8> as Type<DB>>::compatible(ty)
+    }
+}
+
+impl<'q, DB: Database, const P: usize> Encode<'q, DB> for HyperLogLog<P>
+where
+    Vec<u8>: Encode<'q, DB>,
+{
+    fn encode_by_ref(
+        &self,
+        buf: &mut <DB as Database>::ArgumentBuffer<'q>,
+    ) -> Result<IsNull, BoxDynError> {
+        self.to_bytes().encode(buf)
+    }
+
+    fn size_hint(&self) -> usize {
+        self.serialized_size()
+    }
+}
+
+/// Fails if the column isn't a sketch of precision `P` built by the default hasher
+impl<'r, DB: Database, const P: usize> Decode<'r, DB> for HyperLogLog<P>
+where
+    &'r [u8]: Decode<'r, DB>,
+{
+    fn decode(value: <DB as Database>::ValueRef<'r>) -> Result<Self, BoxDynError> {
+        let bytes = <&[u8] as Decode<DB>>::decode(value)?;
+        Ok(Self::from_bytes(bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::sqlx::Connection;
+    use ::sqlx::SqliteConnection;
+
+    use crate::HyperLogLog;
+
+    #[test]
+    fn test_sqlx() {
+        let mut hll = HyperLogLog::<12>::new();
+        for i in 0..1000 {
+            hll.add_object(&i);
+        }
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+            ::sqlx::query("CREATE TABLE daily (day INTEGER, users BLOB)")
+                .execute(&mut conn)
+                .await
+                .unwrap();
+            ::sqlx::query("INSERT INTO daily VALUES (1, ?), (2, x'00')")
+                .bind(&hll)
+                .execute(&mut conn)
+                .await
+                .unwrap();
+
+            let users: HyperLogLog<12> =
+                ::sqlx::query_scalar("SELECT users FROM daily WHERE day = 1")
+                    .fetch_one(&mut conn)
+                    .await
+                    .unwrap();
+            assert_eq!(users, hll);
+            let bytes: Vec<u8> = ::sqlx::query_scalar("SELECT users FROM daily WHERE day = 1")
+                .fetch_one(&mut conn)
+                .await
+                .unwrap();
+            assert_eq!(bytes, hll.to_bytes());
+
+            // invalid bytes, another precision
+            for day in [2, 1] {
+                let users = ::sqlx::query_scalar::<_, HyperLogLog<14>>(
+                    "SELECT users FROM daily WHERE day = ?",
+                )
+                .bind(day)
+                .fetch_one(&mut conn)
+                .await;
+                assert!(users.is_err());
+            }
+        });
+    }
+}
@@ -0,0 +1,82 @@
+//! Fill sketches from async streams.
+
+use core::future::Future;
+use core::hash::Hash;
+use core::pin::pin;
+use core::pin::Pin;
+use core::task::Context;
+use core::task::Poll;
+
+use futures_util::Stream;
+use futures_util::StreamExt;
+
+use crate::Hasher;
+use crate::HyperLogLog;
+
+/// Items added before yielding to the executor, so a stream that is always ready doesn't
+/// starve the other tasks
+const YIELD_EVERY: usize = 1024;
+
+impl<const P: usize> HyperLogLog<P> {
+    /// Adds every item of the stream, hashed with the default hasher like
+    /// [`Self::add_object`]
+    pub async fn add_from_stream<T: Hash>(&mut self, stream: impl Stream<Item = T>) {
+        self.add_from_stream_by_hasher::<T, ahash::AHasher>(stream)
+            .await
+    }
+
+    pub async fn add_from_stream_by_hasher<T: Hash, H: Hasher>(
+        &mut self,
+        stream: impl Stream<Item = T>,
+    ) {
+        let mut stream = pin!(stream);
+        let mut added = 0;
+        while let Some(item) = stream.next().await {
+            self.add_object_by_hasher::<T, H>(&item);
+            added += 1;
+            if added % YIELD_EVERY == 0 {
+                YieldNow(false).await;
+            }
+        }
+    }
+}
+
+/// Pending once, after waking the task up again
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use futures::stream;
+
+    use super::*;
+
+    #[test]
+    fn test_add_from_stream() {
+        let mut expected = HyperLogLog::<14>::new();
+        for i in 0..10_000_u64 {
+            expected.add_object(&i);
+        }
+
+        let mut hll = HyperLogLog::<14>::new();
+        block_on(hll.add_from_stream(stream::iter(0..10_000_u64)));
+        assert_eq!(hll, expected);
+
+        block_on(hll.add_from_stream(stream::empty::<u64>()));
+        assert_eq!(hll, expected);
+    }
+}
@@ -0,0 +1,376 @@
+//! Serde representation of a sketch as a struct with named fields, opt in with
+//! `#[serde(with = "simple_hll::tagged")]`:
+//!
+//! ```json
+//! {"p": 14, "kind": "sparse", "data": [[17, 2], [4021, 1]]}
+//! ```
+//!
+//! - `p`: the precision, a sketch of a higher precision is folded like
+//!   [`HyperLogLog::fold`] does.
+//! - `kind`: `"empty"`, `"sparse"` or `"dense"`.
+//! - `data`: `[index, value]` pairs of the non-zero registers for `"empty"` and `"sparse"`,
+//!   the 6-bit packed registers for `"dense"`, as a base64 string in human-readable formats
+//!   and as bytes otherwise.
+//!
+//! Unlike the default externally tagged enum, it's easy to query from JSONB columns and
+//! other languages, e.g. `sketch->>'kind'` in PostgreSQL.
+
+use core::fmt;
+
+use serde::de::Error as _;
+use serde::ser::SerializeSeq;
+use serde::ser::SerializeStruct;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+
+use crate::encoding;
+use crate::hyperloglog::MAX_P;
+use crate::packed;
+use crate::HllError;
+use crate::HyperLogLog;
+
+const FIELDS: &[&str] = &["p", "kind", "data"];
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Kind {
+    Empty,
+    Sparse,
+    Dense,
+}
+
+/// Serialize `hll` as a struct with named fields
+pub fn serialize<S: Serializer, const P: usize>(
+    hll: &HyperLogLog<P>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let m = HyperLogLog::<P>::NUM_REGISTERS;
+    let non_empty = m - hll.num_empty_registers();
+    // about the same threshold as the sparse representation of the binary format
+    let kind = if non_empty == 0 {
+        Kind::Empty
+    } else if non_empty * 3 < m {
+        Kind::Sparse
+    } else {
+        Kind::Dense
+    };
+
+    let mut s = serializer.serialize_struct("HyperLogLog", FIELDS.len())?;
+    s.serialize_field("p", &(P as u8))?;
+    s.serialize_field("kind", &kind)?;
+    if kind == Kind::Dense {
+        s.serialize_field("data", &DenseData(&hll.registers))?;
+    } else {
+        s.serialize_field("data", &SparseData(&hll.registers, non_empty))?;
+    }
+    s.end()
+}
+
+/// Deserialize a sketch written by [`serialize`]
+pub fn deserialize<'de, D: Deserializer<'de>, const P: usize>(
+    deserializer: D,
+) -> Result<HyperLogLog<P>, D::Error> {
+    deserializer.deserialize_struct("HyperLogLog", FIELDS, TaggedVisitor::<P>)
+}
+
+/// JSON schema of the struct, for `#[schemars(schema_with = "simple_hll::tagged::json_schema::<14>")]`
+#[cfg(feature = "schemars")]
+pub fn json_schema<const P: usize>(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+    // registers of a higher precision are smaller
+    let max = HyperLogLog::<P>::max_register_value();
+    schemars::json_schema!({
+        "type": "object",
+        "description": "HyperLogLog sketch, data is the [index, value] pairs of the non-zero registers if kind is empty or sparse, the base64 of the 6-bit packed registers if kind is dense",
+        "required": ["p", "kind", "data"],
+        "properties": {
+            "p": { "type": "integer", "minimum": P, "maximum": MAX_P },
+            "kind": { "enum": ["empty", "sparse", "dense"] },
+            "data": {
+                "anyOf": [
+                    {
+                        "type": "array",
+                        "items": {
+                            "type": "array",
+                            "prefixItems": [
+                                { "type": "integer", "minimum": 0 },
+                                { "type": "integer", "minimum": 0, "maximum": max }
+                            ],
+                            "minItems": 2,
+                            "maxItems": 2
+                        }
+                    },
+                    { "type": "string", "contentEncoding": "base64" }
+                ]
+            }
+        }
+    })
+}
+
+/// Non-zero registers, serialized as `[index, value]` pairs
+struct SparseData<'a>(&'a [u8], usize);
+
+impl Serialize for SparseData<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.1))?;
+        for (index, value) in self.0.iter().enumerate() {
+            if *value != 0 {
+                seq.serialize_element(&(index as u32, *value))?;
+            }
+        }
+        seq.end()
+    }
+}
+
+/// Registers packed into 6 bits each
+struct DenseData<'a>(&'a [u8]);
+
+impl Serialize for DenseData<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_bytes(&packed::iter(self.0).collect::<Vec<_>>())
+        }
+    }
+}
+
+impl fmt::Display for DenseData<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        encoding::write_base64(f, packed::iter(self.0))
+    }
+}
+
+/// The `data` field, whatever the kind, field order isn't preserved by every format
+/// (e.g. PostgreSQL's JSONB sorts the keys), so `kind` may come after `data`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Data {
+    Sparse(Vec<(u32, u8)>),
+    Base64(String),
+    Bytes(#[serde(deserialize_with = "crate::serde::deserialize_bytes")] Vec<u8>),
+}
+
+struct TaggedVisitor<const P: usize>;
+
+impl<'de, const P: usize> serde::de::Visitor<'de> for TaggedVisitor<P> {
+    type Value = HyperLogLog<P>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("struct HyperLogLog with fields p, kind and data")
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let p: u8 = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+        let kind: Kind = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::invalid_length(1, &self))?;
+        // formats without field names keep the order, the kind is known before the data
+        let data = match kind {
+            Kind::Dense => seq.next_element::<Bytes>()?.map(|b| Data::Bytes(b.0)),
+            _ => seq.next_element()?.map(Data::Sparse),
+        }
+        .ok_or_else(|| A::Error::invalid_length(2, &self))?;
+        from_parts(p, kind, data).map_err(A::Error::custom)
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let (mut p, mut kind, mut data) = (None, None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "p" => p = Some(map.next_value()?),
+                "kind" => kind = Some(map.next_value()?),
+                "data" => data = Some(map.next_value()?),
+                _ => {
+                    map.next_value::<serde::de::IgnoredAny>()?;
+                }
+            }
+        }
+        let p = p.ok_or_else(|| A::Error::missing_field("p"))?;
+        let kind = kind.ok_or_else(|| A::Error::missing_field("kind"))?;
+        let data = data.ok_or_else(|| A::Error::missing_field("data"))?;
+        from_parts(p, kind, data).map_err(A::Error::custom)
+    }
+}
+
+struct Bytes(Vec<u8>);
+
+impl<'de> Deserialize<'de> for Bytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::serde::deserialize_bytes(deserializer).map(Bytes)
+    }
+}
+
+fn from_parts<const P: usize>(p: u8, kind: Kind, data: Data) -> Result<HyperLogLog<P>, HllError> {
+    let p = p as usize;
+    if p > MAX_P {
+        return Err(HllError::InvalidPrecision(p));
+    }
+    if p < P {
+        return Err(HllError::PrecisionMismatch {
+            expected: P,
+            actual: p,
+        });
+    }
+
+    let m = 1 << p;
+    let registers = match (kind, data) {
+        (Kind::Empty | Kind::Sparse, Data::Sparse(entries)) => {
+            let mut registers = vec![0; m];
+            for (index, value) in entries {
+                let index = index as usize;
+                if index >= m {
+                    return Err(HllError::InvalidRegisterIndex { index, len: m });
+                }
+                registers[index] = value;
+            }
+            registers
+        }
+        (Kind::Dense, Data::Base64(s)) => {
+            let bytes = encoding::from_base64(&s)
+                .ok_or_else(|| HllError::Malformed("invalid base64".to_string()))?;
+            unpack(&bytes, m)?
+        }
+        (Kind::Dense, Data::Bytes(bytes)) => unpack(&bytes, m)?,
+        _ => {
+            return Err(HllError::Malformed(
+                "data doesn't match the kind".to_string(),
+            ))
+        }
+    };
+
+    HyperLogLog::<P>::try_from_precision(p, registers)
+}
+
+fn unpack(bytes: &[u8], m: usize) -> Result<Vec<u8>, HllError> {
+    if bytes.len() != packed::packed_len(m) {
+        return Err(HllError::InvalidRegisterLength {
+            expected: m,
+            actual: bytes.len() * 8 / 6,
+        });
+    }
+    Ok(packed::unpack(bytes, m))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Row {
+        #[serde(with = "crate::tagged")]
+        sketch: HyperLogLog<12>,
+    }
+
+    fn row(n: usize) -> Row {
+        let mut sketch = HyperLogLog::new();
+        for i in 0..n {
+            sketch.add_object(&i);
+        }
+        Row { sketch }
+    }
+
+    #[test]
+    fn test_json() {
+        let json = serde_json::to_value(row(0)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"sketch": {"p": 12, "kind": "empty", "data": []}})
+        );
+
+        let mut row = row(0);
+        row.sketch.add_hash(5 | 1 << 14);
+        let json = serde_json::to_value(&row).unwrap();
+        assert_eq!(json["sketch"]["kind"], "sparse");
+        assert_eq!(json["sketch"]["data"], serde_json::json!([[5, 3]]));
+        assert_eq!(serde_json::from_value::<Row>(json).unwrap(), row);
+
+        for n in [100, 100_000] {
+            let row = self::row(n);
+            let json = serde_json::to_string(&row).unwrap();
+            assert_eq!(serde_json::from_str::<Row>(&json).unwrap(), row);
+        }
+        let json = serde_json::to_value(self::row(100_000)).unwrap();
+        assert_eq!(json["sketch"]["kind"], "dense");
+        assert!(json["sketch"]["data"].is_string());
+
+        // keys reordered, like PostgreSQL's JSONB does
+        let json = r#"{"sketch": {"p": 12, "data": [[5, 3]], "kind": "sparse"}}"#;
+        assert_eq!(serde_json::from_str::<Row>(json).unwrap(), row);
+    }
+
+    #[cfg(feature = "schemars")]
+    #[test]
+    fn test_json_schema() {
+        let schema = json_schema::<12>(&mut schemars::SchemaGenerator::default());
+        let sketch = serde_json::to_value(schema).unwrap();
+        assert_eq!(sketch["required"], serde_json::json!(["p", "kind", "data"]));
+        assert_eq!(sketch["properties"]["p"]["minimum"], 12);
+        let value = &sketch["properties"]["data"]["anyOf"][0]["items"]["prefixItems"][1];
+        assert_eq!(value["maximum"], 64 - 12 + 1);
+    }
+
+    #[test]
+    fn test_binary() {
+        for n in [0, 100, 100_000] {
+            let row = row(n);
+            let bytes = bincode::serialize(&row).unwrap();
+            assert_eq!(bincode::deserialize::<Row>(&bytes).unwrap(), row);
+            let bytes = rmp_serde::to_vec(&row).unwrap();
+            assert_eq!(rmp_serde::from_slice::<Row>(&bytes).unwrap(), row);
+            // not self-describing, the data is read according to the kind
+            let bytes = bitcode::serialize(&row).unwrap();
+            assert_eq!(bitcode::deserialize::<Row>(&bytes).unwrap(), row);
+            let bytes = rmp_serde::to_vec_named(&row).unwrap();
+            assert_eq!(rmp_serde::from_slice::<Row>(&bytes).unwrap(), row);
+        }
+    }
+
+    #[test]
+    fn test_fold_and_invalid() {
+        #[derive(Serialize)]
+        struct Wide {
+            #[serde(with = "crate::tagged")]
+            sketch: HyperLogLog<14>,
+        }
+
+        let mut sketch = HyperLogLog::<14>::new();
+        for i in 0..100_000 {
+            sketch.add_object(&i);
+        }
+        let json = serde_json::to_string(&Wide {
+            sketch: sketch.clone(),
+        })
+        .unwrap();
+        assert_eq!(
+            serde_json::from_str::<Row>(&json).unwrap().sketch,
+            sketch.fold::<12>().unwrap()
+        );
+
+        for (json, error) in [
+            (
+                r#"{"sketch": {"p": 10, "kind": "empty", "data": []}}"#,
+                "precision mismatch",
+            ),
+            (
+                r#"{"sketch": {"p": 12, "kind": "sparse", "data": [[4096, 1]]}}"#,
+                "invalid register index 4096",
+            ),
+            (
+                r#"{"sketch": {"p": 12, "kind": "sparse", "data": [[1, 60]]}}"#,
+                "invalid value 60",
+            ),
+            (
+                r#"{"sketch": {"p": 12, "kind": "dense", "data": [[1, 1]]}}"#,
+                "doesn't match the kind",
+            ),
+            (r#"{"sketch": {"p": 12, "kind": "dense"}}"#, "missing field"),
+        ] {
+            let e = serde_json::from_str::<Row>(json).unwrap_err().to_string();
+            assert!(e.contains(error), "{}: {}", json, e);
+        }
+    }
+}
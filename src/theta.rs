@@ -0,0 +1,375 @@
+//! # Theta sketch
+//!
+//! A Theta sketch keeps the `k` smallest hashes, with `theta` the `k + 1`-th smallest, so
+//! every distinct item was kept with probability `theta`. Unlike HyperLogLog, sketches can
+//! be intersected and subtracted, the hashes of the result are the ones found in both
+//! (or only in the first) sketches below the smallest theta.
+//!
+//! Binary format, all integers are little-endian:
+//!
+//! | offset | size | field                                         |
+//! |--------|------|-----------------------------------------------|
+//! | 0      | 4    | magic `b"STHT"`                               |
+//! | 4      | 1    | format version, currently `1`                 |
+//! | 5      | 1    | hasher id, see [`crate::Hasher::ID`]          |
+//! | 6      | 4    | `k`, the nominal number of entries            |
+//! | 10     | 8    | `theta`, `u64::MAX` until more than `k` hashes were seen |
+//! | 18     | 4    | number of hashes                              |
+//! | 22     | ..   | the `u64` hashes, in increasing order, all smaller than `theta` |
+
+use core::hash::Hash;
+use std::collections::BTreeSet;
+
+use crate::format::SliceReader;
+use crate::CardinalitySketch;
+use crate::Hasher;
+use crate::HllError;
+
+/// Nominal entries of [`ThetaSketch::new`], the relative error is about `1 / sqrt(k)`
+pub const DEFAULT_NOMINAL_ENTRIES: usize = 4096;
+
+const MAGIC: &[u8; 4] = b"STHT";
+const VERSION: u8 = 1;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ThetaSketch {
+    k: usize,
+    theta: u64,
+    /// Invariant: at most `k` hashes, all smaller than `theta`
+    hashes: BTreeSet<u64>,
+}
+
+impl Default for ThetaSketch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ThetaSketch {
+    pub fn new() -> Self {
+        Self::with_nominal_entries(DEFAULT_NOMINAL_ENTRIES)
+    }
+
+    /// Keeps `k` hashes, at least one
+    pub fn with_nominal_entries(k: usize) -> Self {
+        Self {
+            k: k.max(1),
+            theta: u64::MAX,
+            hashes: BTreeSet::new(),
+        }
+    }
+
+    #[inline]
+    pub fn add_hash(&mut self, hash: u64) {
+        if hash < self.theta && self.hashes.insert(hash) {
+            self.trim();
+        }
+    }
+
+    #[inline]
+    pub fn add_object<T: Hash>(&mut self, obj: &T) {
+        self.add_object_by_hasher::<T, ahash::AHasher>(obj);
+    }
+
+    #[inline]
+    pub fn add_object_by_hasher<T: Hash, H: Hasher>(&mut self, obj: &T) {
+        self.add_hash(H::hll_hash(obj));
+    }
+
+    /// Drop the largest hashes above `k`, the last one dropped is the new theta
+    fn trim(&mut self) {
+        while self.hashes.len() > self.k {
+            self.theta = self.hashes.pop_last().unwrap_or(self.theta);
+        }
+    }
+
+    /// Merge the other [`ThetaSketch`] into this one, see [`Self::union`]
+    pub fn merge(&mut self, other: &Self) {
+        self.k = self.k.min(other.k);
+        self.theta = self.theta.min(other.theta);
+        let theta = self.theta;
+        self.hashes.retain(|hash| *hash < theta);
+        self.hashes.extend(other.hashes.range(..theta).copied());
+        self.trim();
+    }
+
+    /// The sketch of the items seen by either sketch
+    pub fn union(&self, other: &Self) -> Self {
+        let mut union = self.clone();
+        union.merge(other);
+        union
+    }
+
+    /// The sketch of the items seen by both sketches
+    pub fn intersection(&self, other: &Self) -> Self {
+        let theta = self.theta.min(other.theta);
+        Self {
+            k: self.k.min(other.k),
+            theta,
+            hashes: self
+                .hashes
+                .range(..theta)
+                .filter(|hash| other.hashes.contains(hash))
+                .copied()
+                .collect(),
+        }
+    }
+
+    /// The sketch of the items seen by this sketch but not by the other one
+    pub fn a_not_b(&self, other: &Self) -> Self {
+        let theta = self.theta.min(other.theta);
+        Self {
+            k: self.k,
+            theta,
+            hashes: self
+                .hashes
+                .range(..theta)
+                .filter(|hash| !other.hashes.contains(hash))
+                .copied()
+                .collect(),
+        }
+    }
+
+    /// Probability of an item to be kept
+    #[inline]
+    pub fn theta(&self) -> f64 {
+        if self.theta == u64::MAX {
+            1.0
+        } else {
+            self.theta as f64 / u64::MAX as f64
+        }
+    }
+
+    /// Number of hashes kept
+    #[inline]
+    pub fn num_retained(&self) -> usize {
+        self.hashes.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty() && self.theta == u64::MAX
+    }
+
+    /// Estimate of the number of unique elements, the hashes kept divided by theta
+    pub fn estimate(&self) -> f64 {
+        self.hashes.len() as f64 / self.theta()
+    }
+
+    /// Guess the number of unique elements seen by the sketch.
+    #[inline]
+    pub fn count(&self) -> usize {
+        self.estimate().round() as usize
+    }
+
+    /// Bounds of the estimate with a confidence of `num_std_dev` standard deviations,
+    /// from the normal approximation of the binomial number of hashes kept. The bounds
+    /// are exact until more than `k` hashes were seen.
+    pub fn bounds(&self, num_std_dev: u8) -> (f64, f64) {
+        let n = self.hashes.len() as f64;
+        let theta = self.theta();
+        if theta == 1.0 {
+            return (n, n);
+        }
+        // one hash kept at least, so sketches with no hash left have an upper bound
+        let std_dev = (n.max(1.0) * (1.0 - theta)).sqrt() / theta;
+        let estimate = n / theta;
+        let margin = num_std_dev as f64 * std_dev;
+        ((estimate - margin).max(n), estimate + margin)
+    }
+
+    /// Serialize into the binary format described in the [module](self) documentation,
+    /// tagged with the id of the default hasher.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_with_hasher::<ahash::AHasher>()
+    }
+
+    /// Same as [`Self::to_bytes`], but tagged with the id of hasher `H`
+    pub fn to_bytes_with_hasher<H: Hasher>(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(22 + 8 * self.hashes.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.push(H::ID);
+        bytes.extend_from_slice(&(self.k as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.theta.to_le_bytes());
+        bytes.extend_from_slice(&(self.hashes.len() as u32).to_le_bytes());
+        for hash in &self.hashes {
+            bytes.extend_from_slice(&hash.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, HllError> {
+        Self::from_bytes_with_hasher::<ahash::AHasher>(bytes)
+    }
+
+    /// Deserialize a sketch built with hasher `H`
+    pub fn from_bytes_with_hasher<H: Hasher>(bytes: &[u8]) -> Result<Self, HllError> {
+        let mut reader = SliceReader::new(bytes);
+        if reader.read_slice(MAGIC.len())? != MAGIC {
+            return Err(HllError::InvalidMagic);
+        }
+        let version = reader.read_u8()?;
+        if version != VERSION {
+            return Err(HllError::UnsupportedVersion(version));
+        }
+        let hasher = reader.read_u8()?;
+        if hasher != H::ID {
+            return Err(HllError::HasherMismatch {
+                expected: H::ID,
+                actual: hasher,
+            });
+        }
+        let k = reader.read_u32()? as usize;
+        let theta = reader.read_u64()?;
+        let len = reader.read_u32()? as usize;
+        if k == 0 || len > k {
+            return Err(HllError::Malformed(format!(
+                "theta: {} hashes for k = {}",
+                len, k
+            )));
+        }
+        if len > reader.remaining() / 8 {
+            return Err(HllError::UnexpectedEof);
+        }
+
+        let mut hashes = BTreeSet::new();
+        let mut previous = None;
+        for _ in 0..len {
+            let hash = reader.read_u64()?;
+            if hash >= theta || previous.is_some_and(|previous| hash <= previous) {
+                return Err(HllError::Malformed(
+                    "theta: hashes not increasing below theta".to_string(),
+                ));
+            }
+            previous = Some(hash);
+            hashes.insert(hash);
+        }
+        reader.finish()?;
+        Ok(Self { k, theta, hashes })
+    }
+}
+
+/// Serialized as the bytes of [`ThetaSketch::to_bytes`]
+#[cfg(feature = "serde")]
+impl serde::Serialize for ThetaSketch {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.to_bytes())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for ThetaSketch {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = crate::serde::deserialize_bytes(deserializer)?;
+        Self::from_bytes(&bytes).map_err(serde::de::Error::custom)
+    }
+}
+
+impl CardinalitySketch for ThetaSketch {
+    #[inline]
+    fn add_hash(&mut self, hash: u64) {
+        ThetaSketch::add_hash(self, hash);
+    }
+
+    fn merge(&mut self, other: &Self) {
+        ThetaSketch::merge(self, other);
+    }
+
+    fn count(&self) -> usize {
+        ThetaSketch::count(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        ThetaSketch::is_empty(self)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        ThetaSketch::to_bytes(self)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, HllError> {
+        ThetaSketch::from_bytes(bytes)
+    }
+
+    fn memory_bytes(&self) -> usize {
+        // the nodes of the tree hold up to 11 hashes, with some overhead
+        core::mem::size_of::<Self>() + 12 * self.hashes.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sketch(items: impl Iterator<Item = u64>) -> ThetaSketch {
+        let mut theta = ThetaSketch::with_nominal_entries(1024);
+        for i in items {
+            theta.add_object(&i);
+        }
+        theta
+    }
+
+    fn assert_close(sketch: &ThetaSketch, n: usize) {
+        let (lower, upper) = sketch.bounds(3);
+        assert!(
+            lower <= n as f64 && n as f64 <= upper,
+            "{} not in [{}, {}]",
+            n,
+            lower,
+            upper
+        );
+    }
+
+    #[test]
+    fn test_theta() {
+        let small = sketch(0..500);
+        assert_eq!(small.count(), 500);
+        assert_eq!(small.bounds(2), (500.0, 500.0));
+        assert!(ThetaSketch::new().is_empty());
+
+        let a = sketch(0..100_000);
+        let b = sketch(50_000..200_000);
+        assert_eq!(a.num_retained(), 1024);
+        assert_close(&a, 100_000);
+
+        assert_eq!(a.union(&b), sketch(0..200_000));
+        assert_close(&a.union(&b), 200_000);
+        assert_close(&a.intersection(&b), 50_000);
+        assert_close(&a.a_not_b(&b), 50_000);
+        assert_close(&a.intersection(&sketch(200_000..300_000)), 0);
+
+        let mut merged = a.clone();
+        merged.merge(&b);
+        assert_eq!(merged, a.union(&b));
+    }
+
+    #[test]
+    fn test_serialize() {
+        for theta in [ThetaSketch::new(), sketch(0..500), sketch(0..100_000)] {
+            let bytes = theta.to_bytes();
+            assert_eq!(bytes.len(), 22 + 8 * theta.num_retained());
+            assert_eq!(ThetaSketch::from_bytes(&bytes).unwrap(), theta);
+        }
+
+        let mut bytes = sketch(0..10).to_bytes();
+        assert_eq!(
+            ThetaSketch::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err(),
+            HllError::UnexpectedEof
+        );
+        // swap the first two hashes
+        bytes[22..38].rotate_left(8);
+        assert!(matches!(
+            ThetaSketch::from_bytes(&bytes).unwrap_err(),
+            HllError::Malformed(_)
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let theta = sketch(0..5000);
+        let json = serde_json::to_string(&theta).unwrap();
+        assert_eq!(serde_json::from_str::<ThetaSketch>(&json).unwrap(), theta);
+    }
+}
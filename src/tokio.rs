@@ -0,0 +1,86 @@
+//! Fill sketches from tokio async readers, the async twin of `add_from_reader`.
+
+use ::tokio::io::AsyncBufRead;
+use ::tokio::io::AsyncBufReadExt;
+
+use crate::io::trim_record;
+use crate::Hasher;
+use crate::HyperLogLog;
+
+impl<const P: usize> HyperLogLog<P> {
+    /// A sketch of every line of the reader, hashed like [`Self::add_from_reader`]
+    pub async fn from_async_reader<R: AsyncBufRead + Unpin>(reader: R) -> std::io::Result<Self> {
+        let mut hll = Self::new();
+        hll.add_from_async_reader(reader).await?;
+        Ok(hll)
+    }
+
+    /// Adds every line of the reader to the HyperLogLog, returns the number of lines processed.
+    /// Line endings (`\n` or `\r\n`) are stripped and each line is hashed as a `&[u8]`.
+    pub async fn add_from_async_reader<R: AsyncBufRead + Unpin>(
+        &mut self,
+        reader: R,
+    ) -> std::io::Result<usize> {
+        self.add_from_async_reader_delimited(reader, b'\n').await
+    }
+
+    /// Adds every record separated by `delimiter` to the HyperLogLog,
+    /// returns the number of records processed.
+    pub async fn add_from_async_reader_delimited<R: AsyncBufRead + Unpin>(
+        &mut self,
+        reader: R,
+        delimiter: u8,
+    ) -> std::io::Result<usize> {
+        self.add_from_async_reader_by_hasher::<R, ahash::AHasher>(reader, delimiter)
+            .await
+    }
+
+    pub async fn add_from_async_reader_by_hasher<R: AsyncBufRead + Unpin, H: Hasher>(
+        &mut self,
+        mut reader: R,
+        delimiter: u8,
+    ) -> std::io::Result<usize> {
+        let mut buf = Vec::new();
+        let mut records = 0;
+        loop {
+            buf.clear();
+            if reader.read_until(delimiter, &mut buf).await? == 0 {
+                return Ok(records);
+            }
+            self.add_object_by_hasher::<_, H>(&trim_record(&buf, delimiter));
+            records += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use crate::HyperLogLog;
+
+    #[test]
+    fn test_add_from_async_reader() {
+        let mut input = String::new();
+        for i in 0..10000 {
+            input.push_str(&format!("line-{}\n", i % 100));
+        }
+
+        let mut expected = HyperLogLog::<14>::new();
+        expected.add_from_reader(input.as_bytes()).unwrap();
+
+        let mut hll = HyperLogLog::<14>::new();
+        let lines = block_on(hll.add_from_async_reader(input.as_bytes())).unwrap();
+        assert_eq!(lines, 10000);
+        assert_eq!(hll, expected);
+
+        let crlf = input.replace('\n', "\r\n");
+        let hll = block_on(HyperLogLog::<14>::from_async_reader(crlf.as_bytes())).unwrap();
+        assert_eq!(hll, expected);
+
+        let mut hll = HyperLogLog::<14>::new();
+        let records = block_on(hll.add_from_async_reader_delimited(&b"a,b,a"[..], b',')).unwrap();
+        assert_eq!(records, 3);
+        assert_eq!(hll.count(), 2);
+    }
+}
@@ -0,0 +1,407 @@
+//! # UltraLogLog
+//!
+//! UltraLogLog from "UltraLogLog: A Practical and More Space-Efficient Alternative to
+//! HyperLogLog for Approximate Distinct Counting", Otmar Ertl, https://arxiv.org/abs/2308.16862
+//!
+//! Like HyperLogLog, each register keeps the largest rank of the hashes of its index, and
+//! also whether the two ranks below it were seen. Estimated with maximum likelihood, the
+//! error is about 25% lower than HyperLogLog with the same number of registers.
+//!
+//! Binary format:
+//!
+//! | offset | size | field                                         |
+//! |--------|------|-----------------------------------------------|
+//! | 0      | 4    | magic `b"SULL"`                               |
+//! | 4      | 1    | format version, currently `1`                 |
+//! | 5      | 1    | precision `P`                                 |
+//! | 6      | 1    | hasher id, see [`crate::Hasher::ID`]          |
+//! | 7      | ..   | `1 << P` registers, one byte each             |
+
+use core::hash::Hash;
+
+use crate::format::SliceReader;
+use crate::hyperloglog::DEFAULT_P;
+use crate::CardinalitySketch;
+use crate::Hasher;
+use crate::HllError;
+use crate::HyperLogLog;
+
+const MAGIC: &[u8; 4] = b"SULL";
+const VERSION: u8 = 1;
+const HEADER_SIZE: usize = 7;
+
+/// Indexes and ranks hashes like [`HyperLogLog`], so it can be turned into the
+/// [`HyperLogLog`] of the same hashes, but not the other way around: HyperLogLog registers
+/// don't keep the lower ranks.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UltraLogLog<const P: usize = DEFAULT_P> {
+    /// `0` if empty, otherwise `4 * rank + seen`, with bit 1 of `seen` set if `rank - 1`
+    /// was seen and bit 0 if `rank - 2` was seen. Always `1 << P` registers.
+    registers: Vec<u8>,
+}
+
+impl<const P: usize> Default for UltraLogLog<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Set of the ranks seen by a register, bit `k` for rank `k`
+#[inline]
+fn unpack(register: u8) -> u64 {
+    if register == 0 {
+        0
+    } else {
+        ((4 | (register as u64 & 3)) << (register >> 2)) >> 2
+    }
+}
+
+#[inline]
+fn pack(ranks: u64) -> u8 {
+    let rank = 63 - ranks.leading_zeros();
+    ((rank << 2) as u64 | (((ranks << 2) >> rank) & 3)) as u8
+}
+
+impl<const P: usize> UltraLogLog<P> {
+    /// Bits of the hash left after the register index
+    const Q: usize = 64 - P;
+
+    pub fn new() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = HyperLogLog::<P>::VALID_PRECISION;
+
+        Self {
+            registers: vec![0; 1 << P],
+        }
+    }
+
+    /// View of the registers, one byte per register
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.registers
+    }
+
+    #[inline]
+    pub fn add_hash(&mut self, hash: u64) {
+        let index = (hash & HyperLogLog::<P>::REGISTER_MASK) as usize;
+        let rank = HyperLogLog::<P>::rank(hash);
+        let register = &mut self.registers[index];
+        *register = pack(unpack(*register) | (1 << rank));
+    }
+
+    #[inline]
+    pub fn add_object<T: Hash>(&mut self, obj: &T) {
+        self.add_object_by_hasher::<T, ahash::AHasher>(obj);
+    }
+
+    #[inline]
+    pub fn add_object_by_hasher<T: Hash, H: Hasher>(&mut self, obj: &T) {
+        self.add_hash(H::hll_hash(obj));
+    }
+
+    /// Merge the other [`UltraLogLog`] into this one
+    pub fn merge(&mut self, other: &Self) {
+        for (register, other) in self.registers.iter_mut().zip(&other.registers) {
+            if *register != *other {
+                *register = pack(unpack(*register) | unpack(*other));
+            }
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.registers.iter().all(|r| *r == 0)
+    }
+
+    /// Probability of a hash to have `rank`, the last rank takes the remaining bits
+    #[inline]
+    fn rank_probability(rank: usize) -> f64 {
+        (-(rank.min(Self::Q) as f64)).exp2()
+    }
+
+    /// Guess the number of unique elements seen by the UltraLogLog.
+    ///
+    /// Maximum likelihood estimate under the Poisson model: with `x` hashes per register,
+    /// rank `k` is seen by a register with probability `1 - e^(-x * p(k))`. Each register
+    /// tells which of its ranks were seen or not, from its largest rank up, the estimate
+    /// is the `x` which maximizes the likelihood of all of them.
+    pub fn count(&self) -> usize {
+        let mut histogram = [0_u32; 256];
+        for register in &self.registers {
+            histogram[*register as usize] += 1;
+        }
+
+        // `not_seen` sums the probabilities of the ranks not seen, `seen[k]` counts the
+        // registers which saw rank `k`
+        let mut not_seen = histogram[0] as f64;
+        let mut seen = [0_u32; 66];
+        for (register, count) in histogram.iter().enumerate().skip(4) {
+            if *count == 0 {
+                continue;
+            }
+            let rank = register >> 2;
+            seen[rank] += count;
+            // probability of the ranks above the largest
+            if rank <= Self::Q {
+                not_seen += *count as f64 * Self::rank_probability(rank);
+            }
+            for (below, bit) in [(1, 2), (2, 1)] {
+                if rank > below {
+                    if register & bit != 0 {
+                        seen[rank - below] += count;
+                    } else {
+                        not_seen += *count as f64 * Self::rank_probability(rank - below);
+                    }
+                }
+            }
+        }
+
+        if not_seen == 0.0 {
+            return usize::MAX;
+        }
+        if seen.iter().all(|count| *count == 0) {
+            return 0;
+        }
+
+        // the derivative of the log likelihood decreases with `x`, its root is found by
+        // bisection over `ln(x)`
+        let derivative = |x: f64| {
+            seen.iter()
+                .enumerate()
+                .filter(|(_, count)| **count != 0)
+                .map(|(rank, count)| {
+                    let p = Self::rank_probability(rank);
+                    *count as f64 * p / (x * p).exp_m1()
+                })
+                .sum::<f64>()
+                - not_seen
+        };
+        let (mut low, mut high) = (-50.0_f64, 50.0_f64);
+        for _ in 0..64 {
+            let mid = (low + high) * 0.5;
+            if derivative(mid.exp()) > 0.0 {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+
+        let x = ((low + high) * 0.5).exp();
+        (x * HyperLogLog::<P>::NUM_REGISTERS as f64).round() as usize
+    }
+}
+
+impl<const P: usize> UltraLogLog<P> {
+    /// Serialize into the binary format described in the [module](self) documentation,
+    /// tagged with the id of the default hasher.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_with_hasher::<ahash::AHasher>()
+    }
+
+    /// Same as [`Self::to_bytes`], but tagged with the id of hasher `H`
+    pub fn to_bytes_with_hasher<H: Hasher>(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + self.registers.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.push(P as u8);
+        bytes.push(H::ID);
+        bytes.extend_from_slice(&self.registers);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, HllError> {
+        Self::from_bytes_with_hasher::<ahash::AHasher>(bytes)
+    }
+
+    /// Deserialize a sketch built with hasher `H`, every register is validated
+    pub fn from_bytes_with_hasher<H: Hasher>(bytes: &[u8]) -> Result<Self, HllError> {
+        let mut reader = SliceReader::new(bytes);
+        if reader.read_slice(MAGIC.len())? != MAGIC {
+            return Err(HllError::InvalidMagic);
+        }
+        let version = reader.read_u8()?;
+        if version != VERSION {
+            return Err(HllError::UnsupportedVersion(version));
+        }
+        let precision = reader.read_u8()? as usize;
+        if precision != P {
+            return Err(HllError::PrecisionMismatch {
+                expected: P,
+                actual: precision,
+            });
+        }
+        let hasher = reader.read_u8()?;
+        if hasher != H::ID {
+            return Err(HllError::HasherMismatch {
+                expected: H::ID,
+                actual: hasher,
+            });
+        }
+        let registers = reader.read_slice(1 << P)?;
+        reader.finish()?;
+
+        let max = (4 * (Self::Q + 1) + 3) as u8;
+        let valid = |register: u8| match register >> 2 {
+            0 => register == 0,
+            // ranks below 1 are never seen
+            1 => register & 3 == 0,
+            2 => register & 1 == 0,
+            _ => register <= max,
+        };
+        if let Some(index) = registers.iter().position(|r| !valid(*r)) {
+            return Err(HllError::InvalidRegisterValue {
+                index,
+                value: registers[index],
+                max,
+            });
+        }
+        Ok(Self {
+            registers: registers.to_vec(),
+        })
+    }
+}
+
+impl<const P: usize> From<&UltraLogLog<P>> for HyperLogLog<P> {
+    /// The [`HyperLogLog`] of the same hashes, with the largest rank of each register
+    fn from(ull: &UltraLogLog<P>) -> Self {
+        HyperLogLog {
+            registers: ull.registers.iter().map(|r| r >> 2).collect(),
+        }
+    }
+}
+
+impl<const P: usize> CardinalitySketch for UltraLogLog<P> {
+    #[inline]
+    fn add_hash(&mut self, hash: u64) {
+        UltraLogLog::add_hash(self, hash);
+    }
+
+    fn merge(&mut self, other: &Self) {
+        UltraLogLog::merge(self, other);
+    }
+
+    fn count(&self) -> usize {
+        UltraLogLog::count(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        UltraLogLog::is_empty(self)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        UltraLogLog::to_bytes(self)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, HllError> {
+        UltraLogLog::from_bytes(bytes)
+    }
+
+    fn memory_bytes(&self) -> usize {
+        core::mem::size_of::<Self>() + self.registers.capacity()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack() {
+        assert_eq!(unpack(0), 0);
+        for register in 4..=(4 * 61 + 3_u8) {
+            // ranks below 1 don't exist, their bits are never set
+            let valid = match register >> 2 {
+                1 => register & 3 == 0,
+                2 => register & 1 == 0,
+                _ => true,
+            };
+            if valid {
+                assert_eq!(pack(unpack(register)), register);
+            }
+        }
+        assert_eq!(pack(0b1010), 4 * 3 + 0b01);
+        assert_eq!(pack(0b1100000), 4 * 6 + 0b10);
+        assert_eq!(pack(0b1110000), 4 * 6 + 0b11);
+    }
+
+    #[test]
+    fn test_ultraloglog() {
+        let mut ull = UltraLogLog::<14>::new();
+        let mut hll = HyperLogLog::<14>::new();
+        assert_eq!(ull.count(), 0);
+        assert!(ull.is_empty());
+
+        for i in 0..100_000_u64 {
+            ull.add_object(&i);
+            hll.add_object(&i);
+        }
+        assert_eq!(HyperLogLog::from(&ull), hll);
+        assert!(!ull.is_empty());
+
+        let mut merged = UltraLogLog::<14>::new();
+        let mut other = UltraLogLog::<14>::new();
+        for i in 0..100_000_u64 {
+            if i % 3 == 0 {
+                merged.add_object(&i);
+            } else {
+                other.add_object(&i);
+            }
+        }
+        merged.merge(&other);
+        assert_eq!(merged, ull);
+    }
+
+    #[test]
+    fn test_serialize() {
+        let mut ull = UltraLogLog::<10>::new();
+        for i in 0..10_000_u64 {
+            ull.add_object(&i);
+        }
+        let mut bytes = ull.to_bytes();
+        assert_eq!(bytes.len(), 7 + 1024);
+        assert_eq!(UltraLogLog::<10>::from_bytes(&bytes).unwrap(), ull);
+        assert_eq!(
+            UltraLogLog::<11>::from_bytes(&bytes).unwrap_err(),
+            HllError::PrecisionMismatch {
+                expected: 11,
+                actual: 10
+            }
+        );
+
+        // rank 1 with rank 0 seen
+        bytes[7] = 4 + 2;
+        assert_eq!(
+            UltraLogLog::<10>::from_bytes(&bytes).unwrap_err(),
+            HllError::InvalidRegisterValue {
+                index: 0,
+                value: 6,
+                max: 4 * 55 + 3
+            }
+        );
+    }
+
+    #[test]
+    fn test_accuracy() {
+        // the squared relative errors over the trials, for both sketches
+        for n in [10_u64, 1_000, 100_000] {
+            let (mut ull_error, mut hll_error) = (0.0, 0.0);
+            for trial in 0..20_u64 {
+                let mut ull = UltraLogLog::<10>::new();
+                let mut hll = HyperLogLog::<10>::new();
+                for i in 0..n {
+                    ull.add_object(&(trial, i));
+                    hll.add_object(&(trial, i));
+                }
+                let error = |count: usize| (count as f64 / n as f64 - 1.0).powi(2);
+                ull_error += error(ull.count());
+                hll_error += error(hll.count());
+            }
+            let ull_error = (ull_error / 20.0).sqrt();
+            let hll_error = (hll_error / 20.0).sqrt();
+            assert!(ull_error < 2.0 * HyperLogLog::<10>::error_rate());
+            if n >= 100_000 {
+                assert!(ull_error < hll_error);
+            }
+        }
+    }
+}
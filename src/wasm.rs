@@ -0,0 +1,123 @@
+//! JavaScript bindings, so browsers and edge workers can build sketches client-side and
+//! merge them server-side in Rust.
+//!
+//! The default hasher isn't stable across platforms, wasm32 has 32-bit `usize`s, so items
+//! are hashed with [`Portable`] and `toBytes` writes its hasher id. The server reads them
+//! with `HyperLogLog::<14>::from_bytes_with_hasher::<Portable>` and adds its own items with
+//! `add_object_by_hasher::<_, Portable>`.
+//!
+//! Build with `cargo rustc --release --target wasm32-unknown-unknown --features wasm
+//! --crate-type cdylib`, then run `wasm-bindgen` on the module.
+//!
+//! ```js
+//! const hll = new Hll();
+//! hll.add("user-1");
+//! hll.addBytes(new Uint8Array([1, 2, 3]));
+//! await fetch("/sketches", { method: "POST", body: hll.toBytes() });
+//! ```
+
+use alloc::vec::Vec;
+
+use wasm_bindgen::prelude::*;
+
+use crate::HyperLogLog;
+use crate::Portable;
+
+/// A sketch of precision `14`, hashed with [`Portable`]
+#[wasm_bindgen]
+#[derive(Clone, Debug, Default)]
+pub struct Hll {
+    inner: HyperLogLog,
+}
+
+#[wasm_bindgen]
+impl Hll {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a string, the same as `add_object_by_hasher::<_, Portable>(&s)` in Rust
+    pub fn add(&mut self, s: &str) {
+        self.inner.add_object_by_hasher::<_, Portable>(&s);
+    }
+
+    /// Add the bytes of a `Uint8Array`, the same as `add_object_by_hasher::<_, Portable>`
+    /// of a `&[u8]` in Rust
+    #[wasm_bindgen(js_name = addBytes)]
+    pub fn add_bytes(&mut self, bytes: &[u8]) {
+        self.inner.add_object_by_hasher::<_, Portable>(&bytes);
+    }
+
+    pub fn merge(&mut self, other: &Hll) {
+        self.inner.merge(&other.inner);
+    }
+
+    /// The estimated number of distinct items
+    pub fn count(&self) -> f64 {
+        self.inner.count() as f64
+    }
+
+    /// The binary format of `HyperLogLog::to_bytes`, with the hasher id of [`Portable`]
+    #[wasm_bindgen(js_name = toBytes)]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.inner.to_bytes_with_hasher::<Portable>()
+    }
+
+    /// Read bytes written by `toBytes` or by `to_bytes_with_hasher::<Portable>` in Rust,
+    /// throws if they are invalid
+    #[wasm_bindgen(js_name = fromBytes)]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Hll, JsError> {
+        HyperLogLog::from_bytes_with_hasher::<Portable>(bytes)
+            .map(Hll::from)
+            .map_err(|e| JsError::new(&e.to_string()))
+    }
+}
+
+impl From<HyperLogLog> for Hll {
+    fn from(inner: HyperLogLog) -> Self {
+        Self { inner }
+    }
+}
+
+impl From<Hll> for HyperLogLog {
+    fn from(hll: Hll) -> Self {
+        hll.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wasm_hll() {
+        let mut a = Hll::new();
+        let mut b = Hll::new();
+        for i in 0..10_000 {
+            a.add(&format!("user-{}", i));
+            b.add_bytes(&u32::to_le_bytes(i));
+        }
+        a.merge(&b);
+
+        let mut expected = HyperLogLog::<14>::new();
+        for i in 0..10_000_u32 {
+            expected.add_object_by_hasher::<_, Portable>(&format!("user-{}", i).as_str());
+            expected.add_object_by_hasher::<_, Portable>(&i.to_le_bytes().as_slice());
+        }
+        assert_eq!(a.inner, expected);
+        assert_eq!(a.count(), expected.count() as f64);
+
+        // `JsError` can only be built on wasm32, only valid bytes are read here
+        let bytes = a.to_bytes();
+        assert!(HyperLogLog::<14>::from_bytes(&bytes).is_err());
+        assert_eq!(
+            HyperLogLog::<14>::from_bytes_with_hasher::<Portable>(&bytes).unwrap(),
+            expected
+        );
+        assert_eq!(
+            HyperLogLog::from(Hll::from_bytes(&bytes).unwrap()),
+            expected
+        );
+    }
+}
@@ -0,0 +1,96 @@
+//! Fast insertion of byte rows, e.g. the values of a binary or string column, hashed
+//! with xxh3.
+//!
+//! [`HyperLogLog::add_all_bytes`] hashes every row with `xxh3_64(row)` directly, while
+//! [`Xxh3::hll_hash`] hashes what `Hash` writes, which prefixes slices with their
+//! length, so the two don't map a row to the same register. Fill a sketch in one way only.
+
+use core::hash::BuildHasher;
+use core::hash::Hash;
+
+use xxhash_rust::xxh3::xxh3_64;
+use xxhash_rust::xxh3::Xxh3Builder;
+
+use crate::Hasher;
+use crate::HyperLogLog;
+
+/// Rows hashed before their registers are updated, hashing independent rows in a row
+/// keeps the CPU busy instead of waiting on each register update
+const BATCH_SIZE: usize = 16;
+
+/// xxh3 with seed `0`
+pub struct Xxh3;
+
+impl Hasher for Xxh3 {
+    const ID: u8 = 3;
+
+    fn hll_hash<T: Hash>(x: T) -> u64 {
+        Xxh3Builder::new().hash_one(x)
+    }
+}
+
+impl<const P: usize> HyperLogLog<P> {
+    /// Adds a row of bytes, hashed with `xxh3_64(row)`
+    #[inline]
+    pub fn add_bytes(&mut self, row: &[u8]) {
+        self.add_hash(xxh3_64(row));
+    }
+
+    /// Adds every row, the same as calling [`Self::add_bytes`] for each of them but the
+    /// rows are hashed in batches, which is much cheaper than `add_object` per row
+    pub fn add_all_bytes<'a>(&mut self, rows: impl IntoIterator<Item = &'a [u8]>) {
+        let mut rows = rows.into_iter();
+        let mut hashes = [0_u64; BATCH_SIZE];
+        loop {
+            let mut len = 0;
+            for (hash, row) in hashes.iter_mut().zip(&mut rows) {
+                *hash = xxh3_64(row);
+                len += 1;
+            }
+            for hash in &hashes[..len] {
+                self.add_hash(*hash);
+            }
+            if len < BATCH_SIZE {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_all_bytes() {
+        let rows: Vec<String> = (0..10_000).map(|i| format!("user-{}", i)).collect();
+
+        let mut expected = HyperLogLog::<14>::new();
+        for row in &rows {
+            expected.add_hash(xxh3_64(row.as_bytes()));
+        }
+        // batches and a partial last batch
+        for n in [0, 1, BATCH_SIZE, BATCH_SIZE + 1, rows.len()] {
+            let mut hll = HyperLogLog::<14>::new();
+            hll.add_all_bytes(rows[..n].iter().map(|row| row.as_bytes()));
+            let mut one_by_one = HyperLogLog::<14>::new();
+            rows[..n]
+                .iter()
+                .for_each(|row| one_by_one.add_bytes(row.as_bytes()));
+            assert_eq!(hll, one_by_one);
+        }
+
+        let mut hll = HyperLogLog::<14>::new();
+        hll.add_all_bytes(rows.iter().map(|row| row.as_bytes()));
+        assert_eq!(hll, expected);
+        let error = (hll.count() as f64 - 10_000.0).abs() / 10_000.0;
+        assert!(error < 0.05, "{}", hll.count());
+
+        let bytes = hll.to_bytes_with_hasher::<Xxh3>();
+        assert!(HyperLogLog::<14>::from_bytes(&bytes).is_err());
+        assert_eq!(
+            HyperLogLog::<14>::from_bytes_with_hasher::<Xxh3>(&bytes).unwrap(),
+            hll
+        );
+    }
+}